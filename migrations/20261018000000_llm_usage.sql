@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS llm_usage (
+	llm_usage_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+	task_id UUID,
+	firm_id UUID,
+	user_id UUID,
+	processing_type TEXT NOT NULL,
+	provider TEXT NOT NULL,
+	model TEXT NOT NULL,
+	prompt_tokens INTEGER NOT NULL DEFAULT 0,
+	completion_tokens INTEGER NOT NULL DEFAULT 0,
+	total_tokens INTEGER NOT NULL DEFAULT 0,
+	usage_estimated BOOLEAN NOT NULL DEFAULT FALSE,
+	duration_ms BIGINT NOT NULL DEFAULT 0,
+	estimated_cost DOUBLE PRECISION NOT NULL DEFAULT 0,
+	created_ts TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS llm_usage_task_id_idx ON llm_usage (task_id);
+CREATE INDEX IF NOT EXISTS llm_usage_firm_id_idx ON llm_usage (firm_id);
+CREATE INDEX IF NOT EXISTS llm_usage_user_id_idx ON llm_usage (user_id, created_ts);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS llm_cache (
+	cache_key TEXT PRIMARY KEY,
+	provider TEXT NOT NULL,
+	model TEXT NOT NULL,
+	content TEXT NOT NULL,
+	created_ts TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	expires_ts TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS llm_cache_expires_ts_idx ON llm_cache (expires_ts);
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS llm_rejections (
+	llm_rejection_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+	task_id UUID,
+	firm_id UUID,
+	user_id UUID,
+	processing_type TEXT NOT NULL,
+	provider TEXT NOT NULL,
+	model TEXT NOT NULL,
+	reason TEXT NOT NULL,
+	content TEXT NOT NULL,
+	cyrillic_ratio DOUBLE PRECISION NOT NULL DEFAULT 0,
+	latin_ratio DOUBLE PRECISION NOT NULL DEFAULT 0,
+	cjk_ratio DOUBLE PRECISION NOT NULL DEFAULT 0,
+	attempt INTEGER NOT NULL DEFAULT 1,
+	created_ts TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS llm_rejections_task_id_idx ON llm_rejections (task_id);
+CREATE INDEX IF NOT EXISTS llm_rejections_created_ts_idx ON llm_rejections (processing_type, created_ts);
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS ai_tasks (
+	task_id UUID PRIMARY KEY,
+	request_id UUID,
+	user_id UUID NOT NULL,
+	processing_type TEXT NOT NULL,
+	status TEXT NOT NULL DEFAULT 'in_progress',
+	attempts INTEGER NOT NULL DEFAULT 1,
+	result_data JSONB,
+	error_message TEXT,
+	created_ts TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	started_ts TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	finished_ts TIMESTAMPTZ,
+	updated_ts TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS ai_tasks_status_idx ON ai_tasks (status, updated_ts);
+CREATE INDEX IF NOT EXISTS ai_tasks_request_id_idx ON ai_tasks (request_id);
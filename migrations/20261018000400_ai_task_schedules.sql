@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS ai_task_schedules (
+	schedule_id UUID PRIMARY KEY,
+	request_id UUID NOT NULL,
+	user_id UUID NOT NULL,
+	processing_type TEXT NOT NULL,
+	parameters JSONB NOT NULL DEFAULT '{}',
+	cron TEXT,
+	next_run_ts TIMESTAMPTZ,
+	paused BOOLEAN NOT NULL DEFAULT FALSE,
+	runs INTEGER NOT NULL DEFAULT 0,
+	last_run_ts TIMESTAMPTZ,
+	last_task_id UUID,
+	created_ts TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	updated_ts TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS ai_task_schedules_due_idx ON ai_task_schedules (next_run_ts) WHERE NOT paused;
+CREATE INDEX IF NOT EXISTS ai_task_schedules_user_id_idx ON ai_task_schedules (user_id);
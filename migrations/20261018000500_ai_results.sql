@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS ai_results (
+	result_id BIGSERIAL PRIMARY KEY,
+	kind TEXT NOT NULL,
+	task_id UUID NOT NULL,
+	user_id UUID NOT NULL,
+	request_id UUID,
+	status TEXT NOT NULL,
+	message JSONB NOT NULL,
+	sent_at TEXT NOT NULL,
+	received_ts TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- A redelivered message is stored once
+CREATE UNIQUE INDEX IF NOT EXISTS ai_results_message_idx ON ai_results (kind, task_id, status, sent_at);
+CREATE INDEX IF NOT EXISTS ai_results_user_id_idx ON ai_results (user_id, received_ts);
+
+CREATE TABLE IF NOT EXISTS ai_result_deliveries (
+	result_id BIGINT NOT NULL REFERENCES ai_results (result_id) ON DELETE CASCADE,
+	sink TEXT NOT NULL,
+	attempts INTEGER NOT NULL DEFAULT 0,
+	next_attempt_ts TIMESTAMPTZ DEFAULT NOW(),
+	delivered_ts TIMESTAMPTZ,
+	last_error TEXT,
+	PRIMARY KEY (result_id, sink)
+);
+
+CREATE INDEX IF NOT EXISTS ai_result_deliveries_due_idx ON ai_result_deliveries (next_attempt_ts) WHERE delivered_ts IS NULL;
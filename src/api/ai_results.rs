@@ -0,0 +1,109 @@
+use sqlx::{Pool, Postgres};
+use std::io::{Error, ErrorKind};
+
+use crate::models::{AiResult, AiResultDelivery, SaveAiResult};
+
+impl AiResult {
+	/// Stores a result or progress message with a pending delivery for every sink.
+	/// Returns `None` for a message that is already stored, e.g. after a redelivery
+	pub async fn save_result(
+		db: &Pool<Postgres>,
+		result: SaveAiResult,
+		sinks: &[String],
+	) -> Result<Option<i64>, Error> {
+		sqlx::query_scalar::<_, i64>(
+			r#"WITH saved AS (
+				INSERT INTO ai_results (kind, task_id, user_id, request_id, status, message, sent_at)
+				VALUES ($1, $2, $3, $4, $5, $6::JSONB, $7)
+				ON CONFLICT (kind, task_id, status, sent_at) DO NOTHING
+				RETURNING result_id
+			), deliveries AS (
+				INSERT INTO ai_result_deliveries (result_id, sink)
+				SELECT result_id, sink FROM saved, unnest($8::TEXT[]) AS sink
+			)
+			SELECT result_id FROM saved"#,
+		)
+		.bind(&result.kind)
+		.bind(result.task_id)
+		.bind(result.user_id)
+		.bind(result.request_id)
+		.bind(&result.status)
+		.bind(result.message.to_string())
+		.bind(&result.sent_at)
+		.bind(sinks)
+		.fetch_optional(db)
+		.await
+		.map_err(|e| {
+			println!("Не удалось сохранить результат {}: {}", result.task_id, e);
+			Error::new(ErrorKind::Other, format!("{}", e))
+		})
+	}
+}
+
+impl AiResultDelivery {
+	/// Takes due deliveries, counts the attempt and moves them `lease_secs` ahead,
+	/// so other dispatchers skip them and an unfinished one is retried after the lease
+	pub async fn claim_due_deliveries(
+		db: &Pool<Postgres>,
+		limit: i64,
+		lease_secs: i64,
+	) -> Result<Vec<Self>, Error> {
+		sqlx::query_as::<_, AiResultDelivery>(
+			r#"UPDATE ai_result_deliveries d
+			SET attempts = d.attempts + 1, next_attempt_ts = NOW() + make_interval(secs => $2)
+			FROM ai_results r
+			WHERE r.result_id = d.result_id AND (d.result_id, d.sink) IN (
+				SELECT result_id, sink FROM ai_result_deliveries
+				WHERE delivered_ts IS NULL AND next_attempt_ts <= NOW()
+				ORDER BY next_attempt_ts, result_id
+				LIMIT $1
+				FOR UPDATE SKIP LOCKED
+			)
+			RETURNING d.result_id, d.sink, d.attempts, r.message::TEXT AS message"#,
+		)
+		.bind(limit)
+		.bind(lease_secs as f64)
+		.fetch_all(db)
+		.await
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+
+	pub async fn mark_delivered(
+		db: &Pool<Postgres>,
+		result_id: i64,
+		sink: &str,
+	) -> Result<(), Error> {
+		sqlx::query(
+			r#"UPDATE ai_result_deliveries SET delivered_ts = NOW(), next_attempt_ts = NULL, last_error = NULL
+			WHERE result_id = $1 AND sink = $2"#,
+		)
+		.bind(result_id)
+		.bind(sink)
+		.execute(db)
+		.await
+		.map(|_| ())
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+
+	/// Records a failed attempt; without `next_attempt_ts` the delivery is given up
+	pub async fn mark_failed(
+		db: &Pool<Postgres>,
+		result_id: i64,
+		sink: &str,
+		error: &str,
+		next_attempt_ts: Option<chrono::DateTime<chrono::Utc>>,
+	) -> Result<(), Error> {
+		sqlx::query(
+			r#"UPDATE ai_result_deliveries SET last_error = $3, next_attempt_ts = $4
+			WHERE result_id = $1 AND sink = $2"#,
+		)
+		.bind(result_id)
+		.bind(sink)
+		.bind(error)
+		.bind(next_attempt_ts)
+		.execute(db)
+		.await
+		.map(|_| ())
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+}
@@ -0,0 +1,131 @@
+use sqlx::{Pool, Postgres};
+use std::io::{Error, ErrorKind};
+use uuid::Uuid;
+
+use crate::models::{AiTaskSchedule, SaveAiTaskSchedule};
+
+/// `parameters` is stored as JSONB and read back as text
+const AI_TASK_SCHEDULE_COLUMNS: &str = "schedule_id, request_id, user_id, processing_type, parameters::TEXT AS parameters, cron, next_run_ts, paused, runs, last_run_ts, last_task_id, created_ts, updated_ts";
+
+impl AiTaskSchedule {
+	/// Saves the schedule of a delayed or repeated task; a redelivered task keeps the existing one
+	pub async fn create_schedule(
+		db: &Pool<Postgres>,
+		schedule: SaveAiTaskSchedule,
+	) -> Result<Self, Error> {
+		sqlx::query_as::<_, AiTaskSchedule>(&format!(
+			r#"INSERT INTO ai_task_schedules (schedule_id, request_id, user_id, processing_type, parameters, cron, next_run_ts)
+			VALUES ($1, $2, $3, $4, $5::JSONB, $6, $7)
+			ON CONFLICT (schedule_id) DO UPDATE SET updated_ts = NOW()
+			RETURNING {}"#,
+			AI_TASK_SCHEDULE_COLUMNS
+		))
+		.bind(schedule.schedule_id)
+		.bind(schedule.request_id)
+		.bind(schedule.user_id)
+		.bind(&schedule.processing_type)
+		.bind(schedule.parameters.to_string())
+		.bind(&schedule.cron)
+		.bind(schedule.next_run_ts)
+		.fetch_one(db)
+		.await
+		.map_err(|e| {
+			println!(
+				"Не удалось сохранить расписание {}: {}",
+				schedule.schedule_id, e
+			);
+			Error::new(ErrorKind::Other, format!("{}", e))
+		})
+	}
+
+	/// All schedules, or those of one user, the next to run first
+	pub async fn get_schedules(
+		db: &Pool<Postgres>,
+		user_id: Option<&Uuid>,
+	) -> Result<Vec<Self>, Error> {
+		sqlx::query_as::<_, AiTaskSchedule>(&format!(
+			r#"SELECT {} FROM ai_task_schedules
+			WHERE $1::UUID IS NULL OR user_id = $1
+			ORDER BY next_run_ts NULLS LAST, created_ts"#,
+			AI_TASK_SCHEDULE_COLUMNS
+		))
+		.bind(user_id)
+		.fetch_all(db)
+		.await
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+
+	/// Takes due schedules and moves them `lease_secs` ahead, so other schedulers skip them
+	/// and a schedule whose run was not recorded becomes due again after the lease
+	pub async fn claim_due_schedules(
+		db: &Pool<Postgres>,
+		limit: i64,
+		lease_secs: i64,
+	) -> Result<Vec<Self>, Error> {
+		sqlx::query_as::<_, AiTaskSchedule>(&format!(
+			r#"UPDATE ai_task_schedules SET next_run_ts = NOW() + make_interval(secs => $2), updated_ts = NOW()
+			WHERE schedule_id IN (
+				SELECT schedule_id FROM ai_task_schedules
+				WHERE NOT paused AND next_run_ts <= NOW()
+				ORDER BY next_run_ts
+				LIMIT $1
+				FOR UPDATE SKIP LOCKED
+			)
+			RETURNING {}"#,
+			AI_TASK_SCHEDULE_COLUMNS
+		))
+		.bind(limit)
+		.bind(lease_secs as f64)
+		.fetch_all(db)
+		.await
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+
+	/// Records a published run; `next_run_ts` is empty when the schedule is done
+	pub async fn record_run(
+		db: &Pool<Postgres>,
+		schedule_id: &Uuid,
+		task_id: &Uuid,
+		next_run_ts: Option<chrono::DateTime<chrono::Utc>>,
+	) -> Result<(), Error> {
+		sqlx::query(
+			r#"UPDATE ai_task_schedules SET runs = runs + 1, last_run_ts = NOW(), last_task_id = $2,
+				next_run_ts = $3, updated_ts = NOW()
+			WHERE schedule_id = $1"#,
+		)
+		.bind(schedule_id)
+		.bind(task_id)
+		.bind(next_run_ts)
+		.execute(db)
+		.await
+		.map(|_| ())
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+
+	/// `false` if there is no such schedule
+	pub async fn set_paused(
+		db: &Pool<Postgres>,
+		schedule_id: &Uuid,
+		paused: bool,
+	) -> Result<bool, Error> {
+		sqlx::query(
+			"UPDATE ai_task_schedules SET paused = $2, updated_ts = NOW() WHERE schedule_id = $1",
+		)
+		.bind(schedule_id)
+		.bind(paused)
+		.execute(db)
+		.await
+		.map(|result| result.rows_affected() > 0)
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+
+	/// `false` if there is no such schedule
+	pub async fn delete_schedule(db: &Pool<Postgres>, schedule_id: &Uuid) -> Result<bool, Error> {
+		sqlx::query("DELETE FROM ai_task_schedules WHERE schedule_id = $1")
+			.bind(schedule_id)
+			.execute(db)
+			.await
+			.map(|result| result.rows_affected() > 0)
+			.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+}
@@ -0,0 +1,66 @@
+use sqlx::{Pool, Postgres};
+use std::io::{Error, ErrorKind};
+use uuid::Uuid;
+
+use crate::models::{AiTask, SaveAiTask, AI_TASK_IN_PROGRESS};
+
+/// `result_data` is stored as JSONB and read back as text
+const AI_TASK_COLUMNS: &str = "task_id, request_id, user_id, processing_type, status, attempts, result_data::TEXT AS result_data, error_message, created_ts, started_ts, finished_ts, updated_ts";
+
+impl AiTask {
+	pub async fn get_task(db: &Pool<Postgres>, task_id: &Uuid) -> Result<Option<Self>, Error> {
+		sqlx::query_as::<_, AiTask>(&format!(
+			"SELECT {} FROM ai_tasks WHERE task_id = $1",
+			AI_TASK_COLUMNS
+		))
+		.bind(task_id)
+		.fetch_optional(db)
+		.await
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+
+	/// Records a processing attempt; a repeated attempt increments `attempts`
+	pub async fn start_task(db: &Pool<Postgres>, task: SaveAiTask) -> Result<Self, Error> {
+		sqlx::query_as::<_, AiTask>(&format!(
+			r#"INSERT INTO ai_tasks (task_id, request_id, user_id, processing_type, status) VALUES ($1, $2, $3, $4, $5)
+			ON CONFLICT (task_id) DO UPDATE SET status = EXCLUDED.status, attempts = ai_tasks.attempts + 1,
+				started_ts = NOW(), updated_ts = NOW()
+			RETURNING {}"#,
+			AI_TASK_COLUMNS
+		))
+		.bind(task.task_id)
+		.bind(task.request_id)
+		.bind(task.user_id)
+		.bind(&task.processing_type)
+		.bind(AI_TASK_IN_PROGRESS)
+		.fetch_one(db)
+		.await
+		.map_err(|e| {
+			println!("Не удалось сохранить задачу {}: {}", task.task_id, e);
+			Error::new(ErrorKind::Other, format!("{}", e))
+		})
+	}
+
+	/// Saves the final status and result before it is sent to the backend
+	pub async fn finish_task(
+		db: &Pool<Postgres>,
+		task_id: &Uuid,
+		status: &str,
+		result_data: Option<&serde_json::Value>,
+		error_message: Option<&str>,
+	) -> Result<(), Error> {
+		sqlx::query(
+			r#"UPDATE ai_tasks SET status = $2, result_data = $3::JSONB, error_message = $4,
+				finished_ts = NOW(), updated_ts = NOW()
+			WHERE task_id = $1"#,
+		)
+		.bind(task_id)
+		.bind(status)
+		.bind(result_data.map(|value| value.to_string()))
+		.bind(error_message)
+		.execute(db)
+		.await
+		.map(|_| ())
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+}
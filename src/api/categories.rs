@@ -0,0 +1,27 @@
+use sqlx::{Pool, Postgres};
+use std::io::{Error, ErrorKind};
+use uuid::Uuid;
+
+use crate::models::Category;
+
+impl Category {
+	/// GET категория по id
+	pub async fn get_category_by_id(
+		db: &Pool<Postgres>,
+		category_id: &Uuid,
+	) -> Result<Self, Error> {
+		let category_query_result =
+			sqlx::query_as::<_, Category>("SELECT * FROM categories WHERE category_id = $1")
+				.bind(category_id)
+				.fetch_one(db)
+				.await;
+
+		match category_query_result {
+			Ok(x) => Ok(x),
+			Err(e) => {
+				println!("Что-то пошло не так во время запроса get_category_by_id");
+				Err(Error::new(ErrorKind::NotFound, e))
+			}
+		}
+	}
+}
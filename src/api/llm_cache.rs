@@ -0,0 +1,33 @@
+use sqlx::{Pool, Postgres};
+use std::io::{Error, ErrorKind};
+
+use crate::models::LlmCacheEntry;
+
+impl LlmCacheEntry {
+	/// GET ответ из кэша, если он ещё не истёк
+	pub async fn get_entry(db: &Pool<Postgres>, cache_key: &str) -> Result<Option<Self>, Error> {
+		sqlx::query_as::<_, LlmCacheEntry>(
+			"SELECT * FROM llm_cache WHERE cache_key = $1 AND expires_ts > NOW()",
+		)
+		.bind(cache_key)
+		.fetch_optional(db)
+		.await
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+
+	pub async fn save_entry(db: &Pool<Postgres>, entry: &LlmCacheEntry) -> Result<(), Error> {
+		sqlx::query(
+			r#"INSERT INTO llm_cache (cache_key, provider, model, content, expires_ts) VALUES ($1, $2, $3, $4, $5)
+			ON CONFLICT (cache_key) DO UPDATE SET content = EXCLUDED.content, created_ts = NOW(), expires_ts = EXCLUDED.expires_ts"#,
+		)
+		.bind(&entry.cache_key)
+		.bind(&entry.provider)
+		.bind(&entry.model)
+		.bind(&entry.content)
+		.bind(entry.expires_ts)
+		.execute(db)
+		.await
+		.map(|_| ())
+		.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))
+	}
+}
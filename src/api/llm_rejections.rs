@@ -0,0 +1,33 @@
+use sqlx::{Pool, Postgres};
+use std::io::{Error, ErrorKind};
+
+use crate::models::{LlmRejection, SaveLlmRejection};
+
+impl LlmRejection {
+	pub async fn add_rejection(
+		db: &Pool<Postgres>,
+		rejection: SaveLlmRejection,
+	) -> Result<Self, Error> {
+		sqlx::query_as::<_, LlmRejection>(
+			r#"INSERT INTO llm_rejections (task_id, firm_id, user_id, processing_type, provider, model, reason, content, cyrillic_ratio, latin_ratio, cjk_ratio, attempt) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING *"#,
+		)
+		.bind(rejection.task_id)
+		.bind(rejection.firm_id)
+		.bind(rejection.user_id)
+		.bind(&rejection.processing_type)
+		.bind(&rejection.provider)
+		.bind(&rejection.model)
+		.bind(&rejection.reason)
+		.bind(&rejection.content)
+		.bind(rejection.cyrillic_ratio)
+		.bind(rejection.latin_ratio)
+		.bind(rejection.cjk_ratio)
+		.bind(rejection.attempt)
+		.fetch_one(db)
+		.await
+		.map_err(|e| {
+			println!("Не удалось сохранить отклонённый ответ: {}", e);
+			Error::new(ErrorKind::Other, format!("{}", e))
+		})
+	}
+}
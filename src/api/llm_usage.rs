@@ -0,0 +1,45 @@
+use sqlx::{Pool, Postgres};
+use std::io::{Error, ErrorKind};
+use uuid::Uuid;
+
+use crate::llm::usage_totals;
+use crate::models::{LlmUsage, LlmUsageTotals, SaveLlmUsage};
+
+impl LlmUsage {
+	pub async fn add_usage(db: &Pool<Postgres>, usage: SaveLlmUsage) -> Result<Self, Error> {
+		sqlx::query_as::<_, LlmUsage>(
+			r#"INSERT INTO llm_usage (task_id, firm_id, user_id, processing_type, provider, model, prompt_tokens, completion_tokens, total_tokens, usage_estimated, duration_ms, estimated_cost) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING *"#,
+		)
+		.bind(usage.task_id)
+		.bind(usage.firm_id)
+		.bind(usage.user_id)
+		.bind(&usage.processing_type)
+		.bind(&usage.provider)
+		.bind(&usage.model)
+		.bind(usage.prompt_tokens)
+		.bind(usage.completion_tokens)
+		.bind(usage.prompt_tokens + usage.completion_tokens)
+		.bind(usage.usage_estimated)
+		.bind(usage.duration_ms)
+		.bind(usage.estimated_cost)
+		.fetch_one(db)
+		.await
+		.map_err(|e| {
+			println!("Не удалось сохранить расход токенов: {}", e);
+			Error::new(ErrorKind::Other, format!("{}", e))
+		})
+	}
+
+	pub async fn get_task_totals(
+		db: &Pool<Postgres>,
+		task_id: &Uuid,
+	) -> Result<LlmUsageTotals, Error> {
+		let rows = sqlx::query_as::<_, LlmUsage>(r#"SELECT * FROM llm_usage WHERE task_id = $1"#)
+			.bind(task_id)
+			.fetch_all(db)
+			.await
+			.map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))?;
+
+		Ok(usage_totals(&rows))
+	}
+}
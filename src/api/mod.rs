@@ -1,15 +1,29 @@
+pub mod ai_results;
+pub mod ai_task_schedules;
+pub mod ai_tasks;
 pub mod bestlight_cases;
+pub mod categories;
 pub mod count;
 pub mod counter;
 pub mod firm;
+pub mod llm_cache;
+pub mod llm_rejections;
+pub mod llm_usage;
 pub mod oai_descriptions;
 pub mod page;
 pub mod reviews;
 
+pub use self::ai_results::*;
+pub use self::ai_task_schedules::*;
+pub use self::ai_tasks::*;
 pub use self::bestlight_cases::*;
+pub use self::categories::*;
 pub use self::count::*;
 pub use self::counter::*;
 pub use self::firm::*;
+pub use self::llm_cache::*;
+pub use self::llm_rejections::*;
+pub use self::llm_usage::*;
 pub use self::oai_descriptions::*;
 pub use self::page::*;
 pub use self::reviews::*;
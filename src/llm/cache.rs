@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use std::env;
+use std::path::{Path, PathBuf};
+use tokio::time::Duration;
+
+use crate::llm::{ChatRequest, ChatResponse, LlmProvider};
+use crate::models::LlmCacheEntry;
+
+pub const DEFAULT_CACHE_DIR: &str = ".llm_cache";
+pub const DEFAULT_CACHE_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone)]
+pub enum CacheBackend {
+	Postgres(Pool<Postgres>),
+	Disk(PathBuf),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct DiskCacheEntry {
+	provider: String,
+	model: String,
+	content: String,
+	expires_at: i64,
+}
+
+/// Model answers cached by a hash of provider, model, messages and parameters.
+///
+/// Enabled with `LLM_CACHE=postgres` or `LLM_CACHE=disk` (`LLM_CACHE_DIR`),
+/// entries live for `LLM_CACHE_TTL_SECS`.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+	backend: CacheBackend,
+	ttl: Duration,
+}
+
+impl ResponseCache {
+	pub fn new(backend: CacheBackend, ttl: Duration) -> Self {
+		Self { backend, ttl }
+	}
+
+	pub fn from_env(pool: &Pool<Postgres>) -> Option<Self> {
+		let backend = match env::var("LLM_CACHE")
+			.unwrap_or_default()
+			.trim()
+			.to_lowercase()
+			.as_str()
+		{
+			"postgres" | "pg" | "db" => CacheBackend::Postgres(pool.clone()),
+			"disk" | "file" => CacheBackend::Disk(PathBuf::from(
+				env::var("LLM_CACHE_DIR").unwrap_or_else(|_| DEFAULT_CACHE_DIR.to_string()),
+			)),
+			_ => return None,
+		};
+
+		let ttl = env::var("LLM_CACHE_TTL_SECS")
+			.ok()
+			.and_then(|value| value.parse::<u64>().ok())
+			.unwrap_or(DEFAULT_CACHE_TTL_SECS);
+
+		Some(Self::new(backend, Duration::from_secs(ttl)))
+	}
+
+	pub fn key(provider: &dyn LlmProvider, request: &ChatRequest) -> String {
+		let mut hasher = Sha256::new();
+		hasher.update(provider.name().as_bytes());
+		hasher.update([0]);
+		hasher.update(provider.model().as_bytes());
+		hasher.update([0]);
+		for message in &request.messages {
+			hasher.update(message.role.as_bytes());
+			hasher.update([0]);
+			hasher.update(message.content.as_bytes());
+			hasher.update([0]);
+		}
+		if let Some(temperature) = request.temperature {
+			hasher.update(temperature.to_bits().to_le_bytes());
+		}
+
+		format!("{:x}", hasher.finalize())
+	}
+
+	pub async fn get(&self, key: &str) -> Option<ChatResponse> {
+		match &self.backend {
+			CacheBackend::Postgres(pool) => match LlmCacheEntry::get_entry(pool, key).await {
+				Ok(entry) => entry.map(|entry| ChatResponse {
+					content: entry.content,
+					model: entry.model,
+					usage: None,
+				}),
+				Err(e) => {
+					eprintln!("⚠️ LLM cache read failed: {}", e);
+					None
+				}
+			},
+			CacheBackend::Disk(dir) => {
+				let text = tokio::fs::read_to_string(disk_path(dir, key)).await.ok()?;
+				let entry: DiskCacheEntry = serde_json::from_str(&text).ok()?;
+
+				if entry.expires_at <= chrono::Utc::now().timestamp() {
+					let _ = tokio::fs::remove_file(disk_path(dir, key)).await;
+					return None;
+				}
+
+				Some(ChatResponse {
+					content: entry.content,
+					model: entry.model,
+					usage: None,
+				})
+			}
+		}
+	}
+
+	pub async fn put(&self, key: &str, provider: &dyn LlmProvider, response: &ChatResponse) {
+		let expires_at = chrono::Utc::now()
+			+ chrono::Duration::from_std(self.ttl).unwrap_or(chrono::Duration::zero());
+
+		let result = match &self.backend {
+			CacheBackend::Postgres(pool) => {
+				LlmCacheEntry::save_entry(
+					pool,
+					&LlmCacheEntry {
+						cache_key: key.to_string(),
+						provider: provider.name().to_string(),
+						model: response.model.clone(),
+						content: response.content.clone(),
+						created_ts: None,
+						expires_ts: expires_at,
+					},
+				)
+				.await
+			}
+			CacheBackend::Disk(dir) => {
+				let path = disk_path(dir, key);
+				let entry = DiskCacheEntry {
+					provider: provider.name().to_string(),
+					model: response.model.clone(),
+					content: response.content.clone(),
+					expires_at: expires_at.timestamp(),
+				};
+
+				match path.parent() {
+					Some(parent) => match tokio::fs::create_dir_all(parent).await {
+						Ok(_) => {
+							tokio::fs::write(&path, serde_json::to_vec(&entry).unwrap_or_default())
+								.await
+						}
+						Err(e) => Err(e),
+					},
+					None => Ok(()),
+				}
+			}
+		};
+
+		if let Err(e) = result {
+			eprintln!("⚠️ LLM cache write failed: {}", e);
+		}
+	}
+}
+
+/// `<dir>/ab/abcdef...json`, so that no single directory collects thousands of files
+fn disk_path(dir: &Path, key: &str) -> PathBuf {
+	dir.join(&key[..2]).join(format!("{}.json", key))
+}
+
+/// The `cache_bypass` flag in `AIRequestData.parameters`
+pub fn cache_bypass(parameters: &Value) -> bool {
+	parameters
+		.get("cache_bypass")
+		.and_then(|value| value.as_bool())
+		.unwrap_or(false)
+}
@@ -0,0 +1,50 @@
+use thiserror::Error;
+
+/// Model call errors; `RetryPolicy` decides by them whether to retry the request
+#[derive(Debug, Error)]
+pub enum LlmError {
+	#[error("{provider} request error: {message}")]
+	Transport { provider: String, message: String },
+
+	#[error("{provider} returned {status}: {body}")]
+	Status {
+		provider: String,
+		status: u16,
+		body: String,
+	},
+
+	#[error("Failed to parse {provider} response: {message}")]
+	InvalidResponse { provider: String, message: String },
+
+	#[error("{provider} timed out after {seconds}s")]
+	Timeout { provider: String, seconds: u64 },
+
+	#[error("{provider} command failed: {message}")]
+	Process { provider: String, message: String },
+
+	#[error("{provider} failed after {attempts} attempts: {last_error}")]
+	RetriesExhausted {
+		provider: String,
+		attempts: u32,
+		last_error: String,
+	},
+
+	#[error("{provider} answer rejected by language guard: {reason}")]
+	LanguageRejected { provider: String, reason: String },
+}
+
+impl LlmError {
+	pub fn transport(provider: &str, message: impl ToString) -> Self {
+		Self::Transport {
+			provider: provider.to_string(),
+			message: message.to_string(),
+		}
+	}
+
+	pub fn invalid_response(provider: &str, message: impl ToString) -> Self {
+		Self::InvalidResponse {
+			provider: provider.to_string(),
+			message: message.to_string(),
+		}
+	}
+}
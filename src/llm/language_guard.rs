@@ -0,0 +1,421 @@
+use sqlx::{Pool, Postgres};
+use std::error::Error;
+
+use crate::llm::{
+	chat_with_cache, env_for, ChatRequest, LlmError, LlmProvider, PromptVars, ResponseCache,
+	Sanitizer, UsageContext,
+};
+use crate::models::{LlmRejection, SaveLlmRejection};
+
+/// Letters of each script in a text; digits and punctuation are not counted
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScriptStats {
+	pub letters: usize,
+	pub cyrillic: usize,
+	pub latin: usize,
+	pub cjk: usize,
+}
+
+impl ScriptStats {
+	pub fn measure(text: &str) -> Self {
+		let mut stats = Self::default();
+
+		for c in text.chars() {
+			if is_cjk(c) {
+				stats.cjk += 1;
+			} else if !c.is_alphabetic() {
+				continue;
+			} else if matches!(c, '\u{0400}'..='\u{052f}') {
+				stats.cyrillic += 1;
+			} else if c.is_ascii_alphabetic() || matches!(c, '\u{00c0}'..='\u{024f}') {
+				stats.latin += 1;
+			}
+			stats.letters += 1;
+		}
+
+		stats
+	}
+
+	fn ratio(&self, count: usize) -> f64 {
+		if self.letters == 0 {
+			0.0
+		} else {
+			count as f64 / self.letters as f64
+		}
+	}
+
+	pub fn cyrillic_ratio(&self) -> f64 {
+		self.ratio(self.cyrillic)
+	}
+
+	pub fn latin_ratio(&self) -> f64 {
+		self.ratio(self.latin)
+	}
+
+	pub fn cjk_ratio(&self) -> f64 {
+		self.ratio(self.cjk)
+	}
+}
+
+/// Ideographs, kana and hangul
+fn is_cjk(c: char) -> bool {
+	matches!(c,
+		'\u{2e80}'..='\u{2fdf}'
+			| '\u{3000}'..='\u{30ff}'
+			| '\u{3100}'..='\u{31ff}'
+			| '\u{3400}'..='\u{4dbf}'
+			| '\u{4e00}'..='\u{9fff}'
+			| '\u{ac00}'..='\u{d7af}'
+			| '\u{f900}'..='\u{faff}'
+			| '\u{ff00}'..='\u{ffef}'
+			| '\u{20000}'..='\u{2ebef}')
+}
+
+/// Language policy for answers: models sometimes reply in Chinese or English.
+///
+/// Configured with `LLM_LANGUAGE_GUARD=off`, `LLM_LANGUAGE_MIN_CYRILLIC`,
+/// `LLM_LANGUAGE_MAX_LATIN`, `LLM_LANGUAGE_MAX_CJK` and `LLM_LANGUAGE_RETRIES`
+/// (with or without a type suffix).
+#[derive(Debug, Clone)]
+pub struct LanguageGuard {
+	pub enabled: bool,
+	pub min_cyrillic_ratio: f64,
+	pub max_latin_ratio: f64,
+	pub max_cjk_ratio: f64,
+	/// Ratios mean little in short answers, so only CJK is checked there
+	pub min_letters: usize,
+	pub retries: u32,
+}
+
+impl Default for LanguageGuard {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			min_cyrillic_ratio: 0.5,
+			max_latin_ratio: 0.5,
+			max_cjk_ratio: 0.0,
+			min_letters: 12,
+			retries: 2,
+		}
+	}
+}
+
+impl LanguageGuard {
+	pub fn for_processing_type(processing_type: &str) -> Self {
+		let default = match processing_type {
+			// Keywords often contain brands and part numbers
+			"keyword_extraction" => Self {
+				min_cyrillic_ratio: 0.3,
+				max_latin_ratio: 0.7,
+				..Self::default()
+			},
+			_ => Self::default(),
+		};
+		let ratio = |key: &str| {
+			env_for(processing_type, key).and_then(|value| value.trim().parse::<f64>().ok())
+		};
+
+		Self {
+			enabled: !matches!(
+				env_for(processing_type, "LANGUAGE_GUARD")
+					.unwrap_or_default()
+					.trim()
+					.to_lowercase()
+					.as_str(),
+				"off" | "false" | "0"
+			),
+			min_cyrillic_ratio: ratio("LANGUAGE_MIN_CYRILLIC")
+				.unwrap_or(default.min_cyrillic_ratio),
+			max_latin_ratio: ratio("LANGUAGE_MAX_LATIN").unwrap_or(default.max_latin_ratio),
+			max_cjk_ratio: ratio("LANGUAGE_MAX_CJK").unwrap_or(default.max_cjk_ratio),
+			min_letters: default.min_letters,
+			retries: env_for(processing_type, "LANGUAGE_RETRIES")
+				.and_then(|value| value.trim().parse::<u32>().ok())
+				.unwrap_or(default.retries),
+		}
+	}
+
+	/// Why the answer is rejected, or `Ok` if it passes the policy
+	pub fn check(&self, text: &str, stats: &ScriptStats) -> Result<(), String> {
+		if text.trim().is_empty() {
+			return Err("empty answer".to_string());
+		}
+		if !self.enabled {
+			return Ok(());
+		}
+
+		if stats.cjk_ratio() > self.max_cjk_ratio {
+			return Err(format!("CJK ratio {:.2}", stats.cjk_ratio()));
+		}
+		if stats.letters < self.min_letters {
+			return Ok(());
+		}
+		if stats.cyrillic_ratio() < self.min_cyrillic_ratio {
+			return Err(format!("Cyrillic ratio {:.2}", stats.cyrillic_ratio()));
+		}
+		if stats.latin_ratio() > self.max_latin_ratio {
+			return Err(format!("Latin ratio {:.2}", stats.latin_ratio()));
+		}
+
+		Ok(())
+	}
+}
+
+/// Calls the model, then sanitizes the answer and checks its language.
+///
+/// A rejected answer is written to `llm_rejections` and the request is repeated past the cache;
+/// if no attempt passes, `LlmError::LanguageRejected` is returned
+/// and nothing reaches the database.
+pub async fn chat_checked(
+	provider: &dyn LlmProvider,
+	request: &ChatRequest,
+	pool: &Pool<Postgres>,
+	context: &UsageContext,
+	sanitizer: &Sanitizer,
+	vars: &PromptVars,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+	let cache = ResponseCache::from_env(pool);
+	chat_checked_with(provider, request, pool, context, sanitizer, vars, cache).await
+}
+
+/// `chat_checked` with the given cache instead of `LLM_CACHE`; `None` disables caching
+pub async fn chat_checked_with(
+	provider: &dyn LlmProvider,
+	request: &ChatRequest,
+	pool: &Pool<Postgres>,
+	context: &UsageContext,
+	sanitizer: &Sanitizer,
+	vars: &PromptVars,
+	cache: Option<ResponseCache>,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+	let guard = LanguageGuard::for_processing_type(&context.processing_type);
+	let mut context = context.clone();
+	let mut attempt = 0;
+
+	loop {
+		attempt += 1;
+		let answer = chat_with_cache(provider, request, pool, &context, cache.clone()).await?;
+		let content = sanitizer.apply(&answer.response.content, vars);
+		let stats = ScriptStats::measure(&content);
+
+		let reason = match guard.check(&content, &stats) {
+			Ok(()) => {
+				answer.accept().await;
+				return Ok(content);
+			}
+			Err(reason) => reason,
+		};
+		let response = answer.response;
+
+		eprintln!(
+			"🈲 {} answer rejected ({}), attempt {}/{}",
+			context.processing_type,
+			reason,
+			attempt,
+			guard.retries + 1
+		);
+
+		let _ = LlmRejection::add_rejection(
+			pool,
+			SaveLlmRejection {
+				task_id: context.task_id,
+				firm_id: context.firm_id,
+				user_id: context.user_id,
+				processing_type: context.processing_type.clone(),
+				provider: provider.name().to_string(),
+				model: if response.model.is_empty() {
+					provider.model().to_string()
+				} else {
+					response.model.clone()
+				},
+				reason: reason.clone(),
+				content: response.content.clone(),
+				cyrillic_ratio: stats.cyrillic_ratio(),
+				latin_ratio: stats.latin_ratio(),
+				cjk_ratio: stats.cjk_ratio(),
+				attempt: attempt as i32,
+			},
+		)
+		.await;
+
+		if attempt > guard.retries {
+			return Err(Box::new(LlmError::LanguageRejected {
+				provider: provider.name().to_string(),
+				reason,
+			}));
+		}
+
+		// A rejected answer may come from the cache, e.g. after the policy changed
+		context.cache_bypass = true;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::llm::{CacheBackend, MockLlmServer, MockReply, OllamaProvider};
+	use sqlx::postgres::PgPoolOptions;
+	use std::time::Duration;
+
+	fn guard() -> LanguageGuard {
+		LanguageGuard {
+			enabled: true,
+			min_cyrillic_ratio: 0.5,
+			max_latin_ratio: 0.3,
+			max_cjk_ratio: 0.0,
+			min_letters: 12,
+			retries: 0,
+		}
+	}
+
+	fn check(guard: &LanguageGuard, text: &str) -> Result<(), String> {
+		guard.check(text, &ScriptStats::measure(text))
+	}
+
+	#[test]
+	fn measure_counts_letters_by_script() {
+		assert_eq!(
+			ScriptStats::measure("Ремонт BMW X5, 2019 г. — 5 000 ₽!"),
+			ScriptStats {
+				letters: 11,
+				cyrillic: 7,
+				latin: 4,
+				cjk: 0,
+			}
+		);
+		// Hangul, kana and ideographs are CJK, Greek letters are only counted as letters
+		assert_eq!(
+			ScriptStats::measure("窗户 まど 창문 αβ"),
+			ScriptStats {
+				letters: 8,
+				cyrillic: 0,
+				latin: 0,
+				cjk: 6,
+			}
+		);
+		assert_eq!(ScriptStats::measure("123 — !?"), ScriptStats::default());
+		assert_eq!(ScriptStats::default().cyrillic_ratio(), 0.0);
+	}
+
+	#[test]
+	fn cyrillic_answer_passes() {
+		assert_eq!(check(&guard(), "Ремонт пластиковых окон под ключ"), Ok(()));
+	}
+
+	#[test]
+	fn latin_is_allowed_up_to_the_threshold() {
+		// 4 Latin letters of 28
+		assert_eq!(
+			check(&guard(), "Ремонт фар BMW X5 в Москве недорого"),
+			Ok(())
+		);
+		// 8 Latin letters of 18
+		assert_eq!(
+			check(&guard(), "Ремонт окон Mercedes"),
+			Err("Latin ratio 0.44".to_string())
+		);
+		assert_eq!(
+			check(&guard(), "This answer is written in English only"),
+			Err("Cyrillic ratio 0.00".to_string())
+		);
+	}
+
+	#[test]
+	fn any_cjk_is_rejected_even_in_short_answers() {
+		assert_eq!(
+			check(&guard(), "Окна 窗"),
+			Err("CJK ratio 0.20".to_string())
+		);
+
+		let tolerant = LanguageGuard {
+			max_cjk_ratio: 0.25,
+			..guard()
+		};
+		assert_eq!(check(&tolerant, "Окна 窗"), Ok(()));
+	}
+
+	#[test]
+	fn short_answers_skip_the_ratios() {
+		assert_eq!(check(&guard(), "BMW X5"), Ok(()));
+		assert_eq!(check(&guard(), "  "), Err("empty answer".to_string()));
+
+		let disabled = LanguageGuard {
+			enabled: false,
+			..guard()
+		};
+		assert_eq!(
+			check(&disabled, "This answer is written in English only"),
+			Ok(())
+		);
+		assert_eq!(check(&disabled, ""), Err("empty answer".to_string()));
+	}
+
+	#[tokio::test]
+	async fn only_accepted_answers_are_cached() {
+		let dir = std::env::temp_dir().join(format!("llm_cache_{}", uuid::Uuid::new_v4()));
+		let cache = ResponseCache::new(CacheBackend::Disk(dir.clone()), Duration::from_secs(60));
+
+		// Usage and rejections are only logged when the database is unreachable
+		let pool = PgPoolOptions::new()
+			.acquire_timeout(Duration::from_millis(100))
+			.connect_lazy("postgres://postgres@127.0.0.1:1/none")
+			.unwrap();
+		let server = MockLlmServer::start("127.0.0.1:0").await.unwrap();
+		let provider = OllamaProvider::new(server.ollama_url(), "mock".to_string());
+		let request = ChatRequest::new("system", "Напиши описание");
+		let context = UsageContext {
+			processing_type: "description".to_string(),
+			..UsageContext::default()
+		};
+		let key = ResponseCache::key(&provider, &request);
+
+		server
+			.push_reply(MockReply::text("This answer is written in English only"))
+			.await;
+		server
+			.push_reply(MockReply::text("Этот ответ написан на русском языке"))
+			.await;
+		let content = chat_checked_with(
+			&provider,
+			&request,
+			&pool,
+			&context,
+			&Sanitizer::new(),
+			&PromptVars::new(),
+			Some(cache.clone()),
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(content, "Этот ответ написан на русском языке");
+		assert_eq!(cache.get(&key).await.unwrap().content, content);
+
+		let rejected_request = ChatRequest::new("system", "Напиши заголовок");
+		server
+			.set_default_reply(MockReply::text("This answer is written in English only"))
+			.await;
+		let error = chat_checked_with(
+			&provider,
+			&rejected_request,
+			&pool,
+			&context,
+			&Sanitizer::new(),
+			&PromptVars::new(),
+			Some(cache.clone()),
+		)
+		.await
+		.unwrap_err();
+
+		assert!(matches!(
+			error.downcast_ref::<LlmError>(),
+			Some(LlmError::LanguageRejected { .. })
+		));
+		assert!(cache
+			.get(&ResponseCache::key(&provider, &rejected_request))
+			.await
+			.is_none());
+
+		server.shutdown();
+		let _ = tokio::fs::remove_dir_all(&dir).await;
+	}
+}
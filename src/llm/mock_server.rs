@@ -0,0 +1,622 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Mutex};
+use tokio::time::{sleep, Duration};
+
+use crate::llm::ChatMessage;
+
+pub const DEFAULT_MOCK_ADDR: &str = "127.0.0.1:11435";
+pub const DEFAULT_MOCK_CONTENT: &str = "Mock response";
+
+const MAX_REQUEST_SIZE: usize = 10 * 1024 * 1024;
+
+/// What the mock server answers to one request
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MockReply {
+	#[serde(default)]
+	pub content: String,
+	#[serde(default = "default_status")]
+	pub status: u16,
+	/// Send truncated JSON instead of a valid answer
+	#[serde(default)]
+	pub malformed: bool,
+	#[serde(default)]
+	pub latency_ms: u64,
+}
+
+fn default_status() -> u16 {
+	200
+}
+
+impl Default for MockReply {
+	fn default() -> Self {
+		Self::text(DEFAULT_MOCK_CONTENT)
+	}
+}
+
+impl MockReply {
+	pub fn text(content: impl Into<String>) -> Self {
+		Self {
+			content: content.into(),
+			status: 200,
+			malformed: false,
+			latency_ms: 0,
+		}
+	}
+
+	pub fn server_error(status: u16) -> Self {
+		Self {
+			content: String::new(),
+			status,
+			malformed: false,
+			latency_ms: 0,
+		}
+	}
+
+	pub fn malformed() -> Self {
+		Self {
+			malformed: true,
+			..Self::default()
+		}
+	}
+
+	pub fn with_latency(mut self, latency_ms: u64) -> Self {
+		self.latency_ms = latency_ms;
+		self
+	}
+}
+
+/// Fixture rule: the answer for prompts that contain `match`.
+///
+/// A line of the JSONL fixture file: `{"match": "Напиши отзыв", "content": "...", "latency_ms": 200}`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MockRule {
+	#[serde(rename = "match")]
+	pub pattern: String,
+	#[serde(flatten)]
+	pub reply: MockReply,
+}
+
+/// A request the mock server received
+#[derive(Debug, Clone)]
+pub struct MockRecordedRequest {
+	pub path: String,
+	pub model: String,
+	pub messages: Vec<ChatMessage>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+	script: VecDeque<MockReply>,
+	rules: Vec<MockRule>,
+	default_reply: MockReply,
+	requests: Vec<MockRecordedRequest>,
+}
+
+impl MockState {
+	/// The scripted queue first, then the first matching rule, then the default reply
+	fn next_reply(&mut self, messages: &[ChatMessage]) -> MockReply {
+		if let Some(reply) = self.script.pop_front() {
+			return reply;
+		}
+
+		self.rules
+			.iter()
+			.find(|rule| {
+				messages
+					.iter()
+					.any(|message| message.content.contains(&rule.pattern))
+			})
+			.map(|rule| rule.reply.clone())
+			.unwrap_or_else(|| self.default_reply.clone())
+	}
+}
+
+/// Local server that answers in the Ollama (`/api/chat`) and OpenAI (`/v1/chat/completions`) formats.
+///
+/// Runs in the same process as the processors, or on its own with `RUN_MODE=mock_llm`.
+#[derive(Clone)]
+pub struct MockLlmServer {
+	addr: SocketAddr,
+	state: Arc<Mutex<MockState>>,
+	shutdown: Arc<watch::Sender<bool>>,
+}
+
+impl MockLlmServer {
+	/// Starts the server; `127.0.0.1:0` picks a free port
+	pub async fn start(addr: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let listener = TcpListener::bind(addr).await.map_err(|e| {
+			Box::new(std::io::Error::new(
+				e.kind(),
+				format!("Failed to bind mock LLM server on {}: {}", addr, e),
+			)) as Box<dyn Error + Send + Sync>
+		})?;
+		let addr = listener.local_addr()?;
+
+		let state = Arc::new(Mutex::new(MockState::default()));
+		let (shutdown, mut shutdown_rx) = watch::channel(false);
+
+		let accept_state = state.clone();
+		tokio::spawn(async move {
+			loop {
+				tokio::select! {
+					accepted = listener.accept() => match accepted {
+						Ok((stream, _)) => {
+							let state = accept_state.clone();
+							tokio::spawn(async move {
+								if let Err(e) = handle_connection(stream, state).await {
+									eprintln!("Mock LLM connection error: {}", e);
+								}
+							});
+						}
+						Err(e) => eprintln!("Mock LLM accept error: {}", e),
+					},
+					_ = shutdown_rx.changed() => break,
+				}
+			}
+		});
+
+		Ok(Self {
+			addr,
+			state,
+			shutdown: Arc::new(shutdown),
+		})
+	}
+
+	/// Server configured from env: `MOCK_LLM_ADDR`, `MOCK_LLM_FIXTURES`,
+	/// `MOCK_LLM_CONTENT`, `MOCK_LLM_LATENCY_MS`
+	pub async fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let addr = env::var("MOCK_LLM_ADDR").unwrap_or_else(|_| DEFAULT_MOCK_ADDR.to_string());
+		let server = Self::start(&addr).await?;
+
+		let mut default_reply = MockReply::text(
+			env::var("MOCK_LLM_CONTENT").unwrap_or_else(|_| DEFAULT_MOCK_CONTENT.to_string()),
+		);
+		if let Some(latency_ms) = env::var("MOCK_LLM_LATENCY_MS")
+			.ok()
+			.and_then(|value| value.parse::<u64>().ok())
+		{
+			default_reply = default_reply.with_latency(latency_ms);
+		}
+		server.set_default_reply(default_reply).await;
+
+		if let Ok(fixtures) = env::var("MOCK_LLM_FIXTURES") {
+			server.load_fixtures(&fixtures).await?;
+		}
+
+		Ok(server)
+	}
+
+	pub fn addr(&self) -> SocketAddr {
+		self.addr
+	}
+
+	/// Value for `LLM_API_URL` with `LLM_PROVIDER=ollama`
+	pub fn ollama_url(&self) -> String {
+		format!("http://{}/api/chat", self.addr)
+	}
+
+	/// Value for `LLM_API_URL` with `LLM_PROVIDER=openai`
+	pub fn openai_url(&self) -> String {
+		format!("http://{}/v1/chat/completions", self.addr)
+	}
+
+	/// Replies in order, one per request, before the rules and the default reply
+	pub async fn push_reply(&self, reply: MockReply) {
+		self.state.lock().await.script.push_back(reply);
+	}
+
+	pub async fn add_rule(&self, pattern: impl Into<String>, reply: MockReply) {
+		self.state.lock().await.rules.push(MockRule {
+			pattern: pattern.into(),
+			reply,
+		});
+	}
+
+	pub async fn set_default_reply(&self, reply: MockReply) {
+		self.state.lock().await.default_reply = reply;
+	}
+
+	/// Loads rules from a JSONL file (one `MockRule` per line)
+	pub async fn load_fixtures(
+		&self,
+		path: impl AsRef<Path>,
+	) -> Result<usize, Box<dyn Error + Send + Sync>> {
+		let path = path.as_ref();
+		let text = tokio::fs::read_to_string(path).await.map_err(|e| {
+			Box::new(std::io::Error::new(
+				e.kind(),
+				format!("Failed to read fixtures {}: {}", path.display(), e),
+			)) as Box<dyn Error + Send + Sync>
+		})?;
+
+		let mut rules = Vec::new();
+		for (index, line) in text.lines().enumerate() {
+			if line.trim().is_empty() {
+				continue;
+			}
+			let rule: MockRule = serde_json::from_str(line).map_err(|e| {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::InvalidData,
+					format!("Invalid fixture {}:{}: {}", path.display(), index + 1, e),
+				)) as Box<dyn Error + Send + Sync>
+			})?;
+			rules.push(rule);
+		}
+
+		let count = rules.len();
+		self.state.lock().await.rules.extend(rules);
+		println!(
+			"Mock LLM: loaded {} fixtures from {}",
+			count,
+			path.display()
+		);
+
+		Ok(count)
+	}
+
+	/// Every request received, in arrival order
+	pub async fn requests(&self) -> Vec<MockRecordedRequest> {
+		self.state.lock().await.requests.clone()
+	}
+
+	pub fn shutdown(&self) {
+		let _ = self.shutdown.send(true);
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct MockChatBody {
+	#[serde(default)]
+	model: String,
+	#[serde(default)]
+	messages: Vec<ChatMessage>,
+}
+
+async fn handle_connection(
+	mut stream: TcpStream,
+	state: Arc<Mutex<MockState>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let (path, body) = match read_request(&mut stream).await? {
+		Some(request) => request,
+		None => return Ok(()),
+	};
+
+	let chat: MockChatBody = match serde_json::from_slice(&body) {
+		Ok(chat) => chat,
+		Err(e) => {
+			let error = json!({ "error": format!("invalid request body: {}", e) }).to_string();
+			return write_response(&mut stream, 400, &error).await;
+		}
+	};
+
+	let is_openai = path.ends_with("/chat/completions");
+	if !is_openai && path != "/api/chat" {
+		let error = json!({ "error": format!("unknown path {}", path) }).to_string();
+		return write_response(&mut stream, 404, &error).await;
+	}
+
+	let reply = {
+		let mut state = state.lock().await;
+		state.requests.push(MockRecordedRequest {
+			path: path.clone(),
+			model: chat.model.clone(),
+			messages: chat.messages.clone(),
+		});
+		state.next_reply(&chat.messages)
+	};
+
+	if reply.latency_ms > 0 {
+		sleep(Duration::from_millis(reply.latency_ms)).await;
+	}
+
+	if reply.status >= 400 {
+		let error = json!({ "error": format!("mock error {}", reply.status) }).to_string();
+		return write_response(&mut stream, reply.status, &error).await;
+	}
+
+	let response_body = if is_openai {
+		openai_body(&chat, &reply.content)
+	} else {
+		ollama_body(&chat, &reply.content)
+	};
+
+	let mut response_text = response_body.to_string();
+	if reply.malformed {
+		// Cut the JSON in the middle of the content string, on a char boundary
+		let middle = response_text
+			.char_indices()
+			.map(|(index, _)| index)
+			.take_while(|index| *index <= response_text.len() / 2)
+			.last()
+			.unwrap_or(0);
+		response_text.truncate(middle);
+	}
+
+	write_response(&mut stream, reply.status, &response_text).await
+}
+
+fn count_tokens(text: &str) -> usize {
+	text.split_whitespace().count()
+}
+
+fn prompt_tokens(chat: &MockChatBody) -> usize {
+	chat.messages
+		.iter()
+		.map(|message| count_tokens(&message.content))
+		.sum()
+}
+
+fn ollama_body(chat: &MockChatBody, content: &str) -> Value {
+	json!({
+		"model": chat.model,
+		"created_at": chrono::Utc::now().to_rfc3339(),
+		"message": { "role": "assistant", "content": content },
+		"done": true,
+		"prompt_eval_count": prompt_tokens(chat),
+		"eval_count": count_tokens(content),
+	})
+}
+
+fn openai_body(chat: &MockChatBody, content: &str) -> Value {
+	let prompt_tokens = prompt_tokens(chat);
+	let completion_tokens = count_tokens(content);
+
+	json!({
+		"id": format!("chatcmpl-mock-{}", uuid::Uuid::new_v4()),
+		"object": "chat.completion",
+		"created": chrono::Utc::now().timestamp(),
+		"model": chat.model,
+		"choices": [{
+			"index": 0,
+			"message": { "role": "assistant", "content": content },
+			"finish_reason": "stop",
+		}],
+		"usage": {
+			"prompt_tokens": prompt_tokens,
+			"completion_tokens": completion_tokens,
+			"total_tokens": prompt_tokens + completion_tokens,
+		},
+	})
+}
+
+/// Reads one HTTP/1.1 request: the path and a body of `Content-Length` bytes
+async fn read_request(
+	stream: &mut TcpStream,
+) -> Result<Option<(String, Vec<u8>)>, Box<dyn Error + Send + Sync>> {
+	let mut buffer = Vec::new();
+	let mut chunk = [0u8; 8192];
+
+	let header_end = loop {
+		if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+			break position + 4;
+		}
+		if buffer.len() > MAX_REQUEST_SIZE {
+			return Err("Mock LLM request headers too large".into());
+		}
+
+		let read = stream.read(&mut chunk).await?;
+		if read == 0 {
+			return Ok(None);
+		}
+		buffer.extend_from_slice(&chunk[..read]);
+	};
+
+	let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+	let path = head
+		.lines()
+		.next()
+		.and_then(|line| line.split_whitespace().nth(1))
+		.unwrap_or("/")
+		.to_string();
+
+	let content_length = head
+		.lines()
+		.filter_map(|line| line.split_once(':'))
+		.find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+		.and_then(|(_, value)| value.trim().parse::<usize>().ok())
+		.unwrap_or(0);
+
+	if content_length > MAX_REQUEST_SIZE {
+		return Err("Mock LLM request body too large".into());
+	}
+
+	let mut body = buffer[header_end..].to_vec();
+	while body.len() < content_length {
+		let read = stream.read(&mut chunk).await?;
+		if read == 0 {
+			break;
+		}
+		body.extend_from_slice(&chunk[..read]);
+	}
+	body.truncate(content_length);
+
+	Ok(Some((path, body)))
+}
+
+async fn write_response(
+	stream: &mut TcpStream,
+	status: u16,
+	body: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let reason = match status {
+		200 => "OK",
+		400 => "Bad Request",
+		404 => "Not Found",
+		429 => "Too Many Requests",
+		500 => "Internal Server Error",
+		502 => "Bad Gateway",
+		503 => "Service Unavailable",
+		_ => "Mock Status",
+	};
+
+	let response = format!(
+		"HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		status,
+		reason,
+		body.len(),
+		body
+	);
+
+	stream.write_all(response.as_bytes()).await?;
+	stream.shutdown().await?;
+	Ok(())
+}
+
+/// `RUN_MODE=mock_llm`: the server runs until Ctrl+C
+pub async fn run_mock_llm_server() -> Result<(), Box<dyn Error + Send + Sync>> {
+	let server = MockLlmServer::from_env().await?;
+
+	println!("Mock LLM server listening on {}", server.addr());
+	println!(
+		"  ollama: LLM_PROVIDER=ollama LLM_API_URL={}",
+		server.ollama_url()
+	);
+	println!(
+		"  openai: LLM_PROVIDER=openai LLM_API_URL={}",
+		server.openai_url()
+	);
+
+	tokio::signal::ctrl_c().await?;
+	println!(
+		"Mock LLM server stopped after {} requests",
+		server.requests().await.len()
+	);
+	server.shutdown();
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::llm::{ChatRequest, LlmError, LlmProvider, OllamaProvider, OpenAiProvider};
+
+	async fn server() -> MockLlmServer {
+		MockLlmServer::start("127.0.0.1:0").await.unwrap()
+	}
+
+	#[tokio::test]
+	async fn ollama_provider_gets_scripted_reply_and_usage() {
+		let server = server().await;
+		server.push_reply(MockReply::text("Первый ответ")).await;
+		let provider = OllamaProvider::new(server.ollama_url(), "mock".to_string());
+
+		let response = provider
+			.chat(&ChatRequest::new("system", "Напиши заголовок"))
+			.await
+			.unwrap();
+
+		assert_eq!(response.content, "Первый ответ");
+		let usage = response.usage.unwrap();
+		assert_eq!(usage.prompt_tokens, 3);
+		assert_eq!(usage.completion_tokens, 2);
+
+		let requests = server.requests().await;
+		assert_eq!(requests.len(), 1);
+		assert_eq!(requests[0].path, "/api/chat");
+		assert_eq!(requests[0].model, "mock");
+		assert_eq!(requests[0].messages[1].content, "Напиши заголовок");
+		server.shutdown();
+	}
+
+	#[tokio::test]
+	async fn openai_provider_gets_rule_reply_then_default() {
+		let server = server().await;
+		server
+			.add_rule("отзыв", MockReply::text("Отличный магазин"))
+			.await;
+		let provider = OpenAiProvider::new(server.openai_url(), None, "mock".to_string());
+
+		let matched = provider
+			.chat(&ChatRequest::new("system", "Напиши отзыв"))
+			.await
+			.unwrap();
+		let other = provider
+			.chat(&ChatRequest::new("system", "Напиши заголовок"))
+			.await
+			.unwrap();
+
+		assert_eq!(matched.content, "Отличный магазин");
+		assert_eq!(other.content, DEFAULT_MOCK_CONTENT);
+		server.shutdown();
+	}
+
+	#[tokio::test]
+	async fn malformed_cyrillic_reply_is_an_invalid_response() {
+		let server = server().await;
+		let mut reply =
+			MockReply::text("Длинный ответ на русском языке без единой латинской буквы");
+		reply.malformed = true;
+		server.push_reply(reply).await;
+		let provider = OllamaProvider::new(server.ollama_url(), "mock".to_string());
+
+		let error = provider
+			.chat(&ChatRequest::new("system", "user"))
+			.await
+			.unwrap_err();
+
+		assert!(matches!(
+			error.downcast_ref::<LlmError>(),
+			Some(LlmError::InvalidResponse { .. })
+		));
+		server.shutdown();
+	}
+
+	#[tokio::test]
+	async fn server_error_is_reported_with_status() {
+		let server = server().await;
+		server.push_reply(MockReply::server_error(503)).await;
+		let provider = OpenAiProvider::new(server.openai_url(), None, "mock".to_string());
+
+		let error = provider
+			.chat(&ChatRequest::new("system", "user"))
+			.await
+			.unwrap_err();
+
+		assert!(matches!(
+			error.downcast_ref::<LlmError>(),
+			Some(LlmError::Status { status: 503, .. })
+		));
+		server.shutdown();
+	}
+
+	#[tokio::test]
+	async fn fixtures_are_loaded_from_jsonl() {
+		let server = server().await;
+		let path = env::temp_dir().join(format!("mock_llm_{}.jsonl", uuid::Uuid::new_v4()));
+		tokio::fs::write(
+			&path,
+			"{\"match\": \"описание\", \"content\": \"Описание товара\"}\n\n{\"match\": \"ошибка\", \"status\": 500}\n",
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(server.load_fixtures(&path).await.unwrap(), 2);
+		let provider = OllamaProvider::new(server.ollama_url(), "mock".to_string());
+		let response = provider
+			.chat(&ChatRequest::new("system", "Нужно описание"))
+			.await
+			.unwrap();
+		let error = provider
+			.chat(&ChatRequest::new("system", "ошибка"))
+			.await
+			.unwrap_err();
+
+		assert_eq!(response.content, "Описание товара");
+		assert!(matches!(
+			error.downcast_ref::<LlmError>(),
+			Some(LlmError::Status { status: 500, .. })
+		));
+		let _ = tokio::fs::remove_file(&path).await;
+		server.shutdown();
+	}
+}
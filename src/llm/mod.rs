@@ -0,0 +1,27 @@
+pub mod cache;
+pub mod error;
+pub mod language_guard;
+pub mod mock_server;
+pub mod ollama_provider;
+pub mod openai_provider;
+pub mod prompt_registry;
+pub mod provider;
+pub mod qwen_cli_provider;
+pub mod retry;
+pub mod sanitizer;
+pub mod title_constraints;
+pub mod usage;
+
+pub use self::cache::*;
+pub use self::error::*;
+pub use self::language_guard::*;
+pub use self::mock_server::*;
+pub use self::ollama_provider::*;
+pub use self::openai_provider::*;
+pub use self::prompt_registry::*;
+pub use self::provider::*;
+pub use self::qwen_cli_provider::*;
+pub use self::retry::*;
+pub use self::sanitizer::*;
+pub use self::title_constraints::*;
+pub use self::usage::*;
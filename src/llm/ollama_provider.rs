@@ -0,0 +1,118 @@
+use futures::future::BoxFuture;
+use reqwest::{
+	header::{self, HeaderMap, HeaderValue},
+	Client,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::error::Error;
+
+use crate::llm::{ChatMessage, ChatRequest, ChatResponse, LlmError, LlmProvider, TokenUsage};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct OllamaChatResponse {
+	model: String,
+	message: ChatMessage,
+	#[serde(default)]
+	prompt_eval_count: Option<u32>,
+	#[serde(default)]
+	eval_count: Option<u32>,
+}
+
+/// Ollama `/api/chat`
+pub struct OllamaProvider {
+	client: Client,
+	url: String,
+	model: String,
+}
+
+impl OllamaProvider {
+	pub fn new(url: String, model: String) -> Self {
+		let client = Client::builder()
+			.danger_accept_invalid_certs(true)
+			.build()
+			.unwrap();
+
+		Self { client, url, model }
+	}
+
+	async fn send(
+		&self,
+		request: &ChatRequest,
+	) -> Result<ChatResponse, Box<dyn Error + Send + Sync>> {
+		let headers: HeaderMap<HeaderValue> = header::HeaderMap::from_iter(vec![
+			(header::ACCEPT, "application/json".parse().unwrap()),
+			(header::CONTENT_TYPE, "application/json".parse().unwrap()),
+		]);
+
+		let mut body = json!({
+			"model": self.model,
+			"stream": false,
+			"messages": request.messages,
+		});
+
+		if let Some(temperature) = request.temperature {
+			body["options"] = json!({ "temperature": temperature });
+		}
+
+		let response = self
+			.client
+			.post(&self.url)
+			.headers(headers)
+			.json(&body)
+			.send()
+			.await
+			.map_err(|e| {
+				Box::new(LlmError::transport("ollama", e)) as Box<dyn Error + Send + Sync>
+			})?;
+
+		let status = response.status();
+		let response_text = response.text().await.map_err(|e| {
+			Box::new(LlmError::transport("ollama", e)) as Box<dyn Error + Send + Sync>
+		})?;
+
+		if !status.is_success() {
+			return Err(Box::new(LlmError::Status {
+				provider: "ollama".to_string(),
+				status: status.as_u16(),
+				body: response_text,
+			}));
+		}
+
+		let api_response: OllamaChatResponse =
+			serde_json::from_str(&response_text).map_err(|e| {
+				Box::new(LlmError::invalid_response("ollama", e)) as Box<dyn Error + Send + Sync>
+			})?;
+
+		let usage = match (api_response.prompt_eval_count, api_response.eval_count) {
+			(None, None) => None,
+			(prompt_tokens, completion_tokens) => Some(TokenUsage::new(
+				prompt_tokens.unwrap_or(0),
+				completion_tokens.unwrap_or(0),
+			)),
+		};
+
+		Ok(ChatResponse {
+			content: api_response.message.content,
+			model: api_response.model,
+			usage,
+		})
+	}
+}
+
+impl LlmProvider for OllamaProvider {
+	fn name(&self) -> &str {
+		"ollama"
+	}
+
+	fn model(&self) -> &str {
+		&self.model
+	}
+
+	fn chat<'a>(
+		&'a self,
+		request: &'a ChatRequest,
+	) -> BoxFuture<'a, Result<ChatResponse, Box<dyn Error + Send + Sync>>> {
+		Box::pin(self.send(request))
+	}
+}
@@ -0,0 +1,157 @@
+use futures::future::BoxFuture;
+use reqwest::{
+	header::{self, HeaderMap, HeaderValue},
+	Client,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::error::Error;
+
+use crate::llm::{ChatMessage, ChatRequest, ChatResponse, LlmError, LlmProvider, TokenUsage};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct OpenAiChatResponse {
+	#[serde(default)]
+	model: String,
+	choices: Vec<Choice>,
+	#[serde(default)]
+	usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Choice {
+	message: ChatMessage,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Usage {
+	prompt_tokens: u32,
+	completion_tokens: u32,
+}
+
+/// OpenAI-compatible `/v1/chat/completions`
+pub struct OpenAiProvider {
+	client: Client,
+	url: String,
+	api_key: Option<String>,
+	model: String,
+}
+
+impl OpenAiProvider {
+	pub fn new(url: String, api_key: Option<String>, model: String) -> Self {
+		let client = Client::builder()
+			.danger_accept_invalid_certs(true)
+			.build()
+			.unwrap();
+
+		Self {
+			client,
+			url,
+			api_key,
+			model,
+		}
+	}
+
+	async fn send(
+		&self,
+		request: &ChatRequest,
+	) -> Result<ChatResponse, Box<dyn Error + Send + Sync>> {
+		let mut headers: HeaderMap<HeaderValue> = header::HeaderMap::from_iter(vec![
+			(header::ACCEPT, "application/json".parse().unwrap()),
+			(header::CONTENT_TYPE, "application/json".parse().unwrap()),
+		]);
+
+		if let Some(ref api_key) = self.api_key {
+			headers.insert(
+				header::AUTHORIZATION,
+				format!("Bearer {}", api_key).parse().map_err(|e| {
+					Box::new(std::io::Error::new(
+						std::io::ErrorKind::InvalidInput,
+						format!("Invalid API key header: {}", e),
+					)) as Box<dyn Error + Send + Sync>
+				})?,
+			);
+		}
+
+		let mut body = json!({
+			"model": self.model,
+			"messages": request.messages,
+		});
+
+		if let Some(temperature) = request.temperature {
+			body["temperature"] = json!(temperature);
+		}
+
+		let response = self
+			.client
+			.post(&self.url)
+			.headers(headers)
+			.json(&body)
+			.send()
+			.await
+			.map_err(|e| {
+				Box::new(LlmError::transport("openai", e)) as Box<dyn Error + Send + Sync>
+			})?;
+
+		let status = response.status();
+		let response_text = response.text().await.map_err(|e| {
+			Box::new(LlmError::transport("openai", e)) as Box<dyn Error + Send + Sync>
+		})?;
+
+		if !status.is_success() {
+			return Err(Box::new(LlmError::Status {
+				provider: "openai".to_string(),
+				status: status.as_u16(),
+				body: response_text,
+			}));
+		}
+
+		let api_response: OpenAiChatResponse =
+			serde_json::from_str(&response_text).map_err(|e| {
+				Box::new(LlmError::invalid_response("openai", e)) as Box<dyn Error + Send + Sync>
+			})?;
+
+		let usage = api_response
+			.usage
+			.map(|usage| TokenUsage::new(usage.prompt_tokens, usage.completion_tokens));
+
+		let content = api_response
+			.choices
+			.into_iter()
+			.next()
+			.map(|choice| choice.message.content)
+			.ok_or_else(|| {
+				Box::new(LlmError::invalid_response("openai", "no choices found"))
+					as Box<dyn Error + Send + Sync>
+			})?;
+
+		let model = if api_response.model.is_empty() {
+			self.model.clone()
+		} else {
+			api_response.model
+		};
+
+		Ok(ChatResponse {
+			content,
+			model,
+			usage,
+		})
+	}
+}
+
+impl LlmProvider for OpenAiProvider {
+	fn name(&self) -> &str {
+		"openai"
+	}
+
+	fn model(&self) -> &str {
+		&self.model
+	}
+
+	fn chat<'a>(
+		&'a self,
+		request: &'a ChatRequest,
+	) -> BoxFuture<'a, Result<ChatResponse, Box<dyn Error + Send + Sync>>> {
+		Box::pin(self.send(request))
+	}
+}
@@ -0,0 +1,322 @@
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::llm::{ChatMessage, ChatRequest};
+use crate::models::Category;
+use crate::utils::Translit;
+
+pub const DEFAULT_PROMPTS_DIR: &str = "prompts";
+pub const DEFAULT_VARIANT: &str = "default";
+
+pub type PromptVars = HashMap<String, String>;
+
+/// Prompt template from `prompts/<name>/<variant>.txt`.
+///
+/// The file is split into sections by `[system]` and `[user]` lines; text without a section
+/// header is the user message. Variables are written as `{firm_name}` or
+/// `{category.rod_name}`.
+#[derive(Debug, Clone)]
+pub struct PromptTemplate {
+	pub name: String,
+	pub variant: String,
+	pub system: String,
+	pub user: String,
+}
+
+impl PromptTemplate {
+	pub fn parse(name: &str, variant: &str, text: &str) -> Self {
+		let mut system = Vec::new();
+		let mut user = Vec::new();
+		let mut in_system = false;
+
+		for line in text.lines() {
+			match line.trim() {
+				"[system]" => in_system = true,
+				"[user]" => in_system = false,
+				_ if in_system => system.push(line),
+				_ => user.push(line),
+			}
+		}
+
+		Self {
+			name: name.to_string(),
+			variant: variant.to_string(),
+			system: system.join("\n").trim().to_string(),
+			user: user.join("\n").trim().to_string(),
+		}
+	}
+
+	pub fn render(&self, vars: &PromptVars) -> Result<ChatRequest, Box<dyn Error + Send + Sync>> {
+		let mut messages = Vec::new();
+
+		if !self.system.is_empty() {
+			messages.push(ChatMessage::system(self.substitute(&self.system, vars)?));
+		}
+		messages.push(ChatMessage::user(self.substitute(&self.user, vars)?));
+
+		Ok(ChatRequest {
+			messages,
+			temperature: None,
+			partial_output: None,
+		})
+	}
+
+	fn substitute(
+		&self,
+		text: &str,
+		vars: &PromptVars,
+	) -> Result<String, Box<dyn Error + Send + Sync>> {
+		let mut result = String::with_capacity(text.len());
+		let mut rest = text;
+
+		while let Some(start) = rest.find('{') {
+			result.push_str(&rest[..start]);
+			let after = &rest[start + 1..];
+
+			let end = match after.find('}') {
+				Some(end) if is_variable_name(&after[..end]) => end,
+				_ => {
+					// Not a variable, keep the brace as is
+					result.push('{');
+					rest = after;
+					continue;
+				}
+			};
+
+			let key = &after[..end];
+			let value = vars.get(key).ok_or_else(|| {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::InvalidInput,
+					format!(
+						"Missing variable '{}' for prompt {}/{}",
+						key, self.name, self.variant
+					),
+				)) as Box<dyn Error + Send + Sync>
+			})?;
+
+			result.push_str(value);
+			rest = &after[end + 1..];
+		}
+
+		result.push_str(rest);
+		Ok(result)
+	}
+}
+
+fn is_variable_name(value: &str) -> bool {
+	!value.is_empty()
+		&& value
+			.chars()
+			.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '.')
+}
+
+/// Prompt templates; files are read on every lookup,
+/// so edited prompts are picked up without a restart
+#[derive(Debug, Clone)]
+pub struct PromptRegistry {
+	dir: PathBuf,
+}
+
+impl PromptRegistry {
+	pub fn new(dir: impl Into<PathBuf>) -> Self {
+		Self { dir: dir.into() }
+	}
+
+	pub fn from_env() -> Self {
+		Self::new(env::var("PROMPTS_DIR").unwrap_or_else(|_| DEFAULT_PROMPTS_DIR.to_string()))
+	}
+
+	/// The category's template if there is one, otherwise `default`
+	pub fn load(
+		&self,
+		name: &str,
+		category: Option<&str>,
+	) -> Result<PromptTemplate, Box<dyn Error + Send + Sync>> {
+		let mut variants = Vec::new();
+		if let Some(category) = category.map(category_slug).filter(|slug| !slug.is_empty()) {
+			variants.push(category);
+		}
+		variants.push(DEFAULT_VARIANT.to_string());
+
+		for variant in variants {
+			let path = self.dir.join(name).join(format!("{}.txt", variant));
+
+			match std::fs::read_to_string(&path) {
+				Ok(text) => {
+					println!("Prompt {}: {}", name, path.display());
+					return Ok(PromptTemplate::parse(name, &variant, &text));
+				}
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+				Err(e) => {
+					return Err(Box::new(std::io::Error::new(
+						e.kind(),
+						format!("Failed to read prompt {}: {}", path.display(), e),
+					)));
+				}
+			}
+		}
+
+		Err(Box::new(std::io::Error::new(
+			std::io::ErrorKind::NotFound,
+			format!(
+				"Prompt template '{}' not found in {}",
+				name,
+				self.dir.display()
+			),
+		)))
+	}
+
+	pub fn render(
+		&self,
+		name: &str,
+		category: Option<&str>,
+		vars: &PromptVars,
+	) -> Result<ChatRequest, Box<dyn Error + Send + Sync>> {
+		self.load(name, category)?.render(vars)
+	}
+}
+
+/// File name of a variant: `Автосервисы` -> `avtoservisy`, `night_clubs` -> `night_clubs`
+pub fn category_slug(category: &str) -> String {
+	Translit::convert(Some(category.trim().to_string()))
+		.chars()
+		.filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+		.collect()
+}
+
+/// Adds the category fields as `{category.name}`, `{category.rod_name}` and so on
+pub fn insert_category_vars(vars: &mut PromptVars, category: &Category) {
+	let fields = [
+		("name", &category.name),
+		("abbreviation", &category.abbreviation),
+		("single_name", &category.single_name),
+		("rod_name", &category.rod_name),
+		("pred_name", &category.pred_name),
+		("vin_name", &category.vin_name),
+	];
+
+	for (field, value) in fields {
+		vars.insert(
+			format!("category.{}", field),
+			value.clone().unwrap_or_default(),
+		);
+	}
+}
+
+/// Prompt variant key and category variables for processing by `CRAWLER_CATEGORY_ID`
+pub async fn prompt_category(
+	pool: &Pool<Postgres>,
+	category_id: &Uuid,
+) -> (Option<String>, PromptVars) {
+	let mut vars = PromptVars::new();
+
+	match Category::get_category_by_id(pool, category_id).await {
+		Ok(category) => {
+			insert_category_vars(&mut vars, &category);
+			let key = category.abbreviation.clone().or(category.name.clone());
+			(key, vars)
+		}
+		Err(_) => (None, vars),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn vars(pairs: &[(&str, &str)]) -> PromptVars {
+		pairs
+			.iter()
+			.map(|(key, value)| (key.to_string(), value.to_string()))
+			.collect()
+	}
+
+	fn temp_registry(files: &[(&str, &str)]) -> PromptRegistry {
+		let dir = env::temp_dir().join(format!("prompts_{}", Uuid::new_v4()));
+		for (path, text) in files {
+			let path = dir.join(path);
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(path, text).unwrap();
+		}
+		PromptRegistry::new(dir)
+	}
+
+	#[test]
+	fn splits_system_and_user_sections() {
+		let template = PromptTemplate::parse(
+			"title",
+			"default",
+			"[system]\nТы копирайтер.\n\n[user]\nНапиши заголовок для {firm_name}\n",
+		);
+
+		assert_eq!(template.system, "Ты копирайтер.");
+		assert_eq!(template.user, "Напиши заголовок для {firm_name}");
+	}
+
+	#[test]
+	fn text_without_header_is_the_user_message() {
+		let template = PromptTemplate::parse("title", "default", "  Заголовок для {firm_name}  ");
+		let request = template.render(&vars(&[("firm_name", "Окна")])).unwrap();
+
+		assert!(template.system.is_empty());
+		assert_eq!(request.messages.len(), 1);
+		assert_eq!(request.messages[0].role, "user");
+		assert_eq!(request.messages[0].content, "Заголовок для Окна");
+	}
+
+	#[test]
+	fn substitutes_variables() {
+		let template = PromptTemplate::parse(
+			"title",
+			"default",
+			"[system]\nКатегория: {category.rod_name}\n[user]\n{firm_name}: {\"title\": 1} {Upper} {}",
+		);
+		let request = template
+			.render(&vars(&[
+				("firm_name", "Окна Плюс"),
+				("category.rod_name", "окон"),
+			]))
+			.unwrap();
+
+		assert_eq!(request.messages[0].role, "system");
+		assert_eq!(request.messages[0].content, "Категория: окон");
+		// Braces that are not variable names stay as they are
+		assert_eq!(
+			request.messages[1].content,
+			"Окна Плюс: {\"title\": 1} {Upper} {}"
+		);
+	}
+
+	#[test]
+	fn missing_variable_is_an_error() {
+		let template = PromptTemplate::parse("title", "cafe", "Заголовок для {firm_name}");
+		let error = template.render(&PromptVars::new()).unwrap_err();
+
+		assert!(error.to_string().contains("'firm_name'"), "{}", error);
+		assert!(error.to_string().contains("title/cafe"), "{}", error);
+	}
+
+	#[test]
+	fn loads_category_variant_or_falls_back_to_default() {
+		let registry = temp_registry(&[
+			("title/default.txt", "Общий {firm_name}"),
+			("title/night_clubs.txt", "Клуб {firm_name}"),
+		]);
+
+		let night_club = registry.load("title", Some("night_clubs")).unwrap();
+		assert_eq!(night_club.variant, "night_clubs");
+		assert_eq!(night_club.user, "Клуб {firm_name}");
+
+		for category in [Some("cafe"), Some("  "), None] {
+			let template = registry.load("title", category).unwrap();
+			assert_eq!(template.variant, DEFAULT_VARIANT, "{:?}", category);
+			assert_eq!(template.user, "Общий {firm_name}");
+		}
+
+		assert!(registry.load("description", Some("cafe")).is_err());
+	}
+}
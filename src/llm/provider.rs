@@ -0,0 +1,256 @@
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::llm::{
+	circuit_breaker_for, OllamaProvider, OpenAiProvider, QwenCliProvider, RetryPolicy,
+	RetryingProvider,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatMessage {
+	pub role: String,
+	pub content: String,
+}
+
+impl ChatMessage {
+	pub fn system(content: impl Into<String>) -> Self {
+		Self {
+			role: "system".to_string(),
+			content: content.into(),
+		}
+	}
+
+	pub fn user(content: impl Into<String>) -> Self {
+		Self {
+			role: "user".to_string(),
+			content: content.into(),
+		}
+	}
+
+	pub fn assistant(content: impl Into<String>) -> Self {
+		Self {
+			role: "assistant".to_string(),
+			content: content.into(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChatRequest {
+	pub messages: Vec<ChatMessage>,
+	pub temperature: Option<f32>,
+	/// The answer so far, sent as it is generated (supported by qwen-cli)
+	pub partial_output: Option<UnboundedSender<String>>,
+}
+
+impl ChatRequest {
+	/// Request of a system prompt and one user message
+	pub fn new(system_prompt: impl Into<String>, user_prompt: impl Into<String>) -> Self {
+		Self {
+			messages: vec![
+				ChatMessage::system(system_prompt),
+				ChatMessage::user(user_prompt),
+			],
+			temperature: None,
+			partial_output: None,
+		}
+	}
+
+	pub fn with_partial_output(mut self, sender: Option<UnboundedSender<String>>) -> Self {
+		self.partial_output = sender;
+		self
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct TokenUsage {
+	pub prompt_tokens: u32,
+	pub completion_tokens: u32,
+	/// The backend returned no counts, so they are estimated from the text length
+	pub estimated: bool,
+}
+
+impl TokenUsage {
+	pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
+		Self {
+			prompt_tokens,
+			completion_tokens,
+			estimated: false,
+		}
+	}
+
+	/// Rough estimate: about 4 characters per token
+	pub fn estimate(prompt: &str, completion: &str) -> Self {
+		let tokens = |text: &str| (text.chars().count() as u32).div_ceil(4);
+
+		Self {
+			prompt_tokens: tokens(prompt),
+			completion_tokens: tokens(completion),
+			estimated: true,
+		}
+	}
+
+	pub fn total_tokens(&self) -> u32 {
+		self.prompt_tokens + self.completion_tokens
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatResponse {
+	pub content: String,
+	pub model: String,
+	pub usage: Option<TokenUsage>,
+}
+
+/// Chat completion interface shared by every backend (Ollama, OpenAI, qwen-cli)
+pub trait LlmProvider: Send + Sync {
+	fn name(&self) -> &str;
+
+	fn model(&self) -> &str;
+
+	fn chat<'a>(
+		&'a self,
+		request: &'a ChatRequest,
+	) -> BoxFuture<'a, Result<ChatResponse, Box<dyn Error + Send + Sync>>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderKind {
+	Ollama,
+	OpenAi,
+	QwenCli,
+}
+
+impl ProviderKind {
+	pub fn parse(value: &str) -> Option<Self> {
+		match value.trim().to_lowercase().as_str() {
+			"ollama" => Some(Self::Ollama),
+			"openai" | "open_ai" => Some(Self::OpenAi),
+			"qwen_cli" | "qwen-cli" | "qwen" => Some(Self::QwenCli),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct ProviderConfig {
+	pub kind: ProviderKind,
+	pub api_url: Option<String>,
+	pub api_key: Option<String>,
+	pub model: String,
+}
+
+impl ProviderConfig {
+	/// Reads the provider settings of a processing type from env.
+	///
+	/// A variable with the type suffix comes first (`LLM_PROVIDER_TITLE`, `LLM_MODEL_PAGES`),
+	/// then the shared one (`LLM_PROVIDER`, `LLM_MODEL`). The URL and key default to
+	/// `OPENAI_API_BASE` and `OPENAI_API_KEY`.
+	pub fn from_env(processing_type: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let (default_kind, default_model) = default_provider(processing_type);
+
+		let kind = match env_for(processing_type, "PROVIDER") {
+			Some(value) => ProviderKind::parse(&value).ok_or_else(|| {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::InvalidInput,
+					format!("Unknown LLM provider '{}' for {}", value, processing_type),
+				)) as Box<dyn Error + Send + Sync>
+			})?,
+			None => default_kind,
+		};
+
+		let model = env_for(processing_type, "MODEL").unwrap_or_else(|| match kind {
+			ProviderKind::QwenCli => QWEN_CLI_DEFAULT_MODEL.to_string(),
+			_ if kind == default_kind => default_model.to_string(),
+			_ => DEFAULT_MODEL.to_string(),
+		});
+
+		let api_url =
+			env_for(processing_type, "API_URL").or_else(|| env::var("OPENAI_API_BASE").ok());
+		let api_key =
+			env_for(processing_type, "API_KEY").or_else(|| env::var("OPENAI_API_KEY").ok());
+
+		if kind != ProviderKind::QwenCli && api_url.is_none() {
+			return Err(Box::new(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				format!(
+					"LLM API url not set for {} (LLM_API_URL or OPENAI_API_BASE)",
+					processing_type
+				),
+			)));
+		}
+
+		Ok(Self {
+			kind,
+			api_url,
+			api_key,
+			model,
+		})
+	}
+}
+
+pub const DEFAULT_MODEL: &str = "deepseek-v2:16b";
+pub const QWEN_CLI_DEFAULT_MODEL: &str = "qwen-code";
+
+/// Providers used before they became configurable
+fn default_provider(processing_type: &str) -> (ProviderKind, &'static str) {
+	match processing_type {
+		"title" | "description" | "keyword_extraction" => {
+			(ProviderKind::QwenCli, QWEN_CLI_DEFAULT_MODEL)
+		}
+		"reviews" => (ProviderKind::OpenAi, "gpt-4o-mini"),
+		"reviews_rewrite" => (ProviderKind::Ollama, "qwen2.5:14b"),
+		_ => (ProviderKind::Ollama, DEFAULT_MODEL),
+	}
+}
+
+pub(crate) fn env_for(processing_type: &str, key: &str) -> Option<String> {
+	env::var(format!("LLM_{}_{}", key, processing_type.to_uppercase()))
+		.or_else(|_| env::var(format!("LLM_{}", key)))
+		.ok()
+		.filter(|value| !value.trim().is_empty())
+}
+
+pub fn build_provider(config: ProviderConfig) -> Arc<dyn LlmProvider> {
+	match config.kind {
+		ProviderKind::Ollama => Arc::new(OllamaProvider::new(
+			config.api_url.unwrap_or_default(),
+			config.model,
+		)),
+		ProviderKind::OpenAi => Arc::new(OpenAiProvider::new(
+			config.api_url.unwrap_or_default(),
+			config.api_key,
+			config.model,
+		)),
+		ProviderKind::QwenCli => Arc::new(QwenCliProvider::new(config.model)),
+	}
+}
+
+/// Provider of a processing type ("title", "description", "reviews", "pages", ...)
+/// with retries and a circuit breaker
+pub fn provider_for(
+	processing_type: &str,
+) -> Result<Arc<dyn LlmProvider>, Box<dyn Error + Send + Sync>> {
+	let config = ProviderConfig::from_env(processing_type)?;
+
+	println!(
+		"LLM provider for {}: {:?} ({})",
+		processing_type, config.kind, config.model
+	);
+
+	let breaker_key = format!(
+		"{:?}:{}",
+		config.kind,
+		config.api_url.clone().unwrap_or(config.model.clone())
+	);
+
+	Ok(Arc::new(RetryingProvider::new(
+		build_provider(config),
+		RetryPolicy::from_env(processing_type),
+		circuit_breaker_for(&breaker_key, processing_type),
+	)))
+}
@@ -0,0 +1,442 @@
+use futures::future::BoxFuture;
+use std::env;
+use std::error::Error;
+use std::process::Stdio;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::{timeout, Duration};
+
+use crate::llm::{
+	ChatRequest, ChatResponse, LlmError, LlmProvider, TokenUsage, QWEN_CLI_DEFAULT_MODEL,
+};
+
+pub const QWEN_CLI_DEFAULT_TIMEOUT_SECS: u64 = 300;
+pub const QWEN_CLI_DEFAULT_MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+const QWEN_CLI_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// qwen-cli (`npx @qwen-code/qwen-code -p <prompt>`)
+pub struct QwenCliProvider {
+	model: String,
+	timeout: Duration,
+	max_output_bytes: usize,
+}
+
+impl QwenCliProvider {
+	/// The timeout and output limit come from `QWEN_CLI_TIMEOUT_SECS` and `QWEN_CLI_MAX_OUTPUT_BYTES`
+	pub fn new(model: String) -> Self {
+		let timeout_secs = env::var("QWEN_CLI_TIMEOUT_SECS")
+			.ok()
+			.and_then(|value| value.parse::<u64>().ok())
+			.unwrap_or(QWEN_CLI_DEFAULT_TIMEOUT_SECS);
+		let max_output_bytes = env::var("QWEN_CLI_MAX_OUTPUT_BYTES")
+			.ok()
+			.and_then(|value| value.parse::<usize>().ok())
+			.unwrap_or(QWEN_CLI_DEFAULT_MAX_OUTPUT_BYTES);
+
+		Self {
+			model,
+			timeout: Duration::from_secs(timeout_secs),
+			max_output_bytes,
+		}
+	}
+
+	/// qwen-cli takes a single prompt, so system messages are joined with the user ones
+	fn build_prompt(request: &ChatRequest) -> String {
+		let system_prompt = request
+			.messages
+			.iter()
+			.filter(|message| message.role == "system")
+			.map(|message| message.content.as_str())
+			.collect::<Vec<&str>>()
+			.join("\n\n");
+
+		let user_prompt = request
+			.messages
+			.iter()
+			.filter(|message| message.role != "system")
+			.map(|message| message.content.as_str())
+			.collect::<Vec<&str>>()
+			.join("\n\n");
+
+		if system_prompt.is_empty() {
+			user_prompt
+		} else {
+			format!(
+				"{}\n\nПользовательский запрос: {}",
+				system_prompt, user_prompt
+			)
+		}
+	}
+
+	async fn run(
+		&self,
+		request: &ChatRequest,
+	) -> Result<ChatResponse, Box<dyn Error + Send + Sync>> {
+		let mut args = vec![
+			"-p".to_string(), // Use prompt flag for non-interactive mode
+			Self::build_prompt(request),
+		];
+
+		if self.model != QWEN_CLI_DEFAULT_MODEL {
+			args.push("--model".to_string());
+			args.push(self.model.clone());
+		}
+
+		let mut command = Command::new("npx");
+		command.arg("@qwen-code/qwen-code").args(&args);
+
+		let result = run_command(
+			command,
+			self.timeout,
+			self.max_output_bytes,
+			request.partial_output.as_ref(),
+		)
+		.await?;
+
+		// qwen-cli does not report token counts
+		let content = result.trim().to_string();
+		let usage = TokenUsage::estimate(&Self::build_prompt(request), &content);
+
+		Ok(ChatResponse {
+			content,
+			model: self.model.clone(),
+			usage: Some(usage),
+		})
+	}
+}
+
+/// Runs the CLI with piped output and returns its stdout.
+///
+/// The command runs in its own process group, which is killed on timeout, on cancellation
+/// and when stdout goes over `max_output_bytes`; in the last case the output is cut
+/// after its last complete line.
+async fn run_command(
+	mut command: Command,
+	limit: Duration,
+	max_output_bytes: usize,
+	partial_output: Option<&UnboundedSender<String>>,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+	command
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.kill_on_drop(true);
+
+	// npx starts node as a child, so the whole group has to be killed
+	#[cfg(unix)]
+	command.process_group(0);
+
+	// Execute the qwen-cli command
+	let mut child = command.spawn().map_err(|e| {
+		eprintln!("Failed to execute qwen-cli: {}", e);
+		Box::new(std::io::Error::new(
+			std::io::ErrorKind::Other,
+			format!("Failed to execute qwen-cli: {}", e),
+		)) as Box<dyn Error + Send + Sync>
+	})?;
+
+	// Kills the process group if the request is cancelled, times out or fails
+	let mut guard = ProcessGroupGuard { pgid: child.id() };
+
+	let stdout = child
+		.stdout
+		.take()
+		.ok_or("qwen-cli stdout is not captured")?;
+	let stderr = child
+		.stderr
+		.take()
+		.ok_or("qwen-cli stderr is not captured")?;
+
+	let stderr_task = tokio::spawn(async move {
+		let mut output = Vec::new();
+		let _ = read_bounded(stderr, &mut output, max_output_bytes, None).await;
+		String::from_utf8_lossy(&output).to_string()
+	});
+
+	let mut output = Vec::new();
+	let execution = async {
+		let reading = read_bounded(stdout, &mut output, max_output_bytes, partial_output);
+		tokio::pin!(reading);
+
+		tokio::select! {
+			result = &mut reading => {
+				if result? {
+					// The rest of the answer is not read, so nothing is left to wait for
+					guard.kill();
+					child.wait().await?;
+					return Ok::<_, Box<dyn Error + Send + Sync>>(None);
+				}
+				Ok(Some((child.wait().await?, true)))
+			}
+			status = child.wait() => {
+				// Descendants may keep the pipe open after qwen-cli itself exits
+				let drained = match timeout(QWEN_CLI_DRAIN_TIMEOUT, &mut reading).await {
+					Ok(result) => {
+						result?;
+						true
+					}
+					Err(_) => false,
+				};
+				Ok(Some((status?, drained)))
+			}
+		}
+	};
+
+	match timeout(limit, execution).await {
+		Ok(Ok(Some((status, drained)))) => {
+			// Leftover processes that still hold stdout are killed with the group
+			if drained {
+				guard.disarm();
+			}
+
+			if !status.success() {
+				let stderr = timeout(QWEN_CLI_DRAIN_TIMEOUT, stderr_task)
+					.await
+					.ok()
+					.and_then(|result| result.ok())
+					.filter(|stderr| !stderr.trim().is_empty())
+					.unwrap_or_else(|| "Unknown error".to_string());
+				eprintln!("qwen-cli command failed: {}", stderr);
+				return Err(Box::new(LlmError::Process {
+					provider: "qwen_cli".to_string(),
+					message: stderr,
+				}));
+			}
+		}
+		Ok(Ok(None)) => {
+			eprintln!(
+				"qwen-cli output exceeded {} bytes, keeping {} bytes",
+				max_output_bytes,
+				output.len()
+			);
+		}
+		Ok(Err(e)) => return Err(e),
+		Err(_) => {
+			eprintln!("qwen-cli timed out after {:?}", limit);
+			return Err(Box::new(LlmError::Timeout {
+				provider: "qwen_cli".to_string(),
+				seconds: limit.as_secs(),
+			}));
+		}
+	}
+
+	// Parse the output from qwen-cli
+	String::from_utf8(output).map_err(|e| {
+		eprintln!("Failed to parse qwen-cli output: {}", e);
+		Box::new(LlmError::invalid_response("qwen_cli", e)) as Box<dyn Error + Send + Sync>
+	})
+}
+
+/// Appends the stream to `output`, up to `max_bytes`, and sends the text read so far
+/// to `partial_output` after every complete line. Returns `true` if the stream was longer:
+/// `output` then ends with its last complete line, or at `max_bytes` if there is none.
+async fn read_bounded<R: AsyncRead + Unpin>(
+	mut reader: R,
+	output: &mut Vec<u8>,
+	max_bytes: usize,
+	partial_output: Option<&UnboundedSender<String>>,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	let mut chunk = [0u8; 4096];
+
+	loop {
+		let read = reader.read(&mut chunk).await?;
+		if read == 0 {
+			return Ok(false);
+		}
+
+		if output.len() + read > max_bytes {
+			output.extend_from_slice(&chunk[..max_bytes - output.len()]);
+			cut_at_line(output);
+			return Ok(true);
+		}
+		output.extend_from_slice(&chunk[..read]);
+
+		if let Some(sender) = partial_output {
+			if chunk[..read].contains(&b'\n') {
+				let _ = sender.send(String::from_utf8_lossy(output).trim().to_string());
+			}
+		}
+	}
+}
+
+/// Keeps whole lines, or whole characters when there is no line break at all
+fn cut_at_line(output: &mut Vec<u8>) {
+	let end = match output.iter().rposition(|byte| *byte == b'\n') {
+		Some(newline) => newline,
+		None => match std::str::from_utf8(output) {
+			Ok(_) => output.len(),
+			Err(e) => e.valid_up_to(),
+		},
+	};
+	output.truncate(end);
+}
+
+struct ProcessGroupGuard {
+	pgid: Option<u32>,
+}
+
+impl ProcessGroupGuard {
+	fn disarm(&mut self) {
+		self.pgid = None;
+	}
+
+	fn kill(&mut self) {
+		if let Some(pgid) = self.pgid.take() {
+			eprintln!("Killing qwen-cli process group {}", pgid);
+
+			#[cfg(unix)]
+			unsafe {
+				libc::killpg(pgid as libc::pid_t, libc::SIGKILL);
+			}
+		}
+	}
+}
+
+impl Drop for ProcessGroupGuard {
+	fn drop(&mut self) {
+		self.kill();
+	}
+}
+
+impl LlmProvider for QwenCliProvider {
+	fn name(&self) -> &str {
+		"qwen_cli"
+	}
+
+	fn model(&self) -> &str {
+		&self.model
+	}
+
+	fn chat<'a>(
+		&'a self,
+		request: &'a ChatRequest,
+	) -> BoxFuture<'a, Result<ChatResponse, Box<dyn Error + Send + Sync>>> {
+		Box::pin(self.run(request))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn shell(script: &str) -> Command {
+		let mut command = Command::new("sh");
+		command.arg("-c").arg(script);
+		command
+	}
+
+	/// Gone or a zombie waiting to be reaped
+	#[cfg(target_os = "linux")]
+	fn is_dead(pid: &str) -> bool {
+		match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+			Ok(stat) => stat
+				.rsplit_once(')')
+				.is_some_and(|(_, rest)| rest.trim_start().starts_with('Z')),
+			Err(_) => true,
+		}
+	}
+
+	#[tokio::test]
+	async fn returns_stdout_and_sends_partial_lines() {
+		let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+		let output = run_command(
+			shell("printf 'Первая строка\\n'; sleep 0.1; printf 'Вторая'"),
+			Duration::from_secs(5),
+			1024,
+			Some(&sender),
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(output, "Первая строка\nВторая");
+		assert_eq!(receiver.try_recv().unwrap(), "Первая строка");
+	}
+
+	#[tokio::test]
+	async fn failed_command_reports_stderr() {
+		let error = run_command(
+			shell("echo 'model not found' >&2; exit 3"),
+			Duration::from_secs(5),
+			1024,
+			None,
+		)
+		.await
+		.unwrap_err();
+
+		match error.downcast_ref::<LlmError>() {
+			Some(LlmError::Process { message, .. }) => {
+				assert_eq!(message.trim(), "model not found")
+			}
+			other => panic!("unexpected error: {:?}", other),
+		}
+	}
+
+	#[cfg(target_os = "linux")]
+	#[tokio::test]
+	async fn timeout_kills_the_process_group() {
+		let pid_file = std::env::temp_dir().join(format!("qwen_cli_{}", uuid::Uuid::new_v4()));
+
+		let error = run_command(
+			shell(&format!(
+				"sleep 30 & echo $! > {}; wait",
+				pid_file.display()
+			)),
+			Duration::from_millis(300),
+			1024,
+			None,
+		)
+		.await
+		.unwrap_err();
+
+		assert!(matches!(
+			error.downcast_ref::<LlmError>(),
+			Some(LlmError::Timeout { .. })
+		));
+		let pid = std::fs::read_to_string(&pid_file).unwrap();
+		let pid = pid.trim();
+		let mut dead = false;
+		for _ in 0..50 {
+			if is_dead(pid) {
+				dead = true;
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(20)).await;
+		}
+		assert!(dead, "background sleep {} survived the timeout", pid);
+		let _ = std::fs::remove_file(&pid_file);
+	}
+
+	#[tokio::test]
+	async fn long_output_is_cut_at_a_line_and_the_writer_killed() {
+		let started = tokio::time::Instant::now();
+
+		let output = run_command(
+			shell("while true; do echo 'ремонт окон'; done"),
+			Duration::from_secs(5),
+			100,
+			None,
+		)
+		.await
+		.unwrap();
+
+		assert!(started.elapsed() < Duration::from_secs(5));
+		assert!(output.len() <= 100);
+		// Every line that fits whole is kept, without the trailing line break
+		assert_eq!(output.lines().count(), 100 / "ремонт окон\n".len());
+		assert!(output.lines().all(|line| line == "ремонт окон"));
+	}
+
+	#[test]
+	fn cut_keeps_whole_lines_or_whole_chars() {
+		let mut output = "Ремонт\nокон".as_bytes().to_vec();
+		cut_at_line(&mut output);
+		assert_eq!(output, "Ремонт".as_bytes());
+
+		// No line break and a split Cyrillic letter at the end
+		let mut output = "Окна".as_bytes()[..5].to_vec();
+		cut_at_line(&mut output);
+		assert_eq!(output, "Ок".as_bytes());
+	}
+}
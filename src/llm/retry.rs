@@ -0,0 +1,492 @@
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::time::{sleep, Duration, Instant};
+use uuid::Uuid;
+
+use crate::llm::{env_for, ChatRequest, ChatResponse, LlmError, LlmProvider};
+
+pub const DEFAULT_RETRY_ON_STATUS: [u16; 6] = [408, 429, 500, 502, 503, 504];
+
+/// Retry policy of one processing type.
+///
+/// Configured with `LLM_RETRY_MAX_ATTEMPTS`, `LLM_RETRY_BASE_DELAY_MS`,
+/// `LLM_RETRY_MAX_DELAY_MS` and `LLM_RETRY_ON_STATUS` (with or without a type suffix).
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+	pub max_attempts: u32,
+	pub base_delay: Duration,
+	pub max_delay: Duration,
+	pub retry_on_status: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_attempts: 3,
+			base_delay: Duration::from_millis(500),
+			max_delay: Duration::from_secs(30),
+			retry_on_status: DEFAULT_RETRY_ON_STATUS.to_vec(),
+		}
+	}
+}
+
+impl RetryPolicy {
+	pub fn from_env(processing_type: &str) -> Self {
+		let default = Self::default();
+		let number = |key: &str| {
+			env_for(processing_type, key).and_then(|value| value.trim().parse::<u64>().ok())
+		};
+
+		Self {
+			max_attempts: number("RETRY_MAX_ATTEMPTS")
+				.map(|value| value.max(1) as u32)
+				.unwrap_or(default.max_attempts),
+			base_delay: number("RETRY_BASE_DELAY_MS")
+				.map(Duration::from_millis)
+				.unwrap_or(default.base_delay),
+			max_delay: number("RETRY_MAX_DELAY_MS")
+				.map(Duration::from_millis)
+				.unwrap_or(default.max_delay),
+			retry_on_status: env_for(processing_type, "RETRY_ON_STATUS")
+				.map(|value| {
+					value
+						.split(',')
+						.filter_map(|status| status.trim().parse::<u16>().ok())
+						.collect()
+				})
+				.unwrap_or(default.retry_on_status),
+		}
+	}
+
+	/// Network errors, timeouts and broken answers are always retried, HTTP statuses only if listed
+	pub fn is_retryable(&self, error: &(dyn Error + Send + Sync + 'static)) -> bool {
+		match error.downcast_ref::<LlmError>() {
+			Some(LlmError::Status { status, .. }) => self.retry_on_status.contains(status),
+			Some(LlmError::RetriesExhausted { .. }) | Some(LlmError::LanguageRejected { .. }) => {
+				false
+			}
+			Some(_) => true,
+			None => false,
+		}
+	}
+
+	/// Exponential backoff with jitter: half of the delay is fixed, half is random
+	pub fn delay(&self, attempt: u32) -> Duration {
+		let exponential = self
+			.base_delay
+			.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+			.min(self.max_delay);
+		let half = exponential / 2;
+		let jitter_ms = half.as_millis() as u64;
+		let random = (Uuid::new_v4().as_u128() as u64) % (jitter_ms + 1);
+
+		half + Duration::from_millis(random)
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CircuitState {
+	Closed { failures: u32 },
+	Open { until: Instant },
+	HalfOpen,
+}
+
+/// Circuit breaker of a provider: after `failure_threshold` failures in a row every request
+/// to the provider waits for `open_duration`, then a single trial request goes through.
+///
+/// Configured with `LLM_CIRCUIT_FAILURE_THRESHOLD` and `LLM_CIRCUIT_OPEN_SECS`.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+	name: String,
+	failure_threshold: u32,
+	open_duration: Duration,
+	state: Mutex<CircuitState>,
+}
+
+impl CircuitBreaker {
+	pub fn new(name: impl Into<String>, failure_threshold: u32, open_duration: Duration) -> Self {
+		Self {
+			name: name.into(),
+			failure_threshold: failure_threshold.max(1),
+			open_duration,
+			state: Mutex::new(CircuitState::Closed { failures: 0 }),
+		}
+	}
+
+	pub fn from_env(name: impl Into<String>, processing_type: &str) -> Self {
+		let number = |key: &str| {
+			env_for(processing_type, key).and_then(|value| value.trim().parse::<u64>().ok())
+		};
+
+		Self::new(
+			name,
+			number("CIRCUIT_FAILURE_THRESHOLD").unwrap_or(5) as u32,
+			Duration::from_secs(number("CIRCUIT_OPEN_SECS").unwrap_or(60)),
+		)
+	}
+
+	/// Waits until the provider may be called again, so batch processing pauses
+	/// instead of failing through the remaining items.
+	/// The outcome of the request is reported through `CircuitPermit`
+	pub async fn acquire(&self) -> CircuitPermit<'_> {
+		loop {
+			let wait = {
+				let mut state = self.state.lock().unwrap();
+				match *state {
+					CircuitState::Closed { .. } => return CircuitPermit::new(self, false),
+					CircuitState::Open { until } => {
+						let now = Instant::now();
+						if now >= until {
+							*state = CircuitState::HalfOpen;
+							println!("Circuit {} half-open, sending a trial request", self.name);
+							return CircuitPermit::new(self, true);
+						}
+						until - now
+					}
+					// Another request is probing the provider
+					CircuitState::HalfOpen => Duration::from_secs(1),
+				}
+			};
+
+			println!(
+				"⏸️ Circuit {} is open, pausing for {}s",
+				self.name,
+				wait.as_secs().max(1)
+			);
+			sleep(wait).await;
+		}
+	}
+
+	/// The trial request ended without an outcome: the next request tries again
+	fn release_trial(&self) {
+		let mut state = self.state.lock().unwrap();
+		if matches!(*state, CircuitState::HalfOpen) {
+			*state = CircuitState::Open {
+				until: Instant::now(),
+			};
+		}
+	}
+
+	pub fn record_success(&self) {
+		let mut state = self.state.lock().unwrap();
+		if !matches!(*state, CircuitState::Closed { failures: 0 }) {
+			println!("Circuit {} closed", self.name);
+		}
+		*state = CircuitState::Closed { failures: 0 };
+	}
+
+	pub fn record_failure(&self) {
+		let mut state = self.state.lock().unwrap();
+		let failures = match *state {
+			CircuitState::Closed { failures } => failures + 1,
+			_ => self.failure_threshold,
+		};
+
+		*state = if failures >= self.failure_threshold {
+			eprintln!(
+				"🔌 Circuit {} opened after {} failures for {:?}",
+				self.name, failures, self.open_duration
+			);
+			CircuitState::Open {
+				until: Instant::now() + self.open_duration,
+			}
+		} else {
+			CircuitState::Closed { failures }
+		};
+	}
+}
+
+/// Permission for one request through the breaker. Dropped without `success`/`failure`
+/// (e.g. when the task is cancelled), it frees the trial request of a half-open breaker
+pub struct CircuitPermit<'a> {
+	breaker: &'a CircuitBreaker,
+	trial: bool,
+	settled: bool,
+}
+
+impl<'a> CircuitPermit<'a> {
+	fn new(breaker: &'a CircuitBreaker, trial: bool) -> Self {
+		Self {
+			breaker,
+			trial,
+			settled: false,
+		}
+	}
+
+	pub fn success(mut self) {
+		self.settled = true;
+		self.breaker.record_success();
+	}
+
+	pub fn failure(mut self) {
+		self.settled = true;
+		self.breaker.record_failure();
+	}
+}
+
+impl Drop for CircuitPermit<'_> {
+	fn drop(&mut self) {
+		if self.trial && !self.settled {
+			self.breaker.release_trial();
+		}
+	}
+}
+
+static CIRCUIT_BREAKERS: OnceLock<Mutex<HashMap<String, Arc<CircuitBreaker>>>> = OnceLock::new();
+
+/// The breaker is shared by every request to one backend, even though the provider
+/// is created anew for each task
+pub fn circuit_breaker_for(key: &str, processing_type: &str) -> Arc<CircuitBreaker> {
+	CIRCUIT_BREAKERS
+		.get_or_init(|| Mutex::new(HashMap::new()))
+		.lock()
+		.unwrap()
+		.entry(key.to_string())
+		.or_insert_with(|| Arc::new(CircuitBreaker::from_env(key, processing_type)))
+		.clone()
+}
+
+/// Retries and a circuit breaker on top of any backend
+pub struct RetryingProvider {
+	inner: Arc<dyn LlmProvider>,
+	policy: RetryPolicy,
+	breaker: Arc<CircuitBreaker>,
+}
+
+impl RetryingProvider {
+	pub fn new(
+		inner: Arc<dyn LlmProvider>,
+		policy: RetryPolicy,
+		breaker: Arc<CircuitBreaker>,
+	) -> Self {
+		Self {
+			inner,
+			policy,
+			breaker,
+		}
+	}
+
+	async fn chat_with_retry(
+		&self,
+		request: &ChatRequest,
+	) -> Result<ChatResponse, Box<dyn Error + Send + Sync>> {
+		let mut attempt = 0;
+
+		loop {
+			attempt += 1;
+			let permit = self.breaker.acquire().await;
+
+			let error = match self.inner.chat(request).await {
+				Ok(response) if !response.content.trim().is_empty() => {
+					permit.success();
+					return Ok(response);
+				}
+				Ok(_) => Box::new(LlmError::invalid_response(
+					self.inner.name(),
+					"empty content",
+				)) as Box<dyn Error + Send + Sync>,
+				Err(e) => e,
+			};
+
+			// The backend answered, just not with something a retry would change
+			if !self.policy.is_retryable(error.as_ref()) {
+				permit.success();
+				return Err(error);
+			}
+			permit.failure();
+
+			if attempt >= self.policy.max_attempts {
+				return Err(Box::new(LlmError::RetriesExhausted {
+					provider: self.inner.name().to_string(),
+					attempts: attempt,
+					last_error: error.to_string(),
+				}));
+			}
+
+			let delay = self.policy.delay(attempt);
+			eprintln!(
+				"⚠️ {} attempt {}/{} failed: {}. Retrying in {:?}",
+				self.inner.name(),
+				attempt,
+				self.policy.max_attempts,
+				error,
+				delay
+			);
+			sleep(delay).await;
+		}
+	}
+}
+
+impl LlmProvider for RetryingProvider {
+	fn name(&self) -> &str {
+		self.inner.name()
+	}
+
+	fn model(&self) -> &str {
+		self.inner.model()
+	}
+
+	fn chat<'a>(
+		&'a self,
+		request: &'a ChatRequest,
+	) -> BoxFuture<'a, Result<ChatResponse, Box<dyn Error + Send + Sync>>> {
+		Box::pin(self.chat_with_retry(request))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::llm::{MockLlmServer, MockReply, OllamaProvider};
+	use tokio::time::timeout;
+
+	fn state(breaker: &CircuitBreaker) -> CircuitState {
+		*breaker.state.lock().unwrap()
+	}
+
+	async fn open_breaker(open_duration: Duration) -> CircuitBreaker {
+		let breaker = CircuitBreaker::new("test", 2, open_duration);
+		breaker.acquire().await.failure();
+		assert!(matches!(
+			state(&breaker),
+			CircuitState::Closed { failures: 1 }
+		));
+		breaker.acquire().await.failure();
+		assert!(matches!(state(&breaker), CircuitState::Open { .. }));
+		breaker
+	}
+
+	#[tokio::test]
+	async fn open_breaker_waits_then_closes_after_successful_trial() {
+		let breaker = open_breaker(Duration::from_millis(200)).await;
+
+		assert!(timeout(Duration::from_millis(50), breaker.acquire())
+			.await
+			.is_err());
+
+		let permit = breaker.acquire().await;
+		assert!(matches!(state(&breaker), CircuitState::HalfOpen));
+		permit.success();
+		assert!(matches!(
+			state(&breaker),
+			CircuitState::Closed { failures: 0 }
+		));
+	}
+
+	#[tokio::test]
+	async fn failed_trial_opens_breaker_again() {
+		let breaker = open_breaker(Duration::from_millis(50)).await;
+
+		breaker.acquire().await.failure();
+
+		assert!(matches!(state(&breaker), CircuitState::Open { .. }));
+	}
+
+	#[tokio::test]
+	async fn dropped_trial_lets_next_request_try() {
+		let breaker = open_breaker(Duration::from_millis(50)).await;
+
+		let trial = breaker.acquire().await;
+		drop(trial);
+
+		let permit = timeout(Duration::from_millis(100), breaker.acquire())
+			.await
+			.expect("breaker stuck in half-open");
+		assert!(matches!(state(&breaker), CircuitState::HalfOpen));
+		permit.success();
+	}
+
+	#[tokio::test]
+	async fn non_retryable_error_settles_trial() {
+		let server = MockLlmServer::start("127.0.0.1:0").await.unwrap();
+		server.push_reply(MockReply::server_error(400)).await;
+		let breaker = Arc::new(open_breaker(Duration::from_millis(50)).await);
+		let provider = RetryingProvider::new(
+			Arc::new(OllamaProvider::new(server.ollama_url(), "mock".to_string())),
+			RetryPolicy::default(),
+			breaker.clone(),
+		);
+		sleep(Duration::from_millis(60)).await;
+
+		let error = provider
+			.chat(&ChatRequest::new("system", "user"))
+			.await
+			.unwrap_err();
+		assert!(matches!(
+			error.downcast_ref::<LlmError>(),
+			Some(LlmError::Status { status: 400, .. })
+		));
+		assert!(matches!(
+			state(&breaker),
+			CircuitState::Closed { failures: 0 }
+		));
+
+		let response = timeout(
+			Duration::from_millis(500),
+			provider.chat(&ChatRequest::new("system", "user")),
+		)
+		.await
+		.expect("breaker stuck in half-open")
+		.unwrap();
+		assert_eq!(response.content, crate::llm::DEFAULT_MOCK_CONTENT);
+		server.shutdown();
+	}
+
+	#[tokio::test]
+	async fn retries_server_errors_then_succeeds() {
+		let server = MockLlmServer::start("127.0.0.1:0").await.unwrap();
+		server.push_reply(MockReply::server_error(503)).await;
+		server.push_reply(MockReply::malformed()).await;
+		server.push_reply(MockReply::text("Готово")).await;
+		let breaker = Arc::new(CircuitBreaker::new("test", 5, Duration::from_secs(60)));
+		let provider = RetryingProvider::new(
+			Arc::new(OllamaProvider::new(server.ollama_url(), "mock".to_string())),
+			RetryPolicy {
+				base_delay: Duration::from_millis(1),
+				..RetryPolicy::default()
+			},
+			breaker.clone(),
+		);
+
+		let response = provider
+			.chat(&ChatRequest::new("system", "user"))
+			.await
+			.unwrap();
+
+		assert_eq!(response.content, "Готово");
+		assert_eq!(server.requests().await.len(), 3);
+		assert!(matches!(
+			state(&breaker),
+			CircuitState::Closed { failures: 0 }
+		));
+		server.shutdown();
+	}
+
+	#[tokio::test]
+	async fn gives_up_after_max_attempts() {
+		let server = MockLlmServer::start("127.0.0.1:0").await.unwrap();
+		server.set_default_reply(MockReply::server_error(502)).await;
+		let provider = RetryingProvider::new(
+			Arc::new(OllamaProvider::new(server.ollama_url(), "mock".to_string())),
+			RetryPolicy {
+				max_attempts: 2,
+				base_delay: Duration::from_millis(1),
+				..RetryPolicy::default()
+			},
+			Arc::new(CircuitBreaker::new("test", 5, Duration::from_secs(60))),
+		);
+
+		let error = provider
+			.chat(&ChatRequest::new("system", "user"))
+			.await
+			.unwrap_err();
+
+		assert!(matches!(
+			error.downcast_ref::<LlmError>(),
+			Some(LlmError::RetriesExhausted { attempts: 2, .. })
+		));
+		server.shutdown();
+	}
+}
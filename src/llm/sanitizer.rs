@@ -0,0 +1,494 @@
+use crate::llm::{env_for, PromptVars};
+
+/// Preambles the model writes before the answer
+const PREAMBLE_LABELS: [&str; 10] = [
+	"ответ:",
+	"результат:",
+	"заголовок:",
+	"описание:",
+	"ключевые слова:",
+	"переписанный отзыв:",
+	"answer:",
+	"result:",
+	"title:",
+	"keywords:",
+];
+const PREAMBLE_OPENERS: [&str; 7] = [
+	"конечно",
+	"разумеется",
+	"с удовольствием",
+	"хорошо,",
+	"sure",
+	"certainly",
+	"of course",
+];
+/// Start a preamble only when a colon follows: "Вот переписанный текст:"
+const PREAMBLE_INTROS: [&str; 3] = ["вот ", "here is", "here's"];
+
+const ZERO_WIDTH_CHARS: [char; 7] = [
+	'\u{200b}', '\u{200c}', '\u{200d}', '\u{2060}', '\u{feff}', '\u{fe0e}', '\u{fe0f}',
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SanitizeStep {
+	RemoveZeroWidth,
+	UnescapeHtml,
+	StripMarkdown,
+	StripPreambles,
+	TrimQuotes,
+	/// Line breaks to spaces
+	SingleLine,
+	CollapseWhitespace,
+	Replace {
+		from: String,
+		to: String,
+	},
+	/// `XYZ` -> the value of a prompt variable, e.g. `firm_name`
+	Placeholder {
+		placeholder: String,
+		var: String,
+	},
+	/// At most this many characters, cut at a word boundary
+	MaxChars(usize),
+}
+
+impl SanitizeStep {
+	/// Step names for `LLM_SANITIZE_<TYPE>`:
+	/// `zero_width,html,markdown,preambles,quotes,single_line,whitespace,max_chars=N`
+	pub fn parse(name: &str) -> Option<Self> {
+		let name = name.trim().to_lowercase();
+		if let Some(limit) = name.strip_prefix("max_chars=") {
+			return limit.trim().parse::<usize>().ok().map(Self::MaxChars);
+		}
+
+		match name.as_str() {
+			"zero_width" => Some(Self::RemoveZeroWidth),
+			"html" => Some(Self::UnescapeHtml),
+			"markdown" => Some(Self::StripMarkdown),
+			"preambles" => Some(Self::StripPreambles),
+			"quotes" => Some(Self::TrimQuotes),
+			"single_line" => Some(Self::SingleLine),
+			"whitespace" => Some(Self::CollapseWhitespace),
+			_ => None,
+		}
+	}
+
+	pub fn apply(&self, text: &str, vars: &PromptVars) -> String {
+		match self {
+			Self::RemoveZeroWidth => text
+				.chars()
+				.filter(|c| !ZERO_WIDTH_CHARS.contains(c))
+				.collect(),
+			Self::UnescapeHtml => unescape_html(text),
+			Self::StripMarkdown => strip_markdown(text),
+			Self::StripPreambles => strip_preambles(text),
+			Self::TrimQuotes => text
+				.trim()
+				.trim_matches(|c| matches!(c, '"' | '\'' | '«' | '»' | '“' | '”'))
+				.to_string(),
+			Self::SingleLine => text.replace("\r\n", " ").replace(['\n', '\r'], " "),
+			Self::CollapseWhitespace => collapse_whitespace(text),
+			Self::Replace { from, to } => text.replace(from.as_str(), to),
+			Self::Placeholder { placeholder, var } => match vars.get(var) {
+				Some(value) => text.replace(placeholder.as_str(), value),
+				None => text.to_string(),
+			},
+			Self::MaxChars(limit) => truncate_words(text, *limit),
+		}
+	}
+}
+
+/// Cleanup chain for model answers; steps run in order
+#[derive(Debug, Clone, Default)]
+pub struct Sanitizer {
+	steps: Vec<SanitizeStep>,
+}
+
+impl Sanitizer {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn step(mut self, step: SanitizeStep) -> Self {
+		self.steps.push(step);
+		self
+	}
+
+	pub fn replace(self, from: &str, to: &str) -> Self {
+		self.step(SanitizeStep::Replace {
+			from: from.to_string(),
+			to: to.to_string(),
+		})
+	}
+
+	pub fn placeholder(self, placeholder: &str, var: &str) -> Self {
+		self.step(SanitizeStep::Placeholder {
+			placeholder: placeholder.to_string(),
+			var: var.to_string(),
+		})
+	}
+
+	pub fn steps(&self) -> &[SanitizeStep] {
+		&self.steps
+	}
+
+	pub fn apply(&self, text: &str, vars: &PromptVars) -> String {
+		self.steps
+			.iter()
+			.fold(text.to_string(), |text, step| step.apply(&text, vars))
+			.trim()
+			.to_string()
+	}
+
+	/// Cleanup of a processing type; `LLM_SANITIZE_<TYPE>` replaces the built-in steps
+	pub fn for_processing_type(processing_type: &str) -> Self {
+		if let Some(names) = env_for(processing_type, "SANITIZE") {
+			return names
+				.split(',')
+				.filter_map(SanitizeStep::parse)
+				.fold(Self::new(), Self::step);
+		}
+
+		let base = Self::new()
+			.step(SanitizeStep::RemoveZeroWidth)
+			.step(SanitizeStep::UnescapeHtml)
+			.step(SanitizeStep::StripPreambles);
+
+		match processing_type {
+			"seo_title" => base
+				.replace("`", "")
+				.replace("/", "-")
+				.replace("\"", "")
+				.replace("--", "-")
+				.step(SanitizeStep::SingleLine)
+				.step(SanitizeStep::CollapseWhitespace),
+			"title" | "ai_title" => base
+				.step(SanitizeStep::StripMarkdown)
+				.step(SanitizeStep::TrimQuotes)
+				.step(SanitizeStep::SingleLine)
+				.step(SanitizeStep::CollapseWhitespace),
+			"reviews" | "reviews_rewrite" => base
+				.step(SanitizeStep::StripMarkdown)
+				.placeholder("XYZ", "firm_name")
+				.step(SanitizeStep::CollapseWhitespace),
+			"keyword_extraction" => base
+				.step(SanitizeStep::TrimQuotes)
+				.replace("\r\n", ", ")
+				.replace("\n", ", ")
+				.step(SanitizeStep::CollapseWhitespace)
+				.replace(", ,", ",")
+				.replace(",,", ","),
+			_ => base
+				.step(SanitizeStep::StripMarkdown)
+				.step(SanitizeStep::CollapseWhitespace),
+		}
+	}
+}
+
+fn unescape_html(text: &str) -> String {
+	let mut result = String::with_capacity(text.len());
+	let mut rest = text;
+
+	while let Some(start) = rest.find('&') {
+		result.push_str(&rest[..start]);
+		let after = &rest[start..];
+
+		let decoded = after.find(';').filter(|end| *end <= 10).and_then(|end| {
+			let entity = &after[1..end];
+			let value = match entity {
+				"amp" => Some('&'),
+				"lt" => Some('<'),
+				"gt" => Some('>'),
+				"quot" => Some('"'),
+				"apos" => Some('\''),
+				"nbsp" => Some(' '),
+				"laquo" => Some('«'),
+				"raquo" => Some('»'),
+				"mdash" => Some('—'),
+				"ndash" => Some('–'),
+				_ => entity
+					.strip_prefix("#x")
+					.or_else(|| entity.strip_prefix("#X"))
+					.and_then(|hex| u32::from_str_radix(hex, 16).ok())
+					.or_else(|| {
+						entity
+							.strip_prefix('#')
+							.and_then(|dec| dec.parse::<u32>().ok())
+					})
+					.and_then(char::from_u32),
+			};
+			value.map(|value| (value, end))
+		});
+
+		match decoded {
+			Some((value, end)) => {
+				result.push(value);
+				rest = &after[end + 1..];
+			}
+			None => {
+				result.push('&');
+				rest = &after[1..];
+			}
+		}
+	}
+
+	result.push_str(rest);
+	result
+}
+
+fn strip_markdown(text: &str) -> String {
+	text.lines()
+		.filter(|line| {
+			let trimmed = line.trim();
+			// Horizontal rules
+			trimmed.is_empty() || !trimmed.chars().all(|c| matches!(c, '-' | '*' | '_'))
+		})
+		.map(|line| {
+			line.trim_start()
+				.trim_start_matches('#')
+				.trim_start_matches('>')
+				.replace("**", "")
+				.replace("__", "")
+				.replace(['*', '`'], "")
+		})
+		.collect::<Vec<String>>()
+		.join("\n")
+}
+
+/// Cuts `count` characters off the start (characters, not bytes: preambles are Cyrillic)
+fn skip_chars(text: &str, count: usize) -> &str {
+	match text.char_indices().nth(count) {
+		Some((index, _)) => &text[index..],
+		None => "",
+	}
+}
+
+fn strip_preambles(text: &str) -> String {
+	let mut text = text.trim().to_string();
+
+	loop {
+		let lower = text.to_lowercase();
+
+		let stripped = if let Some(label) = PREAMBLE_LABELS
+			.iter()
+			.find(|label| lower.starts_with(*label))
+		{
+			Some(skip_chars(&text, label.chars().count()).to_string())
+		} else if PREAMBLE_OPENERS
+			.iter()
+			.any(|opener| lower.starts_with(opener))
+		{
+			// The opener sentence ends at the first line break or punctuation
+			text.find(['\n', '.', '!', ':'])
+				.map(|end| text[end + 1..].to_string())
+		} else if PREAMBLE_INTROS.iter().any(|intro| lower.starts_with(intro)) {
+			let line_end = text.find('\n').unwrap_or(text.len());
+			text[..line_end]
+				.find(':')
+				.map(|end| text[end + 1..].to_string())
+		} else {
+			None
+		};
+
+		match stripped {
+			// Never strip the whole answer
+			Some(rest) if !rest.trim().is_empty() => text = rest.trim_start().to_string(),
+			_ => return text,
+		}
+	}
+}
+
+fn truncate_words(text: &str, limit: usize) -> String {
+	let end = match text.char_indices().nth(limit) {
+		Some((end, _)) => end,
+		None => return text.to_string(),
+	};
+
+	// A word cut in the middle is dropped, unless it is the only one
+	let cut = if text[end..].starts_with(char::is_whitespace) {
+		end
+	} else {
+		text[..end].rfind(char::is_whitespace).unwrap_or(end)
+	};
+
+	text[..cut].trim_end().to_string()
+}
+
+fn collapse_whitespace(text: &str) -> String {
+	let mut lines: Vec<String> = Vec::new();
+
+	for line in text.lines() {
+		let line = line.split_whitespace().collect::<Vec<&str>>().join(" ");
+		// Keep at most one empty line between paragraphs
+		if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+			continue;
+		}
+		lines.push(line);
+	}
+
+	lines.join("\n").trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn apply(step: SanitizeStep, text: &str) -> String {
+		step.apply(text, &PromptVars::new())
+	}
+
+	#[test]
+	fn removes_zero_width_chars() {
+		assert_eq!(
+			apply(SanitizeStep::RemoveZeroWidth, "Ре\u{200b}монт\u{feff}"),
+			"Ремонт"
+		);
+	}
+
+	#[test]
+	fn unescapes_html_entities() {
+		assert_eq!(
+			apply(
+				SanitizeStep::UnescapeHtml,
+				"&laquo;Окна&raquo; &amp; двери &#8212; &#x41;&nbsp;&unknown; &"
+			),
+			"«Окна» & двери — A &unknown; &"
+		);
+	}
+
+	#[test]
+	fn strips_markdown() {
+		assert_eq!(
+			apply(
+				SanitizeStep::StripMarkdown,
+				"## Заголовок\n---\n> **Жирный** и `код`\n* пункт"
+			),
+			" Заголовок\n Жирный и код\n пункт"
+		);
+	}
+
+	#[test]
+	fn strips_preambles() {
+		assert_eq!(
+			apply(SanitizeStep::StripPreambles, "Ответ: Ремонт окон"),
+			"Ремонт окон"
+		);
+		assert_eq!(
+			apply(
+				SanitizeStep::StripPreambles,
+				"Конечно, я могу помочь! Результат: Ремонт окон"
+			),
+			"Ремонт окон"
+		);
+		assert_eq!(
+			apply(
+				SanitizeStep::StripPreambles,
+				"Вот переписанный текст:\nОтличный сервис"
+			),
+			"Отличный сервис"
+		);
+		// "Вот" without a colon is part of the answer
+		assert_eq!(
+			apply(SanitizeStep::StripPreambles, "Вот это сервис"),
+			"Вот это сервис"
+		);
+		// An answer that is only a label stays as it is
+		assert_eq!(apply(SanitizeStep::StripPreambles, "Ответ:"), "Ответ:");
+	}
+
+	#[test]
+	fn trims_quotes_and_joins_lines() {
+		assert_eq!(
+			apply(SanitizeStep::TrimQuotes, " «Ремонт окон» "),
+			"Ремонт окон"
+		);
+		assert_eq!(
+			apply(SanitizeStep::SingleLine, "Ремонт\r\nокон\nв Москве"),
+			"Ремонт окон в Москве"
+		);
+	}
+
+	#[test]
+	fn collapses_whitespace() {
+		assert_eq!(
+			apply(
+				SanitizeStep::CollapseWhitespace,
+				"  Первый   абзац \n\n\n\nВторой\tабзац  "
+			),
+			"Первый абзац\n\nВторой абзац"
+		);
+	}
+
+	#[test]
+	fn replaces_text() {
+		let sanitizer = Sanitizer::new().replace("/", "-").replace("--", "-");
+
+		assert_eq!(
+			sanitizer.apply("Окна / двери", &PromptVars::new()),
+			"Окна - двери"
+		);
+	}
+
+	#[test]
+	fn substitutes_placeholders() {
+		let sanitizer = Sanitizer::new().placeholder("XYZ", "firm_name");
+		let mut vars = PromptVars::new();
+
+		assert_eq!(
+			sanitizer.apply("Спасибо XYZ!", &vars),
+			"Спасибо XYZ!",
+			"unknown variables leave the placeholder"
+		);
+
+		vars.insert("firm_name".to_string(), "Окна Плюс".to_string());
+		assert_eq!(
+			sanitizer.apply("Спасибо XYZ! XYZ лучшие", &vars),
+			"Спасибо Окна Плюс! Окна Плюс лучшие"
+		);
+	}
+
+	#[test]
+	fn truncates_at_word_boundary() {
+		assert_eq!(
+			apply(SanitizeStep::MaxChars(14), "Ремонт окон в Москве"),
+			"Ремонт окон в"
+		);
+		assert_eq!(
+			apply(SanitizeStep::MaxChars(11), "Ремонт окон в Москве"),
+			"Ремонт окон"
+		);
+		assert_eq!(
+			apply(SanitizeStep::MaxChars(3), "Ремонт"),
+			"Рем",
+			"a single word is cut"
+		);
+		assert_eq!(apply(SanitizeStep::MaxChars(50), "Ремонт"), "Ремонт");
+	}
+
+	#[test]
+	fn parses_step_names() {
+		assert_eq!(
+			SanitizeStep::parse(" Markdown "),
+			Some(SanitizeStep::StripMarkdown)
+		);
+		assert_eq!(
+			SanitizeStep::parse("max_chars=60"),
+			Some(SanitizeStep::MaxChars(60))
+		);
+		assert_eq!(SanitizeStep::parse("max_chars=x"), None);
+		assert_eq!(SanitizeStep::parse("unknown"), None);
+	}
+
+	#[test]
+	fn title_pipeline_cleans_typical_answer() {
+		let sanitizer = Sanitizer::for_processing_type("title");
+
+		assert_eq!(
+			sanitizer.apply(
+				"Конечно! Заголовок: **«Ремонт&nbsp;окон\nв Москве»**",
+				&PromptVars::new()
+			),
+			"Ремонт окон в Москве"
+		);
+	}
+}
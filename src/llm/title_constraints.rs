@@ -0,0 +1,538 @@
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use std::error::Error;
+use std::fmt;
+
+use crate::llm::{
+	chat_checked, env_for, ChatMessage, ChatRequest, LlmProvider, PromptVars, Sanitizer,
+	UsageContext,
+};
+
+/// Longer words count as caps, shorter ones as abbreviations (BMW, LED, ГБО)
+const MAX_ABBREVIATION_LEN: usize = 4;
+const MIN_PHONE_DIGITS: usize = 10;
+const MAX_PHONE_DIGITS: usize = 12;
+/// Digit groups of a number without the country code: (495) 123-45-67, 495 123 4567, 4012 12-34-56
+const PHONE_GROUPINGS: [&[usize]; 7] = [
+	&[3, 7],
+	&[3, 3, 4],
+	&[3, 3, 2, 2],
+	&[4, 6],
+	&[4, 2, 2, 2],
+	&[4, 3, 3],
+	&[5, 1, 2, 2],
+];
+/// Prepositions and conjunctions a title must not end on
+const DANGLING_WORDS: [&str; 28] = [
+	"и", "в", "во", "с", "со", "на", "для", "по", "от", "до", "из", "к", "ко", "о", "об", "а",
+	"но", "или", "за", "под", "при", "без", "and", "or", "for", "of", "with", "the",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TitleViolation {
+	TooLong { length: usize, max: usize },
+	ForbiddenChar(char),
+	AllCapsWord(String),
+	PhoneNumber(String),
+}
+
+impl TitleViolation {
+	/// Rule name for `constraints.violations` in the task result
+	pub fn rule(&self) -> &'static str {
+		match self {
+			Self::TooLong { .. } => "max_length",
+			Self::ForbiddenChar(_) => "forbidden_chars",
+			Self::AllCapsWord(_) => "all_caps",
+			Self::PhoneNumber(_) => "phone_number",
+		}
+	}
+}
+
+impl fmt::Display for TitleViolation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::TooLong { length, max } => {
+				write!(f, "длина {} символов, максимум {}", length, max)
+			}
+			Self::ForbiddenChar(c) => write!(f, "недопустимый символ '{}'", c),
+			Self::AllCapsWord(word) => write!(f, "слово капслоком '{}'", word),
+			Self::PhoneNumber(phone) => write!(f, "номер телефона '{}'", phone),
+		}
+	}
+}
+
+/// Title rules: Avito titles (`title`, `ai_title`) and firm SEO titles (`seo_title`).
+///
+/// Configured with `LLM_TITLE_MAX_LENGTH`, `LLM_TITLE_FORBIDDEN_CHARS`
+/// and `LLM_TITLE_REGENERATE` (with or without a type suffix).
+#[derive(Debug, Clone)]
+pub struct TitleRules {
+	pub max_length: usize,
+	pub forbidden_chars: String,
+	pub no_all_caps: bool,
+	pub no_phone_numbers: bool,
+	/// How many times the model is asked to fix the title before it is cut
+	pub regenerate_attempts: u32,
+}
+
+impl TitleRules {
+	pub fn for_target(processing_type: &str) -> Self {
+		let default = match processing_type {
+			"seo_title" => Self {
+				max_length: 100,
+				// `|` separates the firm name from the generated part
+				forbidden_chars: "\"`<>{}[]*#".to_string(),
+				no_all_caps: true,
+				no_phone_numbers: true,
+				regenerate_attempts: 1,
+			},
+			_ => Self {
+				max_length: 80,
+				forbidden_chars: "\"`<>{}[]*#|!@$%^=~".to_string(),
+				no_all_caps: true,
+				no_phone_numbers: true,
+				regenerate_attempts: 1,
+			},
+		};
+
+		Self {
+			max_length: env_for(processing_type, "TITLE_MAX_LENGTH")
+				.and_then(|value| value.trim().parse::<usize>().ok())
+				.unwrap_or(default.max_length),
+			forbidden_chars: env_for(processing_type, "TITLE_FORBIDDEN_CHARS")
+				.unwrap_or(default.forbidden_chars),
+			regenerate_attempts: env_for(processing_type, "TITLE_REGENERATE")
+				.and_then(|value| value.trim().parse::<u32>().ok())
+				.unwrap_or(default.regenerate_attempts),
+			..default
+		}
+	}
+
+	/// Length is checked on the whole title, the other rules on the generated part only:
+	/// the model does not write `prefix` (e.g. the firm name) and can't fix it
+	pub fn validate(&self, prefix: &str, generated: &str) -> Vec<TitleViolation> {
+		let mut violations = Vec::new();
+		let title = generated;
+
+		let length = prefix.chars().count() + generated.chars().count();
+		if length > self.max_length {
+			violations.push(TitleViolation::TooLong {
+				length,
+				max: self.max_length,
+			});
+		}
+		if let Some(c) = title.chars().find(|c| self.forbidden_chars.contains(*c)) {
+			violations.push(TitleViolation::ForbiddenChar(c));
+		}
+		if self.no_all_caps {
+			if let Some(word) = title.split_whitespace().find(|word| is_all_caps(word)) {
+				violations.push(TitleViolation::AllCapsWord(word.to_string()));
+			}
+		}
+		if self.no_phone_numbers {
+			if let Some(phone) = phone_numbers(title).into_iter().next() {
+				violations.push(TitleViolation::PhoneNumber(phone));
+			}
+		}
+
+		violations
+	}
+
+	/// Fixes the generated part of the title without the model: removes phones
+	/// and forbidden characters, lowers caps and cuts at a word boundary
+	/// to fit into `max_length` together with `prefix`. `prefix` itself is kept.
+	pub fn fix(&self, prefix: &str, generated: &str) -> String {
+		let mut title = generated.to_string();
+
+		if self.no_phone_numbers {
+			for phone in phone_numbers(&title) {
+				title = title.replace(&phone, " ");
+			}
+		}
+		title.retain(|c| !self.forbidden_chars.contains(c));
+		if self.no_all_caps {
+			title = title
+				.split_whitespace()
+				.map(|word| {
+					if is_all_caps(word) {
+						capitalize(word)
+					} else {
+						word.to_string()
+					}
+				})
+				.collect::<Vec<String>>()
+				.join(" ");
+		}
+
+		let limit = self.max_length.saturating_sub(prefix.chars().count());
+		let generated = truncate_at_word(&title, limit);
+		if generated.is_empty() {
+			return self.fit_prefix(prefix);
+		}
+
+		format!("{}{}", prefix, generated)
+	}
+
+	/// Title of `prefix` alone, when nothing fits after it: without the trailing
+	/// separator and cut at a word boundary to `max_length`
+	pub fn fit_prefix(&self, prefix: &str) -> String {
+		truncate_at_word(
+			prefix.trim_end_matches(|c: char| c.is_whitespace() || c == '|'),
+			self.max_length,
+		)
+	}
+}
+
+fn is_all_caps(word: &str) -> bool {
+	let letters: Vec<char> = word.chars().filter(|c| c.is_alphabetic()).collect();
+	letters.len() > MAX_ABBREVIATION_LEN && letters.iter().all(|c| c.is_uppercase())
+}
+
+fn capitalize(word: &str) -> String {
+	let lower = word.to_lowercase();
+	let mut chars = lower.chars();
+	match chars.next() {
+		Some(first) => first.to_uppercase().chain(chars).collect(),
+		None => String::new(),
+	}
+}
+
+/// Runs of digits, spaces, `+`, `-` and parentheses that look like a phone number
+fn phone_numbers(text: &str) -> Vec<String> {
+	let mut phones = Vec::new();
+	let mut current = String::new();
+
+	for c in text.chars().chain(std::iter::once('\n')) {
+		if c.is_ascii_digit() || matches!(c, '+' | '-' | '(' | ')' | ' ') {
+			current.push(c);
+			continue;
+		}
+		if is_phone(&current) {
+			phones.push(current.trim().to_string());
+		}
+		current.clear();
+	}
+
+	phones
+}
+
+/// A number with a country code (`+7 ...`, `8 800 ...`, `89161234567`) or 10 digits
+/// grouped like a number. Year ranges and part numbers don't qualify.
+fn is_phone(candidate: &str) -> bool {
+	let candidate = candidate.trim();
+	let digits = candidate.chars().filter(|c| c.is_ascii_digit()).count();
+	if !(MIN_PHONE_DIGITS..=MAX_PHONE_DIGITS).contains(&digits) {
+		return false;
+	}
+	if candidate.starts_with('+') {
+		return true;
+	}
+
+	let groups: Vec<&str> = candidate
+		.split(|c: char| !c.is_ascii_digit())
+		.filter(|group| !group.is_empty())
+		.collect();
+	let national = match groups.first() {
+		Some(first) if digits == 11 && matches!(*first, "7" | "8") => &groups[1..],
+		Some(first) if digits == 11 && groups.len() == 1 => {
+			return first.starts_with('7') || first.starts_with('8');
+		}
+		_ if digits == 10 => &groups[..],
+		_ => return false,
+	};
+
+	let lengths: Vec<usize> = national.iter().map(|group| group.len()).collect();
+	PHONE_GROUPINGS.contains(&lengths.as_slice())
+}
+
+/// Cuts to `max` characters at a word boundary and drops trailing separators
+pub fn truncate_at_word(text: &str, max: usize) -> String {
+	let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+	if text.chars().count() <= max {
+		return text;
+	}
+
+	let mut result = String::new();
+	for word in text.split(' ') {
+		let extra = if result.is_empty() { 0 } else { 1 };
+		if result.chars().count() + extra + word.chars().count() > max {
+			break;
+		}
+		if extra == 1 {
+			result.push(' ');
+		}
+		result.push_str(word);
+	}
+
+	// A single word longer than the limit is cut as is
+	if result.is_empty() {
+		result = text.chars().take(max).collect();
+	}
+
+	loop {
+		let trimmed = result
+			.trim_end_matches(|c: char| c.is_whitespace() || ",;:-–—|/(".contains(c))
+			.to_string();
+		match trimmed.rsplit_once(' ') {
+			Some((head, last)) if DANGLING_WORDS.contains(&last.to_lowercase().as_str()) => {
+				result = head.to_string();
+			}
+			_ => return trimmed,
+		}
+	}
+}
+
+/// Outcome of the title check; goes into the task result as `constraints`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TitleCheck {
+	pub title: String,
+	/// Rules broken by at least one attempt
+	pub violations: Vec<String>,
+	pub regenerations: u32,
+	/// The title was fixed without the model
+	pub fixed: bool,
+}
+
+/// Generates the title `prefix` + model answer and brings it in line with `TitleRules`.
+///
+/// On a violation the model is told what was wrong and answers again
+/// (`regenerate_attempts` times), after that the title is fixed with `TitleRules::fix`.
+#[allow(clippy::too_many_arguments)]
+pub async fn chat_title(
+	provider: &dyn LlmProvider,
+	request: &ChatRequest,
+	pool: &Pool<Postgres>,
+	context: &UsageContext,
+	sanitizer: &Sanitizer,
+	vars: &PromptVars,
+	rules: &TitleRules,
+	prefix: &str,
+) -> Result<TitleCheck, Box<dyn Error + Send + Sync>> {
+	let mut request = request.clone();
+	let mut check = TitleCheck::default();
+
+	// No answer can fit after such a prefix, so the model is not asked at all
+	let prefix_length = prefix.chars().count();
+	if prefix_length >= rules.max_length {
+		let violation = TitleViolation::TooLong {
+			length: prefix_length,
+			max: rules.max_length,
+		};
+		check.violations.push(violation.rule().to_string());
+		check.title = rules.fit_prefix(prefix);
+		check.fixed = true;
+		eprintln!(
+			"✂️ {} title prefix is too long ({}), truncated: {}",
+			context.processing_type, violation, check.title
+		);
+		return Ok(check);
+	}
+
+	loop {
+		let answer = chat_checked(provider, &request, pool, context, sanitizer, vars).await?;
+		let title = format!("{}{}", prefix, answer);
+		let violations = rules.validate(prefix, &answer);
+
+		for violation in &violations {
+			if !check.violations.iter().any(|rule| rule == violation.rule()) {
+				check.violations.push(violation.rule().to_string());
+			}
+		}
+
+		if violations.is_empty() {
+			check.title = title;
+			return Ok(check);
+		}
+
+		if check.regenerations >= rules.regenerate_attempts {
+			check.title = rules.fix(prefix, &answer);
+			check.fixed = true;
+			println!(
+				"✂️ {} title fixed ({}): {}",
+				context.processing_type,
+				check.violations.join(", "),
+				check.title
+			);
+			return Ok(check);
+		}
+
+		check.regenerations += 1;
+		let limit = rules
+			.max_length
+			.saturating_sub(prefix.chars().count())
+			.max(1);
+		let problems = violations
+			.iter()
+			.map(|violation| violation.to_string())
+			.collect::<Vec<String>>()
+			.join("; ");
+		request.messages.push(ChatMessage::assistant(answer));
+		request.messages.push(ChatMessage::user(format!(
+			"Заголовок не подходит: {}. Перепиши его: не длиннее {} символов, без телефонов, \
+			 без слов капслоком и без символов {}. Ответь только заголовком.",
+			problems, limit, rules.forbidden_chars
+		)));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rules(max_length: usize) -> TitleRules {
+		TitleRules {
+			max_length,
+			forbidden_chars: "\"#!".to_string(),
+			no_all_caps: true,
+			no_phone_numbers: true,
+			regenerate_attempts: 1,
+		}
+	}
+
+	#[test]
+	fn finds_phone_numbers() {
+		for phone in [
+			"+7 (495) 123-45-67",
+			"+79161234567",
+			"8 800 555-35-35",
+			"8(495)1234567",
+			"89161234567",
+			"(495) 123-45-67",
+			"495 123 4567",
+		] {
+			assert_eq!(
+				phone_numbers(&format!("Звоните {} сейчас", phone)),
+				vec![phone.to_string()],
+				"{}",
+				phone
+			);
+		}
+	}
+
+	#[test]
+	fn ignores_years_and_part_numbers() {
+		for text in [
+			"Toyota Camry 2019–2024",
+			"Toyota Camry 2019-2024",
+			"Модели 2015 2018 2021",
+			"Фильтр 1234567890",
+			"Фильтр 04152-YZZA1 (1234 5678 90)",
+			"Колодки 12345-67890",
+		] {
+			assert!(phone_numbers(text).is_empty(), "{}", text);
+			assert!(rules(80).validate("", text).is_empty(), "{}", text);
+		}
+	}
+
+	#[test]
+	fn validates_rules() {
+		assert_eq!(
+			rules(20).validate("", "РЕМОНТ окон #1, тел. 8 800 555-35-35"),
+			vec![
+				TitleViolation::TooLong {
+					length: 36,
+					max: 20
+				},
+				TitleViolation::ForbiddenChar('#'),
+				TitleViolation::AllCapsWord("РЕМОНТ".to_string()),
+				TitleViolation::PhoneNumber("8 800 555-35-35".to_string()),
+			]
+		);
+		assert!(rules(80).validate("", "Ремонт BMW и LED фар").is_empty());
+	}
+
+	#[test]
+	fn prefix_counts_only_for_length() {
+		let rules = rules(20);
+
+		// The firm name is not generated, so its quotes and caps are not violations
+		assert!(rules.validate("ООО \"ОКНА\" | ", "Ремонт").is_empty());
+		assert_eq!(
+			rules.validate("ООО \"ОКНА\" | ", "Ремонт окон"),
+			vec![TitleViolation::TooLong {
+				length: 24,
+				max: 20
+			}]
+		);
+		assert_eq!(
+			rules.validate("Окна | ", "\"СЕРВИС\""),
+			vec![
+				TitleViolation::ForbiddenChar('"'),
+				TitleViolation::AllCapsWord("\"СЕРВИС\"".to_string()),
+			]
+		);
+	}
+
+	#[test]
+	fn truncates_at_word_without_dangling_words() {
+		assert_eq!(
+			truncate_at_word("Ремонт окон и дверей в Москве", 16),
+			"Ремонт окон"
+		);
+		assert_eq!(truncate_at_word("Окна, двери - балконы", 13), "Окна, двери");
+		assert_eq!(truncate_at_word("Сверхдлинноеслово", 5), "Сверх");
+		assert_eq!(truncate_at_word("  Окна   двери ", 80), "Окна двери");
+	}
+
+	#[test]
+	fn fixes_only_generated_part() {
+		let rules = rules(40);
+		let prefix = "Окна Плюс | ";
+
+		let title = rules.fix(prefix, "ЛУЧШИЕ окна #1 в городе, звоните 8 800 555-35-35");
+
+		assert_eq!(title, "Окна Плюс | Лучшие окна 1 в городе");
+		let generated = title.strip_prefix(prefix).unwrap();
+		assert!(rules.validate(prefix, generated).is_empty());
+		assert_eq!(
+			rules.fix("", "Ремонт окон и дверей в Москве"),
+			"Ремонт окон и дверей в Москве"
+		);
+	}
+
+	#[test]
+	fn keeps_prefix_when_nothing_fits() {
+		assert_eq!(rules(10).fix("Окна Плюс | ", "Ремонт окон"), "Окна Плюс");
+	}
+
+	#[test]
+	fn truncates_prefix_longer_than_the_limit() {
+		let rules = rules(10);
+
+		assert_eq!(rules.fix("Окна Плюс Сервис | ", "Ремонт"), "Окна Плюс");
+		assert_eq!(rules.fit_prefix("Окнаплюссервис | "), "Окнаплюссе");
+	}
+
+	#[tokio::test]
+	async fn long_prefix_is_truncated_without_asking_the_model() {
+		use crate::llm::{MockLlmServer, OllamaProvider};
+		use sqlx::postgres::PgPoolOptions;
+		use std::time::Duration;
+
+		let server = MockLlmServer::start("127.0.0.1:0").await.unwrap();
+		let provider = OllamaProvider::new(server.ollama_url(), "mock".to_string());
+		let pool = PgPoolOptions::new()
+			.acquire_timeout(Duration::from_millis(100))
+			.connect_lazy("postgres://postgres@127.0.0.1:1/none")
+			.unwrap();
+
+		let check = chat_title(
+			&provider,
+			&ChatRequest::new("system", "Напиши заголовок"),
+			&pool,
+			&UsageContext::default(),
+			&Sanitizer::new(),
+			&PromptVars::new(),
+			&rules(10),
+			"Окна Плюс Сервис | ",
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(check.title, "Окна Плюс");
+		assert_eq!(check.violations, vec!["max_length"]);
+		assert!(check.fixed);
+		assert!(server.requests().await.is_empty());
+		server.shutdown();
+	}
+}
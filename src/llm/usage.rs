@@ -0,0 +1,294 @@
+use sqlx::{Pool, Postgres};
+use std::env;
+use std::error::Error;
+use std::time::Instant;
+use uuid::Uuid;
+
+use crate::llm::{cache_bypass, ChatRequest, ChatResponse, LlmProvider, ResponseCache, TokenUsage};
+use crate::models::rabbitmq::AIProcessingTask;
+use crate::models::{LlmUsage, LlmUsageTotals, SaveLlmUsage};
+
+/// What a model call belongs to: a queued task, a firm in batch processing, or both
+#[derive(Debug, Clone, Default)]
+pub struct UsageContext {
+	pub processing_type: String,
+	pub task_id: Option<Uuid>,
+	pub firm_id: Option<Uuid>,
+	pub user_id: Option<Uuid>,
+	/// Don't read the answer from the cache (`cache_bypass` in the task parameters)
+	pub cache_bypass: bool,
+}
+
+impl UsageContext {
+	pub fn for_task(task: &AIProcessingTask) -> Self {
+		Self {
+			processing_type: task.request_data.processing_type.clone(),
+			task_id: Some(task.task_id),
+			firm_id: None,
+			user_id: Some(task.request_data.user_id),
+			cache_bypass: cache_bypass(&task.request_data.parameters),
+		}
+	}
+
+	pub fn for_firm(processing_type: &str, firm_id: Uuid) -> Self {
+		Self {
+			processing_type: processing_type.to_string(),
+			firm_id: Some(firm_id),
+			..Self::default()
+		}
+	}
+}
+
+/// Model price in USD per million tokens
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModelPrice {
+	pub prompt: f64,
+	pub completion: f64,
+}
+
+impl ModelPrice {
+	/// `LLM_PRICES="gpt-4o-mini=0.15/0.6;gpt-4o=2.5/10"`; local models are free
+	pub fn for_model(model: &str) -> Self {
+		env::var("LLM_PRICES")
+			.ok()
+			.and_then(|prices| Self::configured(&prices, model))
+			.unwrap_or_else(|| Self::builtin(model))
+	}
+
+	/// Model price from an `LLM_PRICES` string; malformed entries are skipped
+	fn configured(prices: &str, model: &str) -> Option<Self> {
+		prices.split(';').find_map(|entry| {
+			let (name, price) = entry.split_once('=')?;
+			if name.trim() != model {
+				return None;
+			}
+			let (prompt, completion) = price.split_once('/')?;
+			Some(Self {
+				prompt: prompt.trim().parse().ok()?,
+				completion: completion.trim().parse().ok()?,
+			})
+		})
+	}
+
+	fn builtin(model: &str) -> Self {
+		match model {
+			"gpt-4o-mini" => Self {
+				prompt: 0.15,
+				completion: 0.6,
+			},
+			"gpt-4o" => Self {
+				prompt: 2.5,
+				completion: 10.0,
+			},
+			_ => Self::default(),
+		}
+	}
+
+	pub fn cost(&self, usage: &TokenUsage) -> f64 {
+		(usage.prompt_tokens as f64 * self.prompt
+			+ usage.completion_tokens as f64 * self.completion)
+			/ 1_000_000.0
+	}
+}
+
+/// Totals of all calls of a task, sent to the client in `result_data.usage`
+pub fn usage_totals(rows: &[LlmUsage]) -> LlmUsageTotals {
+	rows.iter()
+		.fold(LlmUsageTotals::default(), |mut totals, row| {
+			totals.calls += 1;
+			totals.prompt_tokens += row.prompt_tokens as i64;
+			totals.completion_tokens += row.completion_tokens as i64;
+			totals.total_tokens += row.total_tokens as i64;
+			totals.duration_ms += row.duration_ms;
+			totals.estimated_cost += row.estimated_cost;
+			totals
+		})
+}
+
+/// A model answer that is not cached yet: `accept` caches it once the caller
+/// has checked it, so a rejected answer never comes back from the cache
+pub struct UsageResponse<'a> {
+	pub response: ChatResponse,
+	provider: &'a dyn LlmProvider,
+	/// Cache and key for a new answer; `None` for a cached answer or without a cache
+	cache: Option<(ResponseCache, String)>,
+}
+
+impl UsageResponse<'_> {
+	pub async fn accept(self) -> ChatResponse {
+		// A bypassed request still refreshes the cached answer
+		if let Some((cache, key)) = self.cache {
+			cache.put(&key, self.provider, &self.response).await;
+		}
+		self.response
+	}
+}
+
+/// Calls the model and records model, tokens, duration and cost in `llm_usage`.
+///
+/// With `ResponseCache` enabled, a repeated request is served from the cache and not counted again;
+/// a new answer is cached only after `UsageResponse::accept`.
+/// A failed write is only logged: accounting must not break processing.
+pub async fn chat_with_usage<'a>(
+	provider: &'a dyn LlmProvider,
+	request: &ChatRequest,
+	pool: &Pool<Postgres>,
+	context: &UsageContext,
+) -> Result<UsageResponse<'a>, Box<dyn Error + Send + Sync>> {
+	chat_with_cache(
+		provider,
+		request,
+		pool,
+		context,
+		ResponseCache::from_env(pool),
+	)
+	.await
+}
+
+/// `chat_with_usage` with the given cache instead of `LLM_CACHE`; `None` disables caching
+pub async fn chat_with_cache<'a>(
+	provider: &'a dyn LlmProvider,
+	request: &ChatRequest,
+	pool: &Pool<Postgres>,
+	context: &UsageContext,
+	cache: Option<ResponseCache>,
+) -> Result<UsageResponse<'a>, Box<dyn Error + Send + Sync>> {
+	let cache_key = ResponseCache::key(provider, request);
+
+	if let Some(ref cache) = cache {
+		if !context.cache_bypass {
+			if let Some(response) = cache.get(&cache_key).await {
+				println!("LLM cache hit: {}", cache_key);
+				return Ok(UsageResponse {
+					response,
+					provider,
+					cache: None,
+				});
+			}
+		}
+	}
+
+	let started = Instant::now();
+	let response = provider.chat(request).await?;
+	let duration_ms = started.elapsed().as_millis() as i64;
+
+	let usage = response.usage.unwrap_or_else(|| {
+		let prompt = request
+			.messages
+			.iter()
+			.map(|message| message.content.as_str())
+			.collect::<Vec<&str>>()
+			.join("\n");
+		TokenUsage::estimate(&prompt, &response.content)
+	});
+	let model = if response.model.is_empty() {
+		provider.model().to_string()
+	} else {
+		response.model.clone()
+	};
+
+	let _ = LlmUsage::add_usage(
+		pool,
+		SaveLlmUsage {
+			task_id: context.task_id,
+			firm_id: context.firm_id,
+			user_id: context.user_id,
+			processing_type: context.processing_type.clone(),
+			provider: provider.name().to_string(),
+			estimated_cost: ModelPrice::for_model(&model).cost(&usage),
+			model,
+			prompt_tokens: usage.prompt_tokens as i32,
+			completion_tokens: usage.completion_tokens as i32,
+			usage_estimated: usage.estimated,
+			duration_ms,
+		},
+	)
+	.await;
+
+	Ok(UsageResponse {
+		response,
+		provider,
+		cache: cache.map(|cache| (cache, cache_key)),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn usage_row(model: &str, prompt_tokens: i32, completion_tokens: i32) -> LlmUsage {
+		let usage = TokenUsage::new(prompt_tokens as u32, completion_tokens as u32);
+		LlmUsage {
+			llm_usage_id: Uuid::new_v4(),
+			task_id: None,
+			firm_id: None,
+			user_id: None,
+			processing_type: "title".to_string(),
+			provider: "openai".to_string(),
+			model: model.to_string(),
+			prompt_tokens,
+			completion_tokens,
+			total_tokens: prompt_tokens + completion_tokens,
+			usage_estimated: false,
+			duration_ms: 250,
+			estimated_cost: ModelPrice::builtin(model).cost(&usage),
+			created_ts: None,
+		}
+	}
+
+	#[test]
+	fn cost_is_per_million_tokens() {
+		let price = ModelPrice {
+			prompt: 2.5,
+			completion: 10.0,
+		};
+		let usage = TokenUsage::new(2_000, 500);
+
+		assert!((price.cost(&usage) - 0.01).abs() < 1e-12);
+		assert_eq!(ModelPrice::builtin("llama3").cost(&usage), 0.0);
+	}
+
+	#[test]
+	fn configured_price_overrides_builtin() {
+		let prices = "gpt-4o=oops; gpt-4o-mini = 0.3/1.2 ;qwen=0/0";
+
+		let mini = ModelPrice::configured(prices, "gpt-4o-mini").unwrap();
+		assert_eq!((mini.prompt, mini.completion), (0.3, 1.2));
+		// A malformed entry is skipped, so the built-in price applies
+		assert!(ModelPrice::configured(prices, "gpt-4o").is_none());
+		assert!(ModelPrice::configured(prices, "llama3").is_none());
+		let builtin = ModelPrice::builtin("gpt-4o");
+		assert_eq!((builtin.prompt, builtin.completion), (2.5, 10.0));
+	}
+
+	#[test]
+	fn totals_sum_every_call() {
+		let rows = vec![
+			usage_row("gpt-4o-mini", 1_000_000, 0),
+			usage_row("gpt-4o-mini", 0, 1_000_000),
+			usage_row("llama3", 300, 200),
+		];
+
+		let totals = usage_totals(&rows);
+
+		assert_eq!(totals.calls, 3);
+		assert_eq!(totals.prompt_tokens, 1_000_300);
+		assert_eq!(totals.completion_tokens, 1_000_200);
+		assert_eq!(totals.total_tokens, 2_000_500);
+		assert_eq!(totals.duration_ms, 750);
+		assert!((totals.estimated_cost - 0.75).abs() < 1e-9);
+
+		let usage = serde_json::json!(totals);
+		assert_eq!(usage["calls"], 3);
+		assert_eq!(usage["total_tokens"], 2_000_500);
+	}
+
+	#[test]
+	fn totals_without_calls_are_zero() {
+		let totals = usage_totals(&[]);
+
+		assert_eq!(totals.calls, 0);
+		assert_eq!(totals.total_tokens, 0);
+		assert_eq!(totals.estimated_cost, 0.0);
+	}
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::rabbitmq::ResultMessage;
+
+pub const AI_RESULT_KIND_RESULT: &str = "result";
+pub const AI_RESULT_KIND_PROGRESS: &str = "progress";
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct AiResult {
+	pub result_id: i64,
+	/// `result` or `progress`
+	pub kind: String,
+	pub task_id: Uuid,
+	pub user_id: Uuid,
+	pub request_id: Option<Uuid>,
+	pub status: String,
+	/// JSON of the message as it was received
+	pub message: String,
+	/// `completed_at` of a result, `timestamp` of a progress update
+	pub sent_at: String,
+	pub received_ts: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SaveAiResult {
+	pub kind: String,
+	pub task_id: Uuid,
+	pub user_id: Uuid,
+	pub request_id: Option<Uuid>,
+	pub status: String,
+	pub message: serde_json::Value,
+	pub sent_at: String,
+}
+
+/// A stored message still to be forwarded to one sink
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct AiResultDelivery {
+	pub result_id: i64,
+	pub sink: String,
+	pub attempts: i32,
+	pub message: String,
+}
+
+impl From<&ResultMessage> for SaveAiResult {
+	fn from(message: &ResultMessage) -> Self {
+		let (kind, request_id, sent_at) = match message {
+			ResultMessage::Result(result) => (
+				AI_RESULT_KIND_RESULT,
+				result.request_id,
+				result.completed_at.clone(),
+			),
+			ResultMessage::Progress(progress) => (
+				AI_RESULT_KIND_PROGRESS,
+				progress.request_id,
+				progress.timestamp.clone(),
+			),
+		};
+
+		Self {
+			kind: kind.to_string(),
+			task_id: message.task_id(),
+			user_id: message.user_id(),
+			request_id,
+			status: message.status().to_string(),
+			message: serde_json::to_value(message).unwrap_or_default(),
+			sent_at,
+		}
+	}
+}
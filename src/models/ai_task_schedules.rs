@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct AiTaskSchedule {
+	/// `task_id` of the task that created the schedule
+	pub schedule_id: Uuid,
+	pub request_id: Uuid,
+	pub user_id: Uuid,
+	pub processing_type: String,
+	/// JSON of `request_data.parameters` for every run
+	pub parameters: String,
+	/// Repeats the task; a schedule without `cron` runs once
+	pub cron: Option<String>,
+	/// Empty once a one-off schedule has run
+	pub next_run_ts: Option<chrono::DateTime<chrono::Utc>>,
+	pub paused: bool,
+	pub runs: i32,
+	pub last_run_ts: Option<chrono::DateTime<chrono::Utc>>,
+	pub last_task_id: Option<Uuid>,
+	pub created_ts: Option<chrono::DateTime<chrono::Utc>>,
+	pub updated_ts: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SaveAiTaskSchedule {
+	pub schedule_id: Uuid,
+	pub request_id: Uuid,
+	pub user_id: Uuid,
+	pub processing_type: String,
+	pub parameters: serde_json::Value,
+	pub cron: Option<String>,
+	pub next_run_ts: chrono::DateTime<chrono::Utc>,
+}
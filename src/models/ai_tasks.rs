@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const AI_TASK_IN_PROGRESS: &str = "in_progress";
+pub const AI_TASK_COMPLETED: &str = "completed";
+pub const AI_TASK_FAILED: &str = "failed";
+pub const AI_TASK_CANCELLED: &str = "cancelled";
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct AiTask {
+	pub task_id: Uuid,
+	pub request_id: Option<Uuid>,
+	pub user_id: Uuid,
+	pub processing_type: String,
+	pub status: String,
+	pub attempts: i32,
+	/// JSON of the final result, as sent to the backend
+	pub result_data: Option<String>,
+	pub error_message: Option<String>,
+	pub created_ts: Option<chrono::DateTime<chrono::Utc>>,
+	pub started_ts: Option<chrono::DateTime<chrono::Utc>>,
+	pub finished_ts: Option<chrono::DateTime<chrono::Utc>>,
+	pub updated_ts: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl AiTask {
+	/// The result was sent once, so a redelivered message must not run the task again
+	pub fn is_finished(&self) -> bool {
+		self.status == AI_TASK_COMPLETED
+			|| self.status == AI_TASK_FAILED
+			|| self.status == AI_TASK_CANCELLED
+	}
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SaveAiTask {
+	pub task_id: Uuid,
+	pub request_id: Option<Uuid>,
+	pub user_id: Uuid,
+	pub processing_type: String,
+}
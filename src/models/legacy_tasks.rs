@@ -0,0 +1,119 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::error::Error;
+use uuid::Uuid;
+
+use crate::models::rabbitmq::{AIProcessingTask, AIRequestData};
+
+/// Title task published before `request_data` existed
+#[derive(Debug, Deserialize)]
+pub struct LegacyTitleTaskFormat {
+	pub task_id: Uuid,
+	pub user_id: Uuid,
+	pub title: String,
+	pub category: String,
+	pub created_ts: DateTime<Utc>,
+}
+
+/// Description task published before `request_data` existed
+#[derive(Debug, Deserialize)]
+pub struct LegacyDescriptionTaskFormat {
+	pub task_id: Uuid,
+	pub user_id: Uuid,
+	pub description: String,
+	pub category: String,
+	pub created_ts: DateTime<Utc>,
+}
+
+impl From<LegacyTitleTaskFormat> for AIProcessingTask {
+	fn from(legacy: LegacyTitleTaskFormat) -> Self {
+		Self {
+			schema_version: 1,
+			task_id: legacy.task_id,
+			request_data: AIRequestData {
+				request_id: legacy.task_id, // Use task_id as request_id for compatibility
+				user_id: legacy.user_id,
+				processing_type: "title".to_string(),
+				parameters: serde_json::json!({
+					"title": legacy.title,
+					"category": legacy.category,
+					"created_ts": legacy.created_ts
+				}),
+			},
+			created_at: legacy.created_ts.to_rfc3339(),
+			run_at: None,
+			cron: None,
+		}
+	}
+}
+
+impl From<LegacyDescriptionTaskFormat> for AIProcessingTask {
+	fn from(legacy: LegacyDescriptionTaskFormat) -> Self {
+		Self {
+			schema_version: 1,
+			task_id: legacy.task_id,
+			request_data: AIRequestData {
+				request_id: legacy.task_id, // Use task_id as request_id for compatibility
+				user_id: legacy.user_id,
+				processing_type: "description".to_string(),
+				parameters: serde_json::json!({
+					"description": legacy.description,
+					"category": legacy.category,
+					"created_ts": legacy.created_ts
+				}),
+			},
+			created_at: legacy.created_ts.to_rfc3339(),
+			run_at: None,
+			cron: None,
+		}
+	}
+}
+
+impl AIProcessingTask {
+	/// Reads a task message, picking the format by its fields instead of trying each one:
+	/// `request_data` is the current format, a top-level `title` or `description`
+	/// one of the legacy formats
+	pub fn from_message(data: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let message_str =
+			std::str::from_utf8(data).map_err(|e| -> Box<dyn Error + Send + Sync> {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("UTF8 error: {}", e),
+				))
+			})?;
+		let value: Value =
+			serde_json::from_str(message_str).map_err(|e| -> Box<dyn Error + Send + Sync> {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Parse error: {}", e),
+				))
+			})?;
+
+		let (format, task) = if value.get("request_data").is_some() {
+			("task", serde_json::from_value::<AIProcessingTask>(value))
+		} else if value.get("title").is_some() {
+			(
+				"legacy title task",
+				serde_json::from_value::<LegacyTitleTaskFormat>(value).map(Self::from),
+			)
+		} else if value.get("description").is_some() {
+			(
+				"legacy description task",
+				serde_json::from_value::<LegacyDescriptionTaskFormat>(value).map(Self::from),
+			)
+		} else {
+			return Err(Box::new(std::io::Error::new(
+				std::io::ErrorKind::Other,
+				"Unknown task format: expected request_data, or title / description of a legacy task",
+			)));
+		};
+
+		task.map_err(|e| -> Box<dyn Error + Send + Sync> {
+			Box::new(std::io::Error::new(
+				std::io::ErrorKind::Other,
+				format!("Invalid {}: {}", format, e),
+			))
+		})
+	}
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct LlmCacheEntry {
+	pub cache_key: String,
+	pub provider: String,
+	pub model: String,
+	pub content: String,
+	pub created_ts: Option<chrono::DateTime<chrono::Utc>>,
+	pub expires_ts: chrono::DateTime<chrono::Utc>,
+}
@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct LlmRejection {
+	pub llm_rejection_id: Uuid,
+	pub task_id: Option<Uuid>,
+	pub firm_id: Option<Uuid>,
+	pub user_id: Option<Uuid>,
+	pub processing_type: String,
+	pub provider: String,
+	pub model: String,
+	pub reason: String,
+	pub content: String,
+	pub cyrillic_ratio: f64,
+	pub latin_ratio: f64,
+	pub cjk_ratio: f64,
+	pub attempt: i32,
+	pub created_ts: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SaveLlmRejection {
+	pub task_id: Option<Uuid>,
+	pub firm_id: Option<Uuid>,
+	pub user_id: Option<Uuid>,
+	pub processing_type: String,
+	pub provider: String,
+	pub model: String,
+	pub reason: String,
+	pub content: String,
+	pub cyrillic_ratio: f64,
+	pub latin_ratio: f64,
+	pub cjk_ratio: f64,
+	pub attempt: i32,
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct LlmUsage {
+	pub llm_usage_id: Uuid,
+	pub task_id: Option<Uuid>,
+	pub firm_id: Option<Uuid>,
+	pub user_id: Option<Uuid>,
+	pub processing_type: String,
+	pub provider: String,
+	pub model: String,
+	pub prompt_tokens: i32,
+	pub completion_tokens: i32,
+	pub total_tokens: i32,
+	pub usage_estimated: bool,
+	pub duration_ms: i64,
+	pub estimated_cost: f64,
+	pub created_ts: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SaveLlmUsage {
+	pub task_id: Option<Uuid>,
+	pub firm_id: Option<Uuid>,
+	pub user_id: Option<Uuid>,
+	pub processing_type: String,
+	pub provider: String,
+	pub model: String,
+	pub prompt_tokens: i32,
+	pub completion_tokens: i32,
+	pub usage_estimated: bool,
+	pub duration_ms: i64,
+	pub estimated_cost: f64,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone, Default)]
+pub struct LlmUsageTotals {
+	pub calls: i64,
+	pub prompt_tokens: i64,
+	pub completion_tokens: i64,
+	pub total_tokens: i64,
+	pub duration_ms: i64,
+	pub estimated_cost: f64,
+}
@@ -1,23 +1,37 @@
 pub mod ai_description;
+pub mod ai_results;
 pub mod ai_review;
+pub mod ai_task_schedules;
+pub mod ai_tasks;
 pub mod bestlight_cases;
 pub mod categories;
 pub mod cities;
 pub mod count;
 pub mod counter;
 pub mod firm;
+pub mod legacy_tasks;
+pub mod llm_cache;
+pub mod llm_rejections;
+pub mod llm_usage;
 pub mod pages;
 pub mod rabbitmq;
 pub mod review;
 
 pub use self::ai_description::*;
+pub use self::ai_results::*;
 pub use self::ai_review::*;
+pub use self::ai_task_schedules::*;
+pub use self::ai_tasks::*;
 pub use self::bestlight_cases::*;
 pub use self::categories::*;
 pub use self::cities::*;
 pub use self::count::*;
 pub use self::counter::*;
 pub use self::firm::*;
+pub use self::legacy_tasks::*;
+pub use self::llm_cache::*;
+pub use self::llm_rejections::*;
+pub use self::llm_usage::*;
 pub use self::pages::*;
 pub use self::rabbitmq::*;
 pub use self::review::*;
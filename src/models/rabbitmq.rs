@@ -1,8 +1,19 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::error::Error;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::services::topology::Topology;
+
+/// Version of the task, result and progress messages this service reads and writes.
+/// Messages without `schema_version` were published before it existed and are version 1.
+pub const MESSAGE_SCHEMA_VERSION: u32 = 1;
+
+fn unversioned_message() -> u32 {
+	1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AIRequestData {
 	pub request_id: Uuid,
 	pub user_id: Uuid,
@@ -10,15 +21,33 @@ pub struct AIRequestData {
 	pub parameters: serde_json::Value, // Additional parameters for the specific processing
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AIProcessingTask {
+	#[serde(default = "unversioned_message")]
+	pub schema_version: u32,
 	pub task_id: Uuid,
 	pub request_data: AIRequestData,
 	pub created_at: String,
+	/// Delays the task; the consumer stores it in `ai_task_schedules` until then
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub run_at: Option<DateTime<Utc>>,
+	/// Repeats the task on a cron expression (UTC), starting from `run_at` if given
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cron: Option<String>,
+}
+
+/// Published with the `task.cancel` routing key; cancels one task or every task of a batch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AITaskCancel {
+	pub task_id: Option<Uuid>,
+	pub batch_id: Option<String>,
+	pub reason: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AIProcessingResult {
+	#[serde(default = "unversioned_message")]
+	pub schema_version: u32,
 	pub task_id: Uuid,
 	pub user_id: Uuid,
 	pub request_id: Option<Uuid>,
@@ -30,6 +59,8 @@ pub struct AIProcessingResult {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AIProcessingProgress {
+	#[serde(default = "unversioned_message")]
+	pub schema_version: u32,
 	pub task_id: Uuid,
 	pub user_id: Uuid,
 	pub request_id: Option<Uuid>,
@@ -38,3 +69,66 @@ pub struct AIProcessingProgress {
 	pub message: String,
 	pub timestamp: String,
 }
+
+/// A message read by the result consumer: a final result or a progress update
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ResultMessage {
+	Result(AIProcessingResult),
+	Progress(AIProcessingProgress),
+}
+
+impl ResultMessage {
+	/// Tells the two apart by their fields: only a result has `completed_at`
+	pub fn from_message(data: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let value: serde_json::Value =
+			serde_json::from_slice(data).map_err(|e| -> Box<dyn Error + Send + Sync> {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Parse error: {}", e),
+				))
+			})?;
+
+		let message = if value.get("completed_at").is_some() {
+			serde_json::from_value(value).map(Self::Result)
+		} else {
+			serde_json::from_value(value).map(Self::Progress)
+		};
+
+		message.map_err(|e| -> Box<dyn Error + Send + Sync> {
+			Box::new(std::io::Error::new(
+				std::io::ErrorKind::Other,
+				format!("Invalid result message: {}", e),
+			))
+		})
+	}
+
+	pub fn task_id(&self) -> Uuid {
+		match self {
+			Self::Result(result) => result.task_id,
+			Self::Progress(progress) => progress.task_id,
+		}
+	}
+
+	pub fn user_id(&self) -> Uuid {
+		match self {
+			Self::Result(result) => result.user_id,
+			Self::Progress(progress) => progress.user_id,
+		}
+	}
+
+	pub fn status(&self) -> &str {
+		match self {
+			Self::Result(result) => &result.status,
+			Self::Progress(progress) => &progress.status,
+		}
+	}
+
+	/// Routing key the worker publishes the message with
+	pub fn routing_key(&self, topology: &Topology) -> String {
+		match self {
+			Self::Result(result) => topology.result_routing_key(result.user_id),
+			Self::Progress(progress) => topology.progress_routing_key(progress.user_id),
+		}
+	}
+}
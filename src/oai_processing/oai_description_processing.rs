@@ -1,10 +1,15 @@
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
 use std::error::Error;
-use std::process::Command;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
 use uuid::Uuid;
 
+use crate::llm::{
+	chat_checked, LlmProvider, PromptRegistry, PromptVars, Sanitizer, UsageContext,
+};
 use crate::models::rabbitmq::AIProcessingTask;
+use crate::tasks::TextParameters;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct QwenCliProcessingMessage {
@@ -15,85 +20,46 @@ pub struct QwenCliProcessingMessage {
 	pub created_ts: chrono::DateTime<chrono::Utc>,
 }
 
-pub async fn process_description_with_qwen_cli(
+pub async fn process_description_with_llm(
 	pool: Pool<Postgres>,
+	provider: Arc<dyn LlmProvider>,
 	task: &AIProcessingTask,
+	partial_output: Option<UnboundedSender<String>>,
 ) -> Result<String, Box<dyn Error + Send + Sync>> {
-	// Extract the input text and category from the task parameters
-	let input_text = task
-        .request_data
-        .parameters
-        .get("input_text")
-        .and_then(|v| v.as_str())
-        .or_else(|| task.request_data.parameters.get("description").and_then(|v| v.as_str()))
-        .ok_or("Description not found in task parameters. Expected 'input_text' or 'description' field.")?
-        .to_string();
-
-	let category = task
-		.request_data
-		.parameters
-		.get("category")
-		.and_then(|v| v.as_str())
-		.unwrap_or("General"); // Default to "General" if category is not provided
+	let TextParameters {
+		input_text,
+		category,
+	} = TextParameters::from_task(task, "description")?;
+	let category = category.as_str();
 
 	println!(
-		"Processing description with qwen-cli: {}, category: {}",
-		input_text, category
+		"Processing description with {}: {}, category: {}",
+		provider.name(),
+		input_text,
+		category
 	);
 
-	// Prepare a contextual prompt for the qwen-cli command
-	// Simple and direct prompt to ensure only the requested content is returned
-	let system_prompt = "Ты - инструмент генерации описаний и профессиональный маркетолог. Отвечай ТОЛЬКО текстом, который нужно скопировать и вставить. НЕ добавляй никаких поясняющих слов, комментариев, мыслей или системных сообщений. НЕ говори 'Ответ:', 'Результат:' или что-либо подобное. Просто предоставь запрашиваемый контент.";
-
-	let user_prompt = format!("Категория: {}. Задача перефразировать, улучшить и дополнить описание для объявления на доске объявлений авито: {}", category, input_text);
-
-	// Combine system and user prompts
-	let contextual_prompt = format!(
-		"{}\n\nПользовательский запрос: {}",
-		system_prompt, user_prompt
-	);
+	let mut vars = PromptVars::new();
+	vars.insert("category".to_string(), category.to_string());
+	vars.insert("input_text".to_string(), input_text.clone());
 
-	// Prepare arguments for the qwen-cli command
-	let args = vec![
-		"-p".to_string(), // Use prompt flag for non-interactive mode
-		contextual_prompt,
-	];
-
-	// Execute the qwen-cli command
-	let output = Command::new("npx")
-		.arg("@qwen-code/qwen-code")
-		.args(&args)
-		.output()
-		.map_err(|e| {
-			eprintln!("Failed to execute qwen-cli: {}", e);
-			Box::new(std::io::Error::new(
-				std::io::ErrorKind::Other,
-				format!("Failed to execute qwen-cli: {}", e),
-			)) as Box<dyn std::error::Error + Send + Sync>
-		})?;
-
-	if !output.status.success() {
-		let stderr =
-			String::from_utf8(output.stderr).unwrap_or_else(|_| "Unknown error".to_string());
-		eprintln!("qwen-cli command failed: {}", stderr);
-		return Err(Box::new(std::io::Error::new(
-			std::io::ErrorKind::Other,
-			format!("qwen-cli command failed: {}", stderr),
-		)));
-	}
+	let request = PromptRegistry::from_env()
+		.render("description", Some(category), &vars)?
+		.with_partial_output(partial_output);
 
-	// Parse the output from qwen-cli
-	let result = String::from_utf8(output.stdout).map_err(|e| {
-		eprintln!("Failed to parse qwen-cli output: {}", e);
-		Box::new(std::io::Error::new(
-			std::io::ErrorKind::Other,
-			format!("Failed to parse qwen-cli output: {}", e),
-		)) as Box<dyn std::error::Error + Send + Sync>
-	})?;
+	let result = chat_checked(
+		provider.as_ref(),
+		&request,
+		&pool,
+		&UsageContext::for_task(task),
+		&Sanitizer::for_processing_type("description"),
+		&vars,
+	)
+	.await?;
 
 	// Print the result to terminal
 	println!("Input description: {}", input_text);
-	println!("Qwen-cli result: {}", result);
+	println!("{} result: {}", provider.name(), result);
 
 	Ok(result.trim().to_string())
 }
@@ -106,7 +72,7 @@ pub async fn oai_description_processing(
 		if needs_to_restart {
 			// This function is now a placeholder since the actual processing happens
 			// in the RabbitMQ consumer with a specific task
-			// The actual processing logic is in the process_description_with_qwen_cli function
+			// The actual processing logic is in the process_description_with_llm function
 			return Ok(());
 		}
 	}
@@ -1,6 +1,4 @@
-use reqwest::header::{self, HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use sqlx::{Pool, Postgres};
 use std::env;
 use std::error::Error;
@@ -9,10 +7,11 @@ use urlencoding::encode;
 use uuid::Uuid;
 
 use crate::{
+	llm::{chat_checked, provider_for, PromptRegistry, PromptVars, Sanitizer, UsageContext},
 	models::{
 		BestlightCase, Count, Counter, Firm, Page, PageBlock, PageBlockSection, Review, SaveCounter,
 	},
-	utils::Translit,
+	utils::{BatchReport, Translit},
 };
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -20,21 +19,6 @@ struct AccessToken {
 	access_token: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct ApiResponse {
-	message: Message,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Choice {
-	message: Message,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Message {
-	content: String,
-}
-
 #[derive(Debug, Deserialize, Serialize)]
 struct Scope {
 	scope: String,
@@ -43,8 +27,10 @@ struct Scope {
 pub async fn oai_pages_processing(
 	pool: Pool<Postgres>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-	let url = env::var("OPENAI_API_BASE").expect("OPEN_AI_API_KEY not set");
-	let open_ai_token = env::var("OPENAI_API_KEY").expect("OPEN_AI_API_KEY not set");
+	let provider = provider_for("pages")?;
+	let prompts = PromptRegistry::from_env();
+	let sanitizer = Sanitizer::for_processing_type("pages");
+	let mut report = BatchReport::new("pages");
 
 	let counter_id: String = String::from("23cae330-9a3d-4655-8b88-5cfaaad914a3");
 	let city_id = uuid::Uuid::parse_str(
@@ -63,15 +49,9 @@ pub async fn oai_pages_processing(
 	let category_name = env::var("CRAWLER_CATEGOTY_NAME").expect("CRAWLER_CATEGOTY_NAME not set");
 	let rubric_id = env::var("CRAWLER_RUBRIC_ID").expect("CRAWLER_RUBRIC_ID not set");
 	let table = String::from("firms");
-	let model_name = "deepseek-v2:16b";
 
 	let split_target = String::from("\n");
 
-	let headers: HeaderMap<HeaderValue> = header::HeaderMap::from_iter(vec![
-		(header::ACCEPT, "application/json".parse().unwrap()),
-		(header::CONTENT_TYPE, "application/json".parse().unwrap()),
-	]);
-
 	// получаем из базы кол-во фирм
 	let firms_count_res: Count = sqlx::query_as!(
 		Count,
@@ -206,6 +186,121 @@ pub async fn oai_pages_processing(
 				.replace(">", "")
 				.replace("--", "-");
 
+			let case_description = cur_case.oai_description.unwrap_or("".to_string());
+
+			let usage_context = UsageContext::for_firm("pages", firm.firm_id);
+			let mut page_vars = PromptVars::new();
+			page_vars.insert(
+				"text".to_string(),
+				case_description.replace("\t", "").replace("\n", ""),
+			);
+			page_vars.insert(
+				"case_name".to_string(),
+				case_name.clone().unwrap_or("".to_string()),
+			);
+
+			// Everything is generated before the first insert, so a failed request
+			// skips the case instead of leaving a half-written page
+			// === KEY POINTS ===
+			let key_points_request = prompts.render("pages_key_points", None, &page_vars)?;
+
+			// request
+			let key_points_oai_res = &match chat_checked(
+				provider.as_ref(),
+				&key_points_request,
+				&pool,
+				&usage_context,
+				&sanitizer,
+				&page_vars,
+			)
+			.await
+			{
+				Ok(content) => content,
+				Err(e) => {
+					report.failure(format!("case {} key points", case_id), e);
+					continue;
+				}
+			};
+
+			// response
+			println!("============");
+			println!("{:?}", &key_points_oai_res);
+
+			// === WORK STAGES ===
+			let work_stages_request = prompts.render("pages_work_stages", None, &page_vars)?;
+
+			// request
+			let work_stages_oai_res = &match chat_checked(
+				provider.as_ref(),
+				&work_stages_request,
+				&pool,
+				&usage_context,
+				&sanitizer,
+				&page_vars,
+			)
+			.await
+			{
+				Ok(content) => content,
+				Err(e) => {
+					report.failure(format!("case {} work stages", case_id), e);
+					continue;
+				}
+			};
+
+			// response
+			println!("============");
+			println!("{:?}", &work_stages_oai_res);
+
+			// === PRICES ===
+			let prices_request = prompts.render("pages_prices", None, &page_vars)?;
+
+			// request
+			let prices_oai_res = &match chat_checked(
+				provider.as_ref(),
+				&prices_request,
+				&pool,
+				&usage_context,
+				&sanitizer,
+				&page_vars,
+			)
+			.await
+			{
+				Ok(content) => content,
+				Err(e) => {
+					report.failure(format!("case {} prices", case_id), e);
+					continue;
+				}
+			};
+
+			// response
+			println!("============");
+			println!("{:?}", &prices_oai_res);
+
+			// === TAGS ===
+			let tags_request = prompts.render("pages_tags", None, &page_vars)?;
+
+			// request
+			let tags_oai_res = &match chat_checked(
+				provider.as_ref(),
+				&tags_request,
+				&pool,
+				&usage_context,
+				&sanitizer,
+				&page_vars,
+			)
+			.await
+			{
+				Ok(content) => content,
+				Err(e) => {
+					report.failure(format!("case {} tags", case_id), e);
+					continue;
+				}
+			};
+
+			// response
+			println!("============");
+			println!("{:?}", &tags_oai_res);
+
 			// запись в бд
 			let created_page: Page = sqlx::query_as!(
 				Page,
@@ -226,7 +321,6 @@ pub async fn oai_pages_processing(
 				"Кейс ремонт фар {}",
 				case_name.clone().unwrap_or("".to_string())
 			);
-			let case_description = cur_case.oai_description.unwrap_or("".to_string());
 
 			let page_block: PageBlock = sqlx::query_as!(
 				PageBlock,
@@ -240,76 +334,6 @@ pub async fn oai_pages_processing(
 			.await
 			.map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e))) as Box<dyn std::error::Error + Send + Sync>)?;
 
-			// === KEY POINTS ===
-			let key_points_preamble = format!(
-				"
-				The Text:
-				{}
-				",
-				&case_description
-			);
-
-			let key_points_body = json!({
-			  "model": model_name,
-				"stream": false,
-			  "messages": [
-					{
-						"role": "system",
-						"content": "
-						1. Act as a professional text analizer.
-						2. Context: I will provide you with the Text.
-						3. Your task: Analyze the text and highlight three key points without prices.
-						4. Format: Write your answer only in the Russian language, do not use Chinese words/hieroglyphs or English words/letters or other languages, only Russian language. Write in plain text. Give the answer in listicle form. Each item from a new line. Keep the meaning and write from the same person as in the text. Write in the first person and preserve the speaker's gender. Try to write as a man. Don't write what you think and don't use any system phrases in your answer. Do not use phrases like: *Ответ:*
-						5. Tone of Voice: Be empathetic, concise, intelligent, driven, and wise. Think step by step."
-					},
-					{
-						"role": "user",
-						"content": &key_points_preamble.replace("\t", "").replace("\n", "")
-					}
-				]
-			});
-
-			// request
-			let key_points_response = reqwest::Client::builder()
-				.danger_accept_invalid_certs(true)
-				.build()
-				.unwrap()
-				.post(url.clone())
-				.headers(headers.clone())
-				.json(&key_points_body)
-				.send()
-				.await
-				.map_err(|e| {
-					Box::new(std::io::Error::new(
-						std::io::ErrorKind::Other,
-						format!("{}", e),
-					)) as Box<dyn std::error::Error + Send + Sync>
-				})?;
-
-			let empty_message = Message {
-				content: "".to_string(),
-			};
-
-			let key_points_res: ApiResponse = match key_points_response.json().await {
-				Ok(result) => result,
-				Err(e) => {
-					println!("Network error: {:?}", e);
-					ApiResponse {
-						message: empty_message,
-					}
-				}
-			};
-
-			let key_points_oai_res = &key_points_res.message.content;
-
-			if key_points_oai_res == "" {
-				continue;
-			}
-
-			// response
-			println!("============");
-			println!("{:?}", &key_points_oai_res);
-
 			let block_title = format!(
 				"Кейс ремонт фар {}",
 				case_name.clone().unwrap_or("".to_string())
@@ -366,75 +390,6 @@ pub async fn oai_pages_processing(
 			let work_stages_block_id = work_stages_block.clone().page_block_id;
 			dbg!(&work_stages_block_id);
 
-			let work_stages_preamble = format!(
-				"
-				The Text:
-				{}
-				",
-				&case_description
-			);
-
-			let work_stages_body = json!({
-			  "model": model_name,
-				"stream": false,
-			  "messages": [
-					{
-						"role": "system",
-						"content": "
-						1. Act as a professional text analizer.
-						2. Context: I will provide you with the Text.
-						3. Your task: Analyze the text and highlight the main stages of the work done without prices.
-						4. Format: Write your answer only in the Russian language, do not use Chinese words/hieroglyphs or English words/letters or other languages, only Russian language. Write in plain text. Give the answer in listicle form. Each item from a new line. Keep the meaning and write from the same person as in the text. Write in the first person and preserve the speaker's gender. Try to write as a man. Don't write what you think and don't use any system phrases in your answer. Do not use phrases like: *Ответ:*
-						5. Tone of Voice: Be empathetic, concise, intelligent, driven, and wise. Think step by step."
-					},
-					{
-						"role": "user",
-						"content": &work_stages_preamble.replace("\t", "").replace("\n", "")
-					}
-				]
-			});
-
-			// request
-			let work_stages_response = reqwest::Client::builder()
-				.danger_accept_invalid_certs(true)
-				.build()
-				.unwrap()
-				.post(url.clone())
-				.headers(headers.clone())
-				.json(&work_stages_body)
-				.send()
-				.await
-				.map_err(|e| {
-					Box::new(std::io::Error::new(
-						std::io::ErrorKind::Other,
-						format!("{}", e),
-					)) as Box<dyn std::error::Error + Send + Sync>
-				})?;
-
-			let empty_message = Message {
-				content: "".to_string(),
-			};
-
-			let work_stages_res: ApiResponse = match work_stages_response.json().await {
-				Ok(result) => result,
-				Err(e) => {
-					println!("Network error: {:?}", e);
-					ApiResponse {
-						message: empty_message,
-					}
-				}
-			};
-
-			let work_stages_oai_res = &work_stages_res.message.content;
-
-			// response
-			println!("============");
-			println!("{:?}", &work_stages_oai_res);
-
-			if work_stages_oai_res == "" {
-				continue;
-			}
-
 			let mut work_stages_oai_array = work_stages_oai_res
 				.split(&split_target)
 				.filter(|&x| *x != *"   ")
@@ -484,75 +439,6 @@ pub async fn oai_pages_processing(
 			let prices_block_id = prices_block.clone().page_block_id;
 			dbg!(&prices_block_id);
 
-			let prices_preamble = format!(
-				"
-				The Text:
-				{}
-				",
-				&case_description
-			);
-
-			let prices_body = json!({
-			  "model": model_name,
-				"stream": false,
-			  "messages": [
-					{
-						"role": "system",
-						"content": "
-						1. Act as a professional text analizer.
-						2. Context: I will provide you with the Text.
-						3. Your task: Analyze the text and highlight approximate prices for headlight glass repair and replacement services.
-						4. Format: Write your answer only in the Russian language, do not use Chinese words/hieroglyphs or English words/letters or other languages, only Russian language. Write in plain text. Give the answer in listicle form. Each item from a new line. Keep the meaning and write from the same person as in the text. Write in the first person and preserve the speaker's gender. Try to write as a man. Don't write what you think and don't use any system phrases in your answer. Do not use phrases like: *Ответ:*
-						5. Tone of Voice: Be empathetic, concise, intelligent, driven, and wise. Think step by step."
-					},
-					{
-						"role": "user",
-						"content": &prices_preamble.replace("\t", "").replace("\n", "")
-					}
-				]
-			});
-
-			// request
-			let prices_response = reqwest::Client::builder()
-				.danger_accept_invalid_certs(true)
-				.build()
-				.unwrap()
-				.post(url.clone())
-				.headers(headers.clone())
-				.json(&prices_body)
-				.send()
-				.await
-				.map_err(|e| {
-					Box::new(std::io::Error::new(
-						std::io::ErrorKind::Other,
-						format!("{}", e),
-					)) as Box<dyn std::error::Error + Send + Sync>
-				})?;
-
-			let empty_message = Message {
-				content: "".to_string(),
-			};
-
-			let prices_res: ApiResponse = match prices_response.json().await {
-				Ok(result) => result,
-				Err(e) => {
-					println!("Network error: {:?}", e);
-					ApiResponse {
-						message: empty_message,
-					}
-				}
-			};
-
-			let prices_oai_res = &prices_res.message.content;
-
-			// response
-			println!("============");
-			println!("{:?}", &prices_oai_res);
-
-			if prices_oai_res == "" {
-				continue;
-			}
-
 			let mut prices_oai_array = prices_oai_res
 				.split(&split_target)
 				.filter(|&x| *x != *"   ")
@@ -605,76 +491,6 @@ pub async fn oai_pages_processing(
 			let tags_block_id = tags_block.clone().page_block_id;
 			dbg!(&tags_block_id);
 
-			let tags_preamble = format!(
-				"
-				The Text:
-				{}
-				",
-				&case_description
-			);
-
-			let tags_body = json!({
-			  "model": model_name,
-				"stream": false,
-			  "messages": [
-					{
-						"role": "system",
-						"content": format!("
-						1. Act as a professional SEO specialist/SEO writer.
-						2. Context: I will provide you with the Text.
-						3. Your task: Generate tags (keyphrases) for SEO promotion of a page: {} headlight repair. Generate 5 Main keyphrases (high frequency). Generate 5 Additional keyphrases (mid- and low-frequency). Generate 5 Technical and LSI keyphrases (to enhance relevance).
-						4. Format: Write your answer only in the Russian language, do not use Chinese words/hieroglyphs or English words/letters or other languages, only Russian language. Give the answer in listicle form. Each item from a new line. Don't write what you think and don't use any system phrases in your answer. Do not use phrases like: *Ответ:*
-						5. Think step by step.",
-						case_name.clone().unwrap_or("".to_string()))
-					},
-					{
-						"role": "user",
-						"content": &tags_preamble.replace("\t", "").replace("\n", "")
-					}
-				]
-			});
-
-			// request
-			let tags_response = reqwest::Client::builder()
-				.danger_accept_invalid_certs(true)
-				.build()
-				.unwrap()
-				.post(url.clone())
-				.headers(headers.clone())
-				.json(&tags_body)
-				.send()
-				.await
-				.map_err(|e| {
-					Box::new(std::io::Error::new(
-						std::io::ErrorKind::Other,
-						format!("{}", e),
-					)) as Box<dyn std::error::Error + Send + Sync>
-				})?;
-
-			let empty_message = Message {
-				content: "".to_string(),
-			};
-
-			let tags_res: ApiResponse = match tags_response.json().await {
-				Ok(result) => result,
-				Err(e) => {
-					println!("Network error: {:?}", e);
-					ApiResponse {
-						message: empty_message,
-					}
-				}
-			};
-
-			let tags_oai_res = &tags_res.message.content;
-
-			// response
-			println!("============");
-			println!("{:?}", &tags_oai_res);
-
-			if tags_oai_res == "" {
-				continue;
-			}
-
 			let mut tags_oai_array = tags_oai_res
 				.split(&split_target)
 				.filter(|&x| *x != *"   ")
@@ -695,8 +511,12 @@ pub async fn oai_pages_processing(
 					.map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e))) as Box<dyn std::error::Error + Send + Sync>)?;
 				}
 			}
+
+			report.success();
 		}
 	}
 
+	report.print_summary();
+
 	Ok(())
 }
@@ -1,34 +1,21 @@
-use reqwest::header::{self, HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use sqlx::{Pool, Postgres};
 use std::env;
 use std::error::Error;
 use tokio::time::{sleep, Duration};
 use uuid::Uuid;
 
+use crate::llm::{
+	chat_checked, prompt_category, provider_for, PromptRegistry, Sanitizer, UsageContext,
+};
 use crate::models::{Count, Counter, Firm, Review, SaveCounter};
+use crate::utils::BatchReport;
 
 #[derive(Debug, Deserialize, Serialize)]
 struct AccessToken {
 	access_token: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct ApiResponse {
-	message: Message,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Choice {
-	message: Message,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Message {
-	content: String,
-}
-
 #[derive(Debug, Deserialize, Serialize)]
 struct Scope {
 	scope: String,
@@ -37,8 +24,10 @@ struct Scope {
 pub async fn oai_reviews_rewrite_processing(
 	pool: Pool<Postgres>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-	let url = env::var("OPENAI_API_BASE").expect("OPEN_AI_API_KEY not set");
-	let open_ai_token = env::var("OPENAI_API_KEY").expect("OPEN_AI_API_KEY not set");
+	let provider = provider_for("reviews_rewrite")?;
+	let prompts = PromptRegistry::from_env();
+	let sanitizer = Sanitizer::for_processing_type("reviews_rewrite");
+	let mut report = BatchReport::new("reviews_rewrite");
 
 	let counter_id: String = String::from("23cae330-9a3d-4655-8b88-5cfaaad914a3");
 	let city_id = uuid::Uuid::parse_str(
@@ -60,6 +49,8 @@ pub async fn oai_reviews_rewrite_processing(
 
 	let query_uuid = Uuid::new_v4();
 
+	let (category_key, category_vars) = prompt_category(&pool, &category_id).await;
+
 	// получаем из базы кол-во фирм
 	let firms_count =
 		Count::count_firms_by_city_category(&pool, table.clone(), city_id, category_id)
@@ -145,112 +136,46 @@ pub async fn oai_reviews_rewrite_processing(
 
 			let cur_review = reviews_by_firm.get(0).unwrap().to_owned();
 
-			let preamble = format!(
-				"
-				The Text:
-				{}
-				",
-				cur_review.text.unwrap_or("".to_string())
+			let mut vars = category_vars.clone();
+			vars.insert("firm_name".to_string(), firm_name.to_string());
+			vars.insert(
+				"text".to_string(),
+				cur_review
+					.text
+					.unwrap_or("".to_string())
+					.replace("\t", "")
+					.replace("\n", ""),
 			);
 
-			let headers: HeaderMap<HeaderValue> = header::HeaderMap::from_iter(vec![
-				(header::ACCEPT, "application/json".parse().unwrap()),
-				(header::CONTENT_TYPE, "application/json".parse().unwrap()),
-				// (
-				// 	header::AUTHORIZATION,
-				// 	format!("Bearer {}", open_ai_token).parse().unwrap(),
-				// ),
-			]);
-
-			let body = json!({
-			  "model": "qwen2.5:14b",
-				"stream": false,
-			  "messages": [
-					{
-						"role": "system", // контекст
-						"content": "
-						1. Act as a professional review writer about other companies and assistant with Strategist (Self-Actualizing) and Alchemist (Construct-Aware) Action Logics according to Ego Development Theory.
-
-						2. Context: I will provide you with the Review Text.
-
-						3. Your task:
-						A. Rewrite the Review Text, but keep it close to the original one.
-
-						4. Format: Write your answer only in the Russian language. Write in plain text. Keep the meaning and write from the same person as in the Review text. Write in the first person and preserve the speaker's gender. Try to write as a man.
-
-						5. Tone of Voice: Be empathetic, concise, intelligent, driven, and wise. Think step by step.
-
-						6. Constraints: Don't write in the Chinese language. Make sure you follow 80/20 rule: provide 80% of essential value using 20% or less volume of text.
-						Don't mention the about the reward. Don't thank me for anything. Don't mention about text.
-						Don't mention about your tasks. Don't mention about your roles.
-						Don't feel sorry or express your condolences. Don't express your opinion. Don't say that you are happy.
-						Don't say the phrases like: 'Ответ', 'Переписанный текст', 'Переформулированный текст', 'Rewritten Text',
-						'Я прочитал твой отзыв', 'Отзыв', 'Описание', 'Мнение', 'понял ваш запрос', 'переписать ваш отзыв',
-						'Конечно, я могу помочь вам с этим', 'Как стратег и алхимик'.
-						If you can not fulfill my request, just leave the original text.
-
-						7. Reward: If the Text is good, I will give you 1000 dollars, but don't mention it and don't thank me."
-					},
-					{
-						"role": "user", // запрос пользователя
-						"content": &preamble.replace("\t", "").replace("\n", "")
-					}
-				]
-			});
+			let request = prompts.render("reviews_rewrite", category_key.as_deref(), &vars)?;
 
 			// request
-			let response = reqwest::Client::builder()
-				.danger_accept_invalid_certs(true)
-				.build()
-				.unwrap()
-				.post(url.clone())
-				.headers(headers)
-				.json(&body)
-				.send()
-				.await
-				.map_err(|e| {
-					Box::new(std::io::Error::new(
-						std::io::ErrorKind::Other,
-						format!("{}", e),
-					)) as Box<dyn std::error::Error + Send + Sync>
-				})?;
-
-			let empty_message = Message {
-				content: "".to_string(),
-			};
-
-			let res: ApiResponse = match response.json().await {
-				Ok(result) => result,
+			let usage_context = UsageContext::for_firm("reviews_rewrite", firm.firm_id);
+			let choices_res = match chat_checked(
+				provider.as_ref(),
+				&request,
+				&pool,
+				&usage_context,
+				&sanitizer,
+				&vars,
+			)
+			.await
+			{
+				Ok(content) => content,
 				Err(e) => {
-					println!("Network error: {:?}", e);
-					ApiResponse {
-						message: empty_message,
-					}
+					report.failure(format!("review {}", cur_review.review_id), e);
+					continue;
 				}
 			};
 
-			// let empty_choices = Choice {
-			// 	message: empty_message,
-			// };
-
-			// let choices_res = &res.choices.get(0).unwrap_or(&empty_choices).message.content;
-			let choices_res = &res.message.content;
-
 			// response
 			println!("{:?}", &choices_res);
 
-			if choices_res == "" {
-				continue;
-			}
-
 			// запись в бд
 			let _ = sqlx::query_as!(
 				Review,
 				r#"UPDATE reviews SET text = $1 WHERE firm_id = $2 AND review_id = $3 RETURNING *"#,
-				choices_res
-					.replace("XYZ", &firm_name)
-					.replace("#", "")
-					.replace("*", ""),
+				choices_res,
 				firm.firm_id.clone(),
 				cur_review.review_id.clone(),
 			)
@@ -279,8 +204,12 @@ pub async fn oai_reviews_rewrite_processing(
 					format!("{}", e),
 				)) as Box<dyn std::error::Error + Send + Sync>
 			})?;
+
+			report.success();
 		}
 	}
 
+	report.print_summary();
+
 	Ok(())
 }
@@ -1,10 +1,16 @@
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
 use std::error::Error;
-use std::process::Command;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
 use uuid::Uuid;
 
+use crate::llm::{
+	chat_title, LlmProvider, PromptRegistry, PromptVars, Sanitizer, TitleCheck, TitleRules,
+	UsageContext,
+};
 use crate::models::rabbitmq::AIProcessingTask;
+use crate::tasks::TextParameters;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct QwenCliProcessingMessage {
@@ -15,92 +21,50 @@ pub struct QwenCliProcessingMessage {
 	pub created_ts: chrono::DateTime<chrono::Utc>,
 }
 
-pub async fn process_title_with_qwen_cli(
+pub async fn process_title_with_llm(
 	pool: Pool<Postgres>,
+	provider: Arc<dyn LlmProvider>,
 	task: &AIProcessingTask,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
-	// Extract the input text and category from the task parameters
-	let input_text = task
-		.request_data
-		.parameters
-		.get("input_text")
-		.and_then(|v| v.as_str())
-		.or_else(|| {
-			task.request_data
-				.parameters
-				.get("title")
-				.and_then(|v| v.as_str())
-		})
-		.ok_or("Title not found in task parameters. Expected 'input_text' or 'title' field.")?
-		.to_string();
-
-	let category = task
-		.request_data
-		.parameters
-		.get("category")
-		.and_then(|v| v.as_str())
-		.unwrap_or("General"); // Default to "General" if category is not provided
+	partial_output: Option<UnboundedSender<String>>,
+) -> Result<TitleCheck, Box<dyn Error + Send + Sync>> {
+	let TextParameters {
+		input_text,
+		category,
+	} = TextParameters::from_task(task, "title")?;
+	let category = category.as_str();
 
 	println!(
-		"Processing title with qwen-cli: {}, category: {}",
-		input_text, category
+		"Processing title with {}: {}, category: {}",
+		provider.name(),
+		input_text,
+		category
 	);
 
-	// Prepare a contextual prompt for the qwen-cli command
-	// Simple and direct prompt to ensure only the requested content is returned
-	let system_prompt = "Ты - инструмент генерации заголовков и профессиональный маркетолог. Отвечай ТОЛЬКО текстом, который нужно скопировать и вставить. НЕ добавляй никаких поясняющих слов, комментариев, мыслей или системных сообщений. НЕ говори 'Ответ:', 'Результат:' или что-либо подобное. Просто предоставь запрашиваемый контент.";
-
-	let user_prompt = format!("Категория: {}. Задача перефразировать и улучшить заголовок для объявления на доске объявлений авито: {}", category, input_text);
-
-	// Combine system and user prompts
-	let contextual_prompt = format!(
-		"{}\n\nПользовательский запрос: {}",
-		system_prompt, user_prompt
-	);
-
-	// Prepare arguments for the qwen-cli command
-	let args = vec![
-		"-p".to_string(), // Use prompt flag for non-interactive mode
-		contextual_prompt,
-	];
-
-	// Execute the qwen-cli command
-	let output = Command::new("npx")
-		.arg("@qwen-code/qwen-code")
-		.args(&args)
-		.output()
-		.map_err(|e| {
-			eprintln!("Failed to execute qwen-cli: {}", e);
-			Box::new(std::io::Error::new(
-				std::io::ErrorKind::Other,
-				format!("Failed to execute qwen-cli: {}", e),
-			)) as Box<dyn std::error::Error + Send + Sync>
-		})?;
-
-	if !output.status.success() {
-		let stderr =
-			String::from_utf8(output.stderr).unwrap_or_else(|_| "Unknown error".to_string());
-		eprintln!("qwen-cli command failed: {}", stderr);
-		return Err(Box::new(std::io::Error::new(
-			std::io::ErrorKind::Other,
-			format!("qwen-cli command failed: {}", stderr),
-		)));
-	}
-
-	// Parse the output from qwen-cli
-	let result = String::from_utf8(output.stdout).map_err(|e| {
-		eprintln!("Failed to parse qwen-cli output: {}", e);
-		Box::new(std::io::Error::new(
-			std::io::ErrorKind::Other,
-			format!("Failed to parse qwen-cli output: {}", e),
-		)) as Box<dyn std::error::Error + Send + Sync>
-	})?;
+	let mut vars = PromptVars::new();
+	vars.insert("category".to_string(), category.to_string());
+	vars.insert("input_text".to_string(), input_text.clone());
+
+	let request = PromptRegistry::from_env()
+		.render("title", Some(category), &vars)?
+		.with_partial_output(partial_output);
+
+	let result = chat_title(
+		provider.as_ref(),
+		&request,
+		&pool,
+		&UsageContext::for_task(task),
+		&Sanitizer::for_processing_type("title"),
+		&vars,
+		&TitleRules::for_target("title"),
+		"",
+	)
+	.await?;
 
 	// Print the result to terminal
 	println!("Input title: {}", input_text);
-	println!("Qwen-cli result: {}", result);
+	println!("{} result: {}", provider.name(), result.title);
 
-	Ok(result.trim().to_string())
+	Ok(result)
 }
 
 pub async fn oai_title_processing(
@@ -111,10 +75,142 @@ pub async fn oai_title_processing(
 		if needs_to_restart {
 			// This function is now a placeholder since the actual processing happens
 			// in the RabbitMQ consumer with a specific task
-			// The actual processing logic is in the process_title_with_qwen_cli function
+			// The actual processing logic is in the process_title_with_llm function
 			return Ok(());
 		}
 	}
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::llm::{
+		CircuitBreaker, MockLlmServer, MockReply, OllamaProvider, RetryPolicy, RetryingProvider,
+	};
+	use sqlx::postgres::PgPoolOptions;
+	use tokio::time::Duration;
+
+	/// Usage rows are only logged when the database is unreachable
+	fn unreachable_pool() -> Pool<Postgres> {
+		PgPoolOptions::new()
+			.acquire_timeout(Duration::from_millis(100))
+			.connect_lazy("postgres://postgres@127.0.0.1:1/none")
+			.unwrap()
+	}
+
+	fn mock_provider(server: &MockLlmServer) -> Arc<dyn LlmProvider> {
+		Arc::new(RetryingProvider::new(
+			Arc::new(OllamaProvider::new(server.ollama_url(), "mock".to_string())),
+			RetryPolicy {
+				max_attempts: 3,
+				base_delay: Duration::from_millis(10),
+				max_delay: Duration::from_millis(20),
+				retry_on_status: vec![500, 503],
+			},
+			Arc::new(CircuitBreaker::new(
+				"title test",
+				10,
+				Duration::from_secs(1),
+			)),
+		))
+	}
+
+	fn title_task(input_text: &str) -> AIProcessingTask {
+		serde_json::from_value(serde_json::json!({
+			"task_id": Uuid::new_v4(),
+			"created_at": "2024-05-01T00:00:00Z",
+			"request_data": {
+				"request_id": Uuid::new_v4(),
+				"user_id": Uuid::new_v4(),
+				"processing_type": "title",
+				"parameters": {"input_text": input_text, "category": "Окна"}
+			}
+		}))
+		.unwrap()
+	}
+
+	#[tokio::test]
+	async fn title_survives_server_errors_and_malformed_replies() {
+		let server = MockLlmServer::start("127.0.0.1:0").await.unwrap();
+		server.push_reply(MockReply::server_error(503)).await;
+		server.push_reply(MockReply::malformed()).await;
+		server
+			.push_reply(MockReply::text("«Ремонт пластиковых окон в Москве»"))
+			.await;
+
+		let check = process_title_with_llm(
+			unreachable_pool(),
+			mock_provider(&server),
+			&title_task("ремонт окон москва"),
+			None,
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(check.title, "Ремонт пластиковых окон в Москве");
+		assert!(check.violations.is_empty());
+		let requests = server.requests().await;
+		assert_eq!(requests.len(), 3);
+		let prompt = &requests[2].messages.last().unwrap().content;
+		assert!(prompt.contains("Категория: Окна"), "{}", prompt);
+		assert!(prompt.contains("ремонт окон москва"), "{}", prompt);
+		server.shutdown();
+	}
+
+	#[tokio::test]
+	async fn title_breaking_rules_is_regenerated() {
+		let server = MockLlmServer::start("127.0.0.1:0").await.unwrap();
+		server
+			.push_reply(MockReply::text("РЕМОНТ окон, звоните 8 800 555-35-35"))
+			.await;
+		server
+			.push_reply(MockReply::text("Ремонт окон недорого"))
+			.await;
+
+		let check = process_title_with_llm(
+			unreachable_pool(),
+			mock_provider(&server),
+			&title_task("ремонт окон"),
+			None,
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(check.title, "Ремонт окон недорого");
+		assert_eq!(check.regenerations, 1);
+		assert!(!check.fixed);
+		assert_eq!(check.violations, vec!["all_caps", "phone_number"]);
+		// The second request carries the rejected answer and the complaint
+		let requests = server.requests().await;
+		assert_eq!(requests.len(), 2);
+		assert!(requests[1]
+			.messages
+			.last()
+			.unwrap()
+			.content
+			.starts_with("Заголовок не подходит"));
+		server.shutdown();
+	}
+
+	#[tokio::test]
+	async fn title_fails_when_server_keeps_failing() {
+		let server = MockLlmServer::start("127.0.0.1:0").await.unwrap();
+		for _ in 0..3 {
+			server.push_reply(MockReply::server_error(500)).await;
+		}
+
+		let result = process_title_with_llm(
+			unreachable_pool(),
+			mock_provider(&server),
+			&title_task("ремонт окон"),
+			None,
+		)
+		.await;
+
+		assert!(result.is_err());
+		assert_eq!(server.requests().await.len(), 3);
+		server.shutdown();
+	}
+}
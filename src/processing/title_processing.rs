@@ -1,7 +1,5 @@
 use chrono::{DateTime, TimeZone, Utc};
-use reqwest::header::{self, HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use sqlx::{Pool, Postgres};
 use std::env;
 use std::error::Error;
@@ -9,28 +7,17 @@ use tokio::time::{sleep, Duration};
 use urlencoding::encode;
 use uuid::Uuid;
 
+use crate::llm::{
+	chat_title, prompt_category, provider_for, PromptRegistry, Sanitizer, TitleRules, UsageContext,
+};
 use crate::models::{AIDescription, Count, Counter, Firm, Review, SaveCounter};
+use crate::utils::BatchReport;
 
 #[derive(Debug, Deserialize, Serialize)]
 struct AccessToken {
 	access_token: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct ApiResponse {
-	message: Message,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Choice {
-	message: Message,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Message {
-	content: String,
-}
-
 #[derive(Debug, Deserialize, Serialize)]
 struct Scope {
 	scope: String,
@@ -51,10 +38,13 @@ pub async fn title_processing(pool: Pool<Postgres>) -> Result<(), Box<dyn std::e
 			.as_str(),
 	)
 	.unwrap();
-	let url = env::var("OPENAI_API_BASE").expect("OPEN_AI_API_KEY not set");
-	let open_ai_token = env::var("OPENAI_API_KEY").expect("OPEN_AI_API_KEY not set");
+	let provider = provider_for("seo_title").map_err(|e| e.to_string())?;
+	let prompts = PromptRegistry::from_env();
+	let sanitizer = Sanitizer::for_processing_type("seo_title");
+	let title_rules = TitleRules::for_target("seo_title");
+	let mut report = BatchReport::new("seo_title");
+	let (category_key, category_vars) = prompt_category(&pool, &category_id).await;
 	let counter_id: String = String::from("759f3b92-4d38-4980-a18f-ada6302e75b8");
-	let model_name: String = String::from("deepseek-v2:16b");
 
 	// let firms_count =
 	// 	Count::count_firms_with_empty_field(&pool, table.clone(), "title".to_string())
@@ -156,122 +146,43 @@ pub async fn title_processing(pool: Pool<Postgres>) -> Result<(), Box<dyn std::e
 			);
 		}
 
-		let preamble = format!(
-			"
-			The Text:
-			{}
-			",
+		let mut vars = category_vars.clone();
+		vars.insert(
+			"text".to_string(),
 			format!("{}, {}", &firm.name.clone().unwrap(), &ai_description)
+				.replace("\t", "")
+				.replace("\n", "")
+				.replace("\u{200b}", " ")
+				.replace("  ", " "),
 		);
 
-		let headers: HeaderMap<HeaderValue> = header::HeaderMap::from_iter(vec![
-			(header::ACCEPT, "application/json".parse().unwrap()),
-			(header::CONTENT_TYPE, "application/json".parse().unwrap()),
-			// (
-			// 	header::AUTHORIZATION,
-			// 	format!("Bearer {}", open_ai_token).parse().unwrap(),
-			// ),
-		]);
-
-		let body = json!({
-		  "model": model_name,
-			"stream": false,
-		  "messages": [
-				{
-					"role": "system", // контекст
-					"content": "
-					1. Act as a professional SEO specialist and writer about and assistant with Strategist (Self-Actualizing) and Alchemist (Construct-Aware) Action Logics according to Ego Development Theory.
-
-					2. Context: I will provide you with the Text.
-
-					3. Your task:
-					A. Generate the best SEO Title, for web page about organization. Example:  Автосервис АВТОДОМ BRP - быстрый и надежный ремонт | Опытные мастера | Гарантия качества
-
-					4. Format: Write your answer only in the Russian language. Title must be 100 symbols length maximum.
-
-					5. Tone of Voice: Be empathetic, concise, intelligent, driven, and wise. Think step by step.
-
-					6. Constraints:
-					Text must be 100 symbols length maximum.
-					Don't write in the Chinese language.
-					Don't translate the name of the organization.
-					Don't mention the about the reward. Don't thank me for anything. Don't mention about text. Don't use the symbols \".
-					Don't mention about your tasks. Don't mention about your roles.
-					Don't feel sorry or express your condolences. Don't express your opinion. Don't say that you are happy.
-					Don't say the phrases like: 'Ответ', 'Переписанный текст', 'Переформулированный текст', 'Rewritten Text',
-					'Я прочитал твой текст', 'Отзыв', 'Описание', 'Мнение', 'понял ваш запрос', 'переписать ваш отзыв',
-					'Конечно, я могу помочь вам с этим', 'Как стратег и алхимик'.
-					If you can not fulfill my request, just leave the original text.
-
-					7. Reward: If the Text is good, I will give you 1000 dollars, but don't mention it and don't thank me."
-				},
-				{
-					"role": "user", // запрос пользователя
-					"content": &preamble.replace("\t", "").replace("\n", "").replace("\u{200b}", " ").replace("  ", " ")
-				}
-			]
-		});
+		let request = prompts
+			.render("seo_title", category_key.as_deref(), &vars)
+			.map_err(|e| e.to_string())?;
 
 		// request
-		let response = reqwest::Client::builder()
-			.danger_accept_invalid_certs(true)
-			.build()
-			.unwrap()
-			.post(url.clone())
-			.headers(headers)
-			.json(&body)
-			.send()
-			.await?;
-
-		let empty_message = Message {
-			content: "".to_string(),
-		};
-
-		let res: ApiResponse = match response.json().await {
-			Ok(result) => result,
+		let usage_context = UsageContext::for_firm("seo_title", firm.firm_id);
+		let prefix = format!("{} | ", sanitizer.apply(&firm_title, &vars));
+		let title = match chat_title(
+			provider.as_ref(),
+			&request,
+			&pool,
+			&usage_context,
+			&sanitizer,
+			&vars,
+			&title_rules,
+			&prefix,
+		)
+		.await
+		{
+			Ok(check) => check.title,
 			Err(e) => {
-				println!("Network error: {:?}", e);
-				ApiResponse {
-					message: empty_message,
-				}
+				// Keep the old title instead of writing one with an empty suffix
+				report.failure(format!("firm {}", firm.firm_id), e);
+				continue;
 			}
 		};
 
-		// let empty_choices = Choice {
-		// 	message: empty_message,
-		// };
-
-		// let choices_res = &res.choices.get(0).unwrap_or(&empty_choices).message.content;
-		let choices_res = &res.message.content;
-
-		let title = format!(
-			"{} {}",
-			&firm_title
-				.replace("`", "")
-				.replace("/", "-")
-				.replace("&amp;", "&")
-				.replace("--", "-")
-				.replace("\"", "")
-				.replace("\u{200b}", " ")
-				.replace("\u{fe0f}", " ")
-				.replace("  ", " ")
-				.as_str(),
-			format!(
-				"| {}",
-				&choices_res
-					.replace("`", "")
-					.replace("/", "-")
-					.replace("&amp;", "&")
-					.replace("--", "-")
-					.replace("\"", "")
-					.replace("\"", "")
-					.replace("\u{200b}", " ")
-					.replace("\u{fe0f}", " ")
-					.replace("  ", " ")
-					.as_str()
-			)
-		);
-
 		// response
 		println!("{}", &title);
 
@@ -293,7 +204,11 @@ pub async fn title_processing(pool: Pool<Postgres>) -> Result<(), Box<dyn std::e
 			},
 		)
 		.await;
+
+		report.success();
 	}
 
+	report.print_summary();
+
 	Ok(())
 }
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::rabbitmq::{AIProcessingTask, AITaskCancel};
+
+/// Returned by processors that stopped because their task was cancelled
+#[derive(Debug, thiserror::Error)]
+#[error("Task cancelled: {reason}")]
+pub struct TaskCancelled {
+	pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum CancelTarget {
+	Task(Uuid),
+	Batch(String),
+}
+
+#[derive(Debug, Clone)]
+struct Cancellation {
+	reason: String,
+	received: Instant,
+}
+
+/// Task and batch ids cancelled with `task.cancel` messages.
+///
+/// Entries are kept for `TASK_CANCEL_TTL_SECS` (default 86400), so tasks that are
+/// still queued when the cancellation arrives are skipped as well.
+#[derive(Debug)]
+pub struct CancellationRegistry {
+	entries: Mutex<HashMap<CancelTarget, Cancellation>>,
+	notify: Notify,
+	ttl: Duration,
+}
+
+impl CancellationRegistry {
+	pub fn new(ttl: Duration) -> Self {
+		Self {
+			entries: Mutex::new(HashMap::new()),
+			notify: Notify::new(),
+			ttl,
+		}
+	}
+
+	pub fn from_env() -> Self {
+		Self::new(Duration::from_secs(
+			env::var("TASK_CANCEL_TTL_SECS")
+				.ok()
+				.and_then(|value| value.trim().parse::<u64>().ok())
+				.unwrap_or(86400),
+		))
+	}
+
+	/// Registers a cancellation; `false` if the message names neither a task nor a batch
+	pub fn cancel(&self, request: &AITaskCancel) -> bool {
+		let mut targets = Vec::new();
+		if let Some(task_id) = request.task_id {
+			targets.push(CancelTarget::Task(task_id));
+		}
+		if let Some(ref batch_id) = request.batch_id {
+			targets.push(CancelTarget::Batch(batch_id.clone()));
+		}
+		if targets.is_empty() {
+			return false;
+		}
+
+		let cancellation = Cancellation {
+			reason: request
+				.reason
+				.clone()
+				.unwrap_or_else(|| "cancelled by user".to_string()),
+			received: Instant::now(),
+		};
+
+		let mut entries = self.entries.lock().unwrap();
+		entries.retain(|_, entry| entry.received.elapsed() < self.ttl);
+		for target in targets {
+			entries.insert(target, cancellation.clone());
+		}
+		drop(entries);
+
+		self.notify.notify_waiters();
+		true
+	}
+
+	/// Why the task was cancelled, by its own id or by its `batch_id` parameter
+	pub fn reason(&self, task: &AIProcessingTask) -> Option<String> {
+		let entries = self.entries.lock().unwrap();
+		let batch_target = task
+			.request_data
+			.parameters
+			.get("batch_id")
+			.and_then(|value| value.as_str())
+			.map(|batch_id| CancelTarget::Batch(batch_id.to_string()));
+
+		std::iter::once(CancelTarget::Task(task.task_id))
+			.chain(batch_target)
+			.filter_map(|target| entries.get(&target))
+			.find(|entry| entry.received.elapsed() < self.ttl)
+			.map(|entry| entry.reason.clone())
+	}
+
+	/// Resolves with the reason once the task is cancelled
+	pub async fn cancelled(&self, task: &AIProcessingTask) -> String {
+		loop {
+			let notified = self.notify.notified();
+			tokio::pin!(notified);
+			// Registered before the check, so a cancellation in between is not missed
+			notified.as_mut().enable();
+
+			if let Some(reason) = self.reason(task) {
+				return reason;
+			}
+			notified.await;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+
+	fn task(batch_id: Option<&str>) -> AIProcessingTask {
+		let mut parameters = serde_json::json!({"feed_id": Uuid::new_v4()});
+		if let Some(batch_id) = batch_id {
+			parameters["batch_id"] = serde_json::json!(batch_id);
+		}
+		serde_json::from_value(serde_json::json!({
+			"task_id": Uuid::new_v4(),
+			"created_at": "2024-05-01T00:00:00Z",
+			"request_data": {
+				"request_id": Uuid::new_v4(),
+				"user_id": Uuid::new_v4(),
+				"processing_type": "keyword_extraction",
+				"parameters": parameters
+			}
+		}))
+		.unwrap()
+	}
+
+	#[test]
+	fn cancel_before_start_is_remembered() {
+		let registry = CancellationRegistry::new(Duration::from_secs(60));
+		let queued = task(None);
+		let batched = task(Some("feed-42"));
+		let other = task(Some("feed-7"));
+
+		assert!(registry.cancel(&AITaskCancel {
+			task_id: Some(queued.task_id),
+			..AITaskCancel::default()
+		}));
+		assert!(registry.cancel(&AITaskCancel {
+			batch_id: Some("feed-42".to_string()),
+			reason: Some("feed removed".to_string()),
+			..AITaskCancel::default()
+		}));
+
+		assert_eq!(registry.reason(&queued).unwrap(), "cancelled by user");
+		assert_eq!(registry.reason(&batched).unwrap(), "feed removed");
+		assert_eq!(registry.reason(&other), None);
+	}
+
+	#[test]
+	fn cancel_without_ids_is_ignored() {
+		let registry = CancellationRegistry::new(Duration::from_secs(60));
+
+		assert!(!registry.cancel(&AITaskCancel {
+			reason: Some("no target".to_string()),
+			..AITaskCancel::default()
+		}));
+		assert!(registry.entries.lock().unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn waiting_task_wakes_on_cancel() {
+		let registry = Arc::new(CancellationRegistry::new(Duration::from_secs(60)));
+		let running = task(Some("feed-42"));
+		let task_id = running.task_id;
+
+		let waiter = {
+			let registry = registry.clone();
+			tokio::spawn(async move { registry.cancelled(&running).await })
+		};
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		assert!(!waiter.is_finished());
+
+		// Another task's cancellation wakes the waiter, which keeps waiting
+		registry.cancel(&AITaskCancel {
+			task_id: Some(Uuid::new_v4()),
+			..AITaskCancel::default()
+		});
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		assert!(!waiter.is_finished());
+
+		registry.cancel(&AITaskCancel {
+			task_id: Some(task_id),
+			reason: Some("stopped".to_string()),
+			..AITaskCancel::default()
+		});
+		let reason = tokio::time::timeout(Duration::from_millis(500), waiter)
+			.await
+			.expect("the waiter is woken by the cancellation")
+			.unwrap();
+		assert_eq!(reason, "stopped");
+	}
+
+	#[tokio::test]
+	async fn expired_entries_are_ignored_and_purged() {
+		let registry = CancellationRegistry::new(Duration::from_millis(50));
+		let old = task(None);
+
+		registry.cancel(&AITaskCancel {
+			task_id: Some(old.task_id),
+			..AITaskCancel::default()
+		});
+		assert!(registry.reason(&old).is_some());
+
+		tokio::time::sleep(Duration::from_millis(80)).await;
+		assert_eq!(registry.reason(&old), None);
+
+		// The next cancellation drops the expired entry
+		registry.cancel(&AITaskCancel {
+			batch_id: Some("feed-42".to_string()),
+			..AITaskCancel::default()
+		});
+		let entries = registry.entries.lock().unwrap();
+		assert_eq!(entries.len(), 1);
+		assert!(!entries.contains_key(&CancelTarget::Task(old.task_id)));
+	}
+}
@@ -0,0 +1,489 @@
+use lapin::{
+	message::Delivery,
+	options::*,
+	publisher_confirm::Confirmation,
+	types::{AMQPValue, FieldTable, LongString, ShortString},
+	BasicProperties, Channel,
+};
+use std::env;
+use std::error::Error;
+use tokio::time::Duration;
+
+pub const RETRY_COUNT_HEADER: &str = "x-retry-count";
+
+/// What happens to deliveries that cannot be handled.
+///
+/// Unparseable messages go straight to the dead-letter exchange, handler failures
+/// wait in a retry queue and go back to the queue up to `max_retries` times,
+/// then they are dead-lettered. Configured with `RABBITMQ_DLX`, `RABBITMQ_DEAD_LETTER_QUEUE`,
+/// `RABBITMQ_RETRY_QUEUE`, `CONSUMER_MAX_RETRIES` and `CONSUMER_RETRY_DELAY_MS`.
+///
+/// The dead-letter exchange is direct and the dead queue is bound with the queue name,
+/// so consumers of different queues can share an exchange without getting each other's messages.
+/// Each retry gets its own queue with a fixed TTL (`<retry_queue>.<retry>`): RabbitMQ only
+/// expires messages at the head of a queue, so mixed delays in one queue would hold each other up.
+///
+/// Both publish on the consumer channel, which must be in confirm mode:
+/// a delivery is acked only after the broker confirmed its copy.
+#[derive(Debug, Clone)]
+pub struct FailurePolicy {
+	pub queue_name: String,
+	pub dead_letter_exchange: String,
+	pub dead_letter_queue: String,
+	/// Prefix of the retry queues, which hold a message for their TTL,
+	/// then dead-letter it back to the queue
+	pub retry_queue: String,
+	pub max_retries: u32,
+	pub retry_delay: Duration,
+}
+
+impl FailurePolicy {
+	pub fn from_env(queue_name: &str) -> Self {
+		Self {
+			queue_name: queue_name.to_string(),
+			dead_letter_exchange: env::var("RABBITMQ_DLX")
+				.unwrap_or_else(|_| format!("{}.dlx", queue_name)),
+			dead_letter_queue: env::var("RABBITMQ_DEAD_LETTER_QUEUE")
+				.unwrap_or_else(|_| format!("{}.dead", queue_name)),
+			retry_queue: env::var("RABBITMQ_RETRY_QUEUE")
+				.unwrap_or_else(|_| format!("{}.retry", queue_name)),
+			max_retries: env::var("CONSUMER_MAX_RETRIES")
+				.ok()
+				.and_then(|value| value.trim().parse::<u32>().ok())
+				.unwrap_or(3),
+			retry_delay: Duration::from_millis(
+				env::var("CONSUMER_RETRY_DELAY_MS")
+					.ok()
+					.and_then(|value| value.trim().parse::<u64>().ok())
+					.unwrap_or(1000),
+			),
+		}
+	}
+
+	/// Retry queue for the next attempt, or `None` once `max_retries` is used up
+	pub fn retry_queue_for(&self, retries: u32) -> Option<String> {
+		if retries >= self.max_retries {
+			return None;
+		}
+		Some(format!("{}.{}", self.retry_queue, retries + 1))
+	}
+
+	/// Delay of the n-th retry; later retries wait longer
+	pub fn retry_delay_for(&self, retry: u32) -> Duration {
+		self.retry_delay * retry
+	}
+
+	/// Declares the dead-letter exchange, the queue that keeps failed messages
+	/// and the retry queues that return expired messages to `queue_name`
+	pub async fn declare(&self, channel: &Channel) -> Result<(), Box<dyn Error + Send + Sync>> {
+		channel
+			.exchange_declare(
+				&self.dead_letter_exchange,
+				lapin::ExchangeKind::Direct,
+				ExchangeDeclareOptions {
+					durable: true,
+					..ExchangeDeclareOptions::default()
+				},
+				FieldTable::default(),
+			)
+			.await
+			.map_err(|e| -> Box<dyn Error + Send + Sync> {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Dead-letter exchange declaration error: {}", e),
+				))
+			})?;
+
+		channel
+			.queue_declare(
+				&self.dead_letter_queue,
+				QueueDeclareOptions {
+					durable: true,
+					..QueueDeclareOptions::default()
+				},
+				FieldTable::default(),
+			)
+			.await
+			.map_err(|e| -> Box<dyn Error + Send + Sync> {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Dead-letter queue error: {}", e),
+				))
+			})?;
+
+		channel
+			.queue_bind(
+				&self.dead_letter_queue,
+				&self.dead_letter_exchange,
+				&self.queue_name,
+				QueueBindOptions::default(),
+				FieldTable::default(),
+			)
+			.await
+			.map_err(|e| -> Box<dyn Error + Send + Sync> {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Dead-letter queue binding error: {}", e),
+				))
+			})?;
+
+		for retry in 1..=self.max_retries {
+			// Expired messages go through the default exchange straight to the queue,
+			// so other bindings of the original routing key do not get a duplicate
+			let mut arguments = FieldTable::default();
+			insert_string(&mut arguments, "x-dead-letter-exchange", "");
+			insert_string(
+				&mut arguments,
+				"x-dead-letter-routing-key",
+				&self.queue_name,
+			);
+			arguments.insert(
+				ShortString::from("x-message-ttl"),
+				AMQPValue::LongLongInt(self.retry_delay_for(retry).as_millis() as i64),
+			);
+
+			channel
+				.queue_declare(
+					&format!("{}.{}", self.retry_queue, retry),
+					QueueDeclareOptions {
+						durable: true,
+						..QueueDeclareOptions::default()
+					},
+					arguments,
+				)
+				.await
+				.map_err(|e| -> Box<dyn Error + Send + Sync> {
+					Box::new(std::io::Error::new(
+						std::io::ErrorKind::Other,
+						format!("Retry queue error: {}", e),
+					))
+				})?;
+		}
+
+		Ok(())
+	}
+
+	/// Publishes the message to the dead-letter exchange with the error in headers, then acks it
+	pub async fn dead_letter(
+		&self,
+		channel: &Channel,
+		delivery: &Delivery,
+		kind: &str,
+		error: &str,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		eprintln!(
+			"☠️ Dead-lettering message from '{}' ({}): {}",
+			delivery.routing_key.as_str(),
+			kind,
+			error
+		);
+
+		let mut headers = original_headers(delivery);
+		insert_string(&mut headers, "x-error", error);
+		insert_string(&mut headers, "x-error-kind", kind);
+		insert_string(
+			&mut headers,
+			"x-original-exchange",
+			delivery.exchange.as_str(),
+		);
+		insert_string(
+			&mut headers,
+			"x-original-routing-key",
+			delivery.routing_key.as_str(),
+		);
+		insert_string(
+			&mut headers,
+			"x-failed-at",
+			&chrono::Utc::now().to_rfc3339(),
+		);
+
+		self.publish(
+			channel,
+			&self.dead_letter_exchange,
+			&self.queue_name,
+			delivery,
+			headers,
+		)
+		.await?;
+		ack(delivery).await
+	}
+
+	/// Parks the message in the retry queue of its attempt with an incremented retry count; once
+	/// the delay expires it goes back to the end of its queue. Dead-letters it once `max_retries` is reached
+	pub async fn retry_or_dead_letter(
+		&self,
+		channel: &Channel,
+		delivery: &Delivery,
+		error: &str,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		let retries = retry_count(delivery);
+		let retry_queue = match self.retry_queue_for(retries) {
+			Some(retry_queue) => retry_queue,
+			None => {
+				return self
+					.dead_letter(
+						channel,
+						delivery,
+						"handler",
+						&format!("{} (after {} retries)", error, retries),
+					)
+					.await;
+			}
+		};
+
+		let delay = self.retry_delay_for(retries + 1);
+		eprintln!(
+			"🔁 Retrying message in {:?} (retry {}/{}): {}",
+			delay,
+			retries + 1,
+			self.max_retries,
+			error
+		);
+
+		let mut headers = original_headers(delivery);
+		headers.insert(
+			ShortString::from(RETRY_COUNT_HEADER),
+			AMQPValue::LongLongInt((retries + 1) as i64),
+		);
+		insert_string(&mut headers, "x-last-error", error);
+
+		self.publish(channel, "", &retry_queue, delivery, headers)
+			.await?;
+		ack(delivery).await
+	}
+
+	async fn publish(
+		&self,
+		channel: &Channel,
+		exchange: &str,
+		routing_key: &str,
+		delivery: &Delivery,
+		headers: FieldTable,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		let properties = BasicProperties::default()
+			.with_headers(headers)
+			.with_delivery_mode(2);
+		let properties = match delivery.properties.content_type() {
+			Some(content_type) => properties.with_content_type(content_type.clone()),
+			None => properties,
+		};
+		// A retried task keeps its place among higher and lower priority tasks
+		let properties = match delivery.properties.priority() {
+			Some(priority) => properties.with_priority(*priority),
+			None => properties,
+		};
+
+		let confirmation = channel
+			.basic_publish(
+				exchange,
+				routing_key,
+				BasicPublishOptions {
+					mandatory: true,
+					..BasicPublishOptions::default()
+				},
+				&delivery.data,
+				properties,
+			)
+			.await
+			.map_err(|e| -> Box<dyn Error + Send + Sync> {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Publish error: {}", e),
+				))
+			})?
+			.await
+			.map_err(|e| -> Box<dyn Error + Send + Sync> {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Publish confirm error: {}", e),
+				))
+			})?;
+
+		// Without a confirmed copy the delivery stays unacked and comes back after a reconnect
+		match confirmation {
+			Confirmation::Ack(None) => Ok(()),
+			Confirmation::Ack(Some(returned)) | Confirmation::Nack(Some(returned)) => {
+				Err(Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!(
+						"Message to {} returned as unroutable: {} {}",
+						routing_key, returned.reply_code, returned.reply_text
+					),
+				)))
+			}
+			Confirmation::Nack(None) | Confirmation::NotRequested => {
+				Err(Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Broker did not confirm message to {}", routing_key),
+				)))
+			}
+		}
+	}
+}
+
+/// Retries from our own header or from `x-death`, if the broker dead-lettered the message before
+pub fn retry_count(delivery: &Delivery) -> u32 {
+	match delivery.properties.headers() {
+		Some(headers) => retries_in_headers(headers),
+		None => 0,
+	}
+}
+
+fn retries_in_headers(headers: &FieldTable) -> u32 {
+	let headers = headers.inner();
+	let own = headers
+		.get(RETRY_COUNT_HEADER)
+		.and_then(amqp_integer)
+		.unwrap_or(0);
+	let deaths = match headers.get("x-death") {
+		Some(AMQPValue::FieldArray(deaths)) => deaths
+			.as_slice()
+			.iter()
+			.filter_map(|death| match death {
+				AMQPValue::FieldTable(death) => death.inner().get("count").and_then(amqp_integer),
+				_ => None,
+			})
+			.sum(),
+		_ => 0,
+	};
+
+	own.max(deaths).max(0) as u32
+}
+
+fn amqp_integer(value: &AMQPValue) -> Option<i64> {
+	match value {
+		AMQPValue::ShortShortInt(v) => Some(*v as i64),
+		AMQPValue::ShortShortUInt(v) => Some(*v as i64),
+		AMQPValue::ShortInt(v) => Some(*v as i64),
+		AMQPValue::ShortUInt(v) => Some(*v as i64),
+		AMQPValue::LongInt(v) => Some(*v as i64),
+		AMQPValue::LongUInt(v) => Some(*v as i64),
+		AMQPValue::LongLongInt(v) => Some(*v),
+		_ => None,
+	}
+}
+
+fn original_headers(delivery: &Delivery) -> FieldTable {
+	delivery.properties.headers().clone().unwrap_or_default()
+}
+
+fn insert_string(headers: &mut FieldTable, key: &str, value: &str) {
+	headers.insert(
+		ShortString::from(key),
+		AMQPValue::LongString(LongString::from(value)),
+	);
+}
+
+async fn ack(delivery: &Delivery) -> Result<(), Box<dyn Error + Send + Sync>> {
+	delivery
+		.ack(BasicAckOptions::default())
+		.await
+		.map_err(|e| -> Box<dyn Error + Send + Sync> {
+			Box::new(std::io::Error::new(
+				std::io::ErrorKind::Other,
+				format!("Ack error: {}", e),
+			))
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use lapin::types::FieldArray;
+
+	fn policy(max_retries: u32) -> FailurePolicy {
+		FailurePolicy {
+			queue_name: "ai_tasks".to_string(),
+			dead_letter_exchange: "ai_tasks.dlx".to_string(),
+			dead_letter_queue: "ai_tasks.dead".to_string(),
+			retry_queue: "ai_tasks.retry".to_string(),
+			max_retries,
+			retry_delay: Duration::from_millis(1000),
+		}
+	}
+
+	fn death(count: i64) -> AMQPValue {
+		let mut death = FieldTable::default();
+		insert_string(&mut death, "queue", "ai_tasks.retry.1");
+		death.insert(ShortString::from("count"), AMQPValue::LongLongInt(count));
+		AMQPValue::FieldTable(death)
+	}
+
+	fn headers(own: Option<AMQPValue>, deaths: &[i64]) -> FieldTable {
+		let mut headers = FieldTable::default();
+		if let Some(own) = own {
+			headers.insert(ShortString::from(RETRY_COUNT_HEADER), own);
+		}
+		if !deaths.is_empty() {
+			let deaths: Vec<AMQPValue> = deaths.iter().map(|count| death(*count)).collect();
+			headers.insert(
+				ShortString::from("x-death"),
+				AMQPValue::FieldArray(FieldArray::from(deaths)),
+			);
+		}
+		headers
+	}
+
+	#[test]
+	fn counts_retries_from_own_header() {
+		assert_eq!(retries_in_headers(&FieldTable::default()), 0);
+		assert_eq!(
+			retries_in_headers(&headers(Some(AMQPValue::LongLongInt(2)), &[])),
+			2
+		);
+		// Other publishers may use a narrower integer type
+		assert_eq!(
+			retries_in_headers(&headers(Some(AMQPValue::ShortShortUInt(1)), &[])),
+			1
+		);
+		assert_eq!(
+			retries_in_headers(&headers(
+				Some(AMQPValue::LongString(LongString::from("2"))),
+				&[]
+			)),
+			0
+		);
+	}
+
+	#[test]
+	fn counts_retries_from_x_death() {
+		assert_eq!(retries_in_headers(&headers(None, &[1, 2])), 3);
+		assert_eq!(
+			retries_in_headers(&headers(Some(AMQPValue::LongLongInt(-1)), &[])),
+			0
+		);
+	}
+
+	#[test]
+	fn takes_the_larger_of_both_counts() {
+		// Each expiry from a retry queue adds an x-death entry next to our header
+		assert_eq!(
+			retries_in_headers(&headers(Some(AMQPValue::LongLongInt(2)), &[1, 1])),
+			2
+		);
+		assert_eq!(
+			retries_in_headers(&headers(Some(AMQPValue::LongLongInt(1)), &[3])),
+			3
+		);
+	}
+
+	#[test]
+	fn each_retry_has_its_own_queue_until_max_retries() {
+		let policy = policy(3);
+
+		assert_eq!(policy.retry_queue_for(0).unwrap(), "ai_tasks.retry.1");
+		assert_eq!(policy.retry_queue_for(2).unwrap(), "ai_tasks.retry.3");
+		assert_eq!(policy.retry_queue_for(3), None);
+		assert_eq!(policy.retry_queue_for(7), None);
+		assert_eq!(policy.retry_delay_for(3), Duration::from_millis(3000));
+
+		assert_eq!(
+			FailurePolicy {
+				max_retries: 0,
+				..policy
+			}
+			.retry_queue_for(0),
+			None
+		);
+	}
+}
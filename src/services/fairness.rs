@@ -0,0 +1,222 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// Hands out a consumer's handler slots round-robin by `user_id`.
+///
+/// Tasks waiting for a slot are queued per user and a freed slot goes to the next
+/// user in turn, so one user's prefetched tasks cannot take every slot while other
+/// users wait. `max_per_user` (0 for no limit) caps the running tasks of one user.
+///
+/// Only deliveries the consumer already holds can wait here, so `CONSUMER_PREFETCH` must be
+/// larger than the number of slots (`CONSUMER_CONCURRENCY`): with prefetch equal to the slots
+/// every delivery gets a slot at once and tasks run in the order RabbitMQ sends them.
+#[derive(Debug)]
+pub struct FairScheduler {
+	state: Mutex<SchedulerState>,
+	max_per_user: usize,
+}
+
+#[derive(Debug, Default)]
+struct SchedulerState {
+	free: usize,
+	running: HashMap<Uuid, usize>,
+	waiting: HashMap<Uuid, VecDeque<oneshot::Sender<UserSlot>>>,
+	/// Users with waiting tasks, in the order they get their next slot
+	turns: VecDeque<Uuid>,
+}
+
+/// A running task's slot; dropping it passes the slot on to the next user
+#[derive(Debug)]
+pub struct UserSlot {
+	scheduler: Option<Arc<FairScheduler>>,
+	user_id: Uuid,
+}
+
+impl FairScheduler {
+	pub fn new(slots: usize, max_per_user: usize) -> Arc<Self> {
+		Arc::new(Self {
+			state: Mutex::new(SchedulerState {
+				free: slots,
+				..SchedulerState::default()
+			}),
+			max_per_user,
+		})
+	}
+
+	pub async fn acquire(self: &Arc<Self>, user_id: Uuid) -> UserSlot {
+		let (sender, receiver) = oneshot::channel();
+		{
+			let mut state = self.state.lock().unwrap();
+			let queue = state.waiting.entry(user_id).or_default();
+			queue.push_back(sender);
+			if queue.len() == 1 {
+				state.turns.push_back(user_id);
+			}
+			self.dispatch(&mut state);
+		}
+
+		// Senders are only dropped unsent once their receiver is gone
+		receiver
+			.await
+			.expect("waiting tasks are served before their sender is dropped")
+	}
+
+	fn release(self: &Arc<Self>, user_id: Uuid) {
+		let mut state = self.state.lock().unwrap();
+		state.free += 1;
+		if let Some(running) = state.running.get_mut(&user_id) {
+			*running -= 1;
+			if *running == 0 {
+				state.running.remove(&user_id);
+			}
+		}
+		self.dispatch(&mut state);
+	}
+
+	/// Gives free slots to waiting users in turn, skipping users at their limit
+	fn dispatch(self: &Arc<Self>, state: &mut SchedulerState) {
+		let mut skipped = 0;
+
+		while state.free > 0 && skipped < state.turns.len() {
+			let user_id = match state.turns.pop_front() {
+				Some(user_id) => user_id,
+				None => break,
+			};
+
+			let running = state.running.get(&user_id).copied().unwrap_or(0);
+			if self.max_per_user > 0 && running >= self.max_per_user {
+				state.turns.push_back(user_id);
+				skipped += 1;
+				continue;
+			}
+
+			let (sender, more_waiting) = match state.waiting.get_mut(&user_id) {
+				Some(queue) => (queue.pop_front(), !queue.is_empty()),
+				None => (None, false),
+			};
+			if more_waiting {
+				state.turns.push_back(user_id);
+			} else {
+				state.waiting.remove(&user_id);
+			}
+
+			if let Some(sender) = sender {
+				let slot = UserSlot {
+					scheduler: Some(self.clone()),
+					user_id,
+				};
+				match sender.send(slot) {
+					Ok(()) => {
+						state.free -= 1;
+						*state.running.entry(user_id).or_default() += 1;
+					}
+					// The task stopped waiting (shutdown), the slot was never taken
+					Err(mut slot) => slot.scheduler = None,
+				}
+			}
+			skipped = 0;
+		}
+	}
+}
+
+impl Drop for UserSlot {
+	fn drop(&mut self) {
+		if let Some(scheduler) = self.scheduler.take() {
+			scheduler.release(self.user_id);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio::sync::mpsc;
+	use tokio::time::{sleep, timeout, Duration};
+
+	/// Starts a task that waits for a slot, reports `label` and holds the slot for a moment
+	async fn spawn_waiter(
+		scheduler: &Arc<FairScheduler>,
+		user_id: Uuid,
+		label: &'static str,
+		started: &mpsc::UnboundedSender<&'static str>,
+	) {
+		let scheduler = scheduler.clone();
+		let started = started.clone();
+		tokio::spawn(async move {
+			let _slot = scheduler.acquire(user_id).await;
+			started.send(label).unwrap();
+			sleep(Duration::from_millis(10)).await;
+		});
+		// Lets the task register before the next one
+		sleep(Duration::from_millis(10)).await;
+	}
+
+	#[tokio::test]
+	async fn slots_alternate_between_users() {
+		let scheduler = FairScheduler::new(1, 0);
+		let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+		let (started, mut order) = mpsc::unbounded_channel();
+
+		let slot = scheduler.acquire(first).await;
+		spawn_waiter(&scheduler, first, "first 2", &started).await;
+		spawn_waiter(&scheduler, first, "first 3", &started).await;
+		spawn_waiter(&scheduler, second, "second 1", &started).await;
+		spawn_waiter(&scheduler, second, "second 2", &started).await;
+		drop(slot);
+
+		let mut labels = Vec::new();
+		for _ in 0..4 {
+			labels.push(order.recv().await.unwrap());
+		}
+		assert_eq!(labels, vec!["first 2", "second 1", "first 3", "second 2"]);
+	}
+
+	#[tokio::test]
+	async fn user_at_cap_does_not_block_others() {
+		let scheduler = FairScheduler::new(2, 1);
+		let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+		let (started, mut order) = mpsc::unbounded_channel();
+
+		let slot = scheduler.acquire(first).await;
+		spawn_waiter(&scheduler, first, "first 2", &started).await;
+
+		// The free slot goes to the other user, although the first one asked earlier
+		let other = timeout(Duration::from_millis(100), scheduler.acquire(second))
+			.await
+			.expect("a user under the cap gets the free slot");
+		assert!(order.try_recv().is_err());
+
+		drop(slot);
+		assert_eq!(order.recv().await.unwrap(), "first 2");
+		drop(other);
+	}
+
+	#[tokio::test]
+	async fn dropped_waiter_does_not_leak_a_slot() {
+		let scheduler = FairScheduler::new(1, 0);
+
+		let slot = scheduler.acquire(Uuid::new_v4()).await;
+		// Gives up waiting, like a task stopped on shutdown
+		assert!(
+			timeout(Duration::from_millis(20), scheduler.acquire(Uuid::new_v4()))
+				.await
+				.is_err()
+		);
+		drop(slot);
+
+		let slot = timeout(
+			Duration::from_millis(100),
+			scheduler.acquire(Uuid::new_v4()),
+		)
+		.await
+		.expect("the slot of the dropped waiter is free again");
+		drop(slot);
+
+		let state = scheduler.state.lock().unwrap();
+		assert_eq!(state.free, 1);
+		assert!(state.running.is_empty());
+		assert!(state.waiting.is_empty());
+	}
+}
@@ -1,2 +1,12 @@
+pub mod cancellation;
+pub mod dead_letter;
+pub mod fairness;
+pub mod priority;
 pub mod rabbitmq_consumer;
 pub mod rabbitmq_producer;
+pub mod reconnect;
+pub mod result_dispatcher;
+pub mod result_sinks;
+pub mod scheduler;
+pub mod shutdown;
+pub mod topology;
@@ -0,0 +1,84 @@
+use lapin::types::{AMQPValue, FieldTable, ShortString};
+use std::collections::HashMap;
+use std::env;
+
+use crate::models::rabbitmq::AIProcessingTask;
+
+/// Message priorities of tasks, for queues declared with `x-max-priority`.
+///
+/// `RABBITMQ_MAX_PRIORITY` (default 0, priorities off) is declared on every queue the
+/// service uses, `TASK_PRIORITY_<TYPE>` (e.g. `TASK_PRIORITY_TITLE=8`) sets the priority
+/// of one processing type and `TASK_PRIORITY` the one of all other types.
+#[derive(Debug, Clone, Default)]
+pub struct TaskPriorities {
+	pub max_priority: u8,
+	default: u8,
+	per_type: HashMap<String, u8>,
+}
+
+impl TaskPriorities {
+	pub fn new(max_priority: u8, default: u8, per_type: HashMap<String, u8>) -> Self {
+		Self {
+			max_priority,
+			default,
+			per_type,
+		}
+	}
+
+	pub fn from_env() -> Self {
+		let number = |value: &str| value.trim().parse::<u8>().ok();
+		let per_type = env::vars()
+			.filter_map(|(key, value)| {
+				let processing_type = key.strip_prefix("TASK_PRIORITY_")?;
+				Some((processing_type.to_lowercase(), number(&value)?))
+			})
+			.collect();
+
+		Self::new(
+			env::var("RABBITMQ_MAX_PRIORITY")
+				.ok()
+				.and_then(|value| number(&value))
+				.unwrap_or(0),
+			env::var("TASK_PRIORITY")
+				.ok()
+				.and_then(|value| number(&value))
+				.unwrap_or(0),
+			per_type,
+		)
+	}
+
+	/// Arguments for `queue_declare`; a queue keeps them for life, so every declaration must match
+	pub fn queue_arguments(&self) -> FieldTable {
+		let mut arguments = FieldTable::default();
+		if self.max_priority > 0 {
+			arguments.insert(
+				ShortString::from("x-max-priority"),
+				AMQPValue::LongInt(self.max_priority as i32),
+			);
+		}
+		arguments
+	}
+
+	/// A `priority` parameter of the task wins over the priority of its type.
+	/// `None` while priorities are off.
+	pub fn for_task(&self, task: &AIProcessingTask) -> Option<u8> {
+		if self.max_priority == 0 {
+			return None;
+		}
+
+		let priority = task
+			.request_data
+			.parameters
+			.get("priority")
+			.and_then(|value| value.as_u64())
+			.map(|value| value.min(u8::MAX as u64) as u8)
+			.or_else(|| {
+				self.per_type
+					.get(&task.request_data.processing_type)
+					.copied()
+			})
+			.unwrap_or(self.default);
+
+		Some(priority.min(self.max_priority))
+	}
+}
@@ -1,44 +1,212 @@
 use crate::models::rabbitmq::{
-	AIProcessingProgress, AIProcessingResult, AIProcessingTask, AIRequestData,
+	AIProcessingProgress, AIProcessingTask, AITaskCancel, ResultMessage,
 };
-use chrono::{DateTime, Utc};
+use crate::services::cancellation::CancellationRegistry;
+use crate::services::dead_letter::FailurePolicy;
+use crate::services::fairness::{FairScheduler, UserSlot};
+use crate::services::priority::TaskPriorities;
+use crate::services::reconnect::{ConnectionMetrics, ConnectionStats, ReconnectPolicy};
+use crate::services::shutdown::Shutdown;
+use crate::services::topology::{self, Exchange, Topology};
+use futures::future::BoxFuture;
 use futures_util::stream::StreamExt;
-use lapin::{message::Delivery, options::*, types::FieldTable, Connection, ConnectionProperties};
-use serde::{Deserialize, Serialize};
+use lapin::{
+	message::Delivery, options::*, types::FieldTable, Channel, Connection, ConnectionProperties,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, sleep_until, Instant};
 use uuid::Uuid;
 
-#[derive(Debug, Deserialize)]
-struct LegacyTitleTaskFormat {
-	task_id: Uuid,
-	user_id: Uuid,
-	title: String,
-	category: String,
-	created_ts: DateTime<Utc>,
+/// Limits on tasks handled at once by one consumer process.
+///
+/// `CONSUMER_CONCURRENCY` caps all in-flight handlers, `CONSUMER_CONCURRENCY_<TYPE>`
+/// (e.g. `CONSUMER_CONCURRENCY_TITLE=2`) caps a single processing type,
+/// `CONSUMER_MAX_PER_USER` caps the handlers of one `user_id` and
+/// `CONSUMER_PREFETCH` sets how many unacked deliveries RabbitMQ hands out.
+/// Free slots go to waiting users in turn, which only matters when `CONSUMER_PREFETCH`
+/// is above `CONSUMER_CONCURRENCY`, so that deliveries actually wait for a slot.
+#[derive(Debug)]
+pub struct ConcurrencyLimits {
+	pub prefetch: u16,
+	pub max_in_flight: usize,
+	global: Arc<FairScheduler>,
+	per_type: HashMap<String, Arc<Semaphore>>,
+}
+
+/// Held while a handler runs; dropping it frees the slots
+pub struct HandlerPermit {
+	_type_permit: Option<OwnedSemaphorePermit>,
+	_global_permit: UserSlot,
+}
+
+impl ConcurrencyLimits {
+	pub fn new(
+		max_in_flight: usize,
+		prefetch: u16,
+		per_type: HashMap<String, usize>,
+		max_per_user: usize,
+	) -> Self {
+		let max_in_flight = max_in_flight.max(1);
+
+		Self {
+			prefetch: prefetch.max(1),
+			max_in_flight,
+			global: FairScheduler::new(max_in_flight, max_per_user),
+			per_type: per_type
+				.into_iter()
+				.map(|(processing_type, limit)| {
+					(processing_type, Arc::new(Semaphore::new(limit.max(1))))
+				})
+				.collect(),
+		}
+	}
+
+	pub fn from_env() -> Self {
+		let max_in_flight = env::var("CONSUMER_CONCURRENCY")
+			.ok()
+			.and_then(|value| value.trim().parse::<usize>().ok())
+			.unwrap_or(1);
+		let prefetch = env::var("CONSUMER_PREFETCH")
+			.ok()
+			.and_then(|value| value.trim().parse::<u16>().ok())
+			.unwrap_or(max_in_flight.min(u16::MAX as usize) as u16);
+		let per_type = env::vars()
+			.filter_map(|(key, value)| {
+				let processing_type = key.strip_prefix("CONSUMER_CONCURRENCY_")?;
+				Some((
+					processing_type.to_lowercase(),
+					value.trim().parse::<usize>().ok()?,
+				))
+			})
+			.collect();
+		let max_per_user = env::var("CONSUMER_MAX_PER_USER")
+			.ok()
+			.and_then(|value| value.trim().parse::<usize>().ok())
+			.unwrap_or(0);
+
+		Self::new(max_in_flight, prefetch, per_type, max_per_user)
+	}
+
+	/// Waits for a slot of the task's type first, so a busy type does not hold global slots
+	pub async fn acquire(&self, processing_type: &str, user_id: Uuid) -> HandlerPermit {
+		let type_permit = match self.per_type.get(processing_type) {
+			Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+			None => None,
+		};
+		let global_permit = self.global.acquire(user_id).await;
+
+		HandlerPermit {
+			_type_permit: type_permit,
+			_global_permit: global_permit,
+		}
+	}
+}
+
+/// Called for tasks that were still running when the shutdown grace period ran out
+pub type CancelledHook = Arc<dyn Fn(AIProcessingTask) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Everything a spawned delivery handler needs besides the message handler itself
+#[derive(Clone)]
+struct DeliveryContext {
+	limits: Arc<ConcurrencyLimits>,
+	failure_policy: Arc<FailurePolicy>,
+	channel: Channel,
+	shutdown: Shutdown,
+	in_flight: Arc<std::sync::Mutex<HashMap<Uuid, AIProcessingTask>>>,
+	cancellation: Option<Arc<CancellationRegistry>>,
+	cancel_routing_key: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct LegacyDescriptionTaskFormat {
-	task_id: Uuid,
-	user_id: Uuid,
-	description: String,
-	category: String,
-	created_ts: DateTime<Utc>,
+/// One connection to the broker with the channel and the consumer stream on it
+struct Session {
+	connection: Connection,
+	channel: Channel,
+	consumer: lapin::Consumer,
+}
+
+enum SessionEnd {
+	Shutdown,
+	Lost(String),
 }
 
 pub struct RabbitMQConsumer {
 	connection_string: String,
 	queue_name: String,
+	limits: Arc<ConcurrencyLimits>,
+	failure_policy: Arc<FailurePolicy>,
+	shutdown: Shutdown,
+	cancelled_hook: Option<CancelledHook>,
+	cancellation: Option<Arc<CancellationRegistry>>,
+	priorities: TaskPriorities,
+	topology: Topology,
+	reconnect: ReconnectPolicy,
+	metrics: Arc<ConnectionMetrics>,
 }
 
 impl RabbitMQConsumer {
 	pub fn new(connection_string: String, queue_name: String) -> Self {
 		Self {
+			failure_policy: Arc::new(FailurePolicy::from_env(&queue_name)),
 			connection_string,
 			queue_name,
+			limits: Arc::new(ConcurrencyLimits::from_env()),
+			shutdown: Shutdown::never(),
+			cancelled_hook: None,
+			cancellation: None,
+			priorities: TaskPriorities::from_env(),
+			topology: topology::current().clone(),
+			reconnect: ReconnectPolicy::from_env(),
+			metrics: Arc::new(ConnectionMetrics::default()),
 		}
 	}
 
+	pub fn with_limits(mut self, limits: ConcurrencyLimits) -> Self {
+		self.limits = Arc::new(limits);
+		self
+	}
+
+	/// Stop consuming when `shutdown` is requested instead of running until the connection drops
+	pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+		self.shutdown = shutdown;
+		self
+	}
+
+	pub fn on_cancelled<F>(mut self, hook: F) -> Self
+	where
+		F: Fn(AIProcessingTask) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+	{
+		self.cancelled_hook = Some(Arc::new(hook));
+		self
+	}
+
+	/// Fill `registry` from `task.cancel` messages; every worker gets its own queue for them
+	pub fn with_cancellation(mut self, registry: Arc<CancellationRegistry>) -> Self {
+		self.cancellation = Some(registry);
+		self
+	}
+
+	/// `x-max-priority` of the consumed queue; must match the producers' declaration
+	pub fn with_priorities(mut self, priorities: TaskPriorities) -> Self {
+		self.priorities = priorities;
+		self
+	}
+
+	/// Backoff used when the connection cannot be established or is lost
+	pub fn with_reconnect(mut self, reconnect: ReconnectPolicy) -> Self {
+		self.reconnect = reconnect;
+		self
+	}
+
+	pub fn connection_stats(&self) -> ConnectionStats {
+		self.metrics.snapshot()
+	}
+
 	pub async fn start_consuming<F>(
 		&self,
 		message_handler: F,
@@ -51,6 +219,366 @@ impl RabbitMQConsumer {
 			+ Sync
 			+ 'static,
 	{
+		let message_handler = Arc::new(message_handler);
+		let in_flight = Arc::new(std::sync::Mutex::new(HashMap::new()));
+		let mut shutdown = self.shutdown.clone();
+
+		loop {
+			let session = match self
+				.connect_with_backoff(
+					&self.topology.task_exchange,
+					&self.topology.task_bindings,
+					&self.topology.task_consumer_tag,
+					self.limits.prefetch,
+					&mut shutdown,
+				)
+				.await?
+			{
+				Some(session) => session,
+				None => return Ok(()),
+			};
+
+			println!("🚀 Consumer started. Waiting for messages...");
+
+			match self
+				.consume_tasks(session, message_handler.clone(), in_flight.clone())
+				.await
+			{
+				SessionEnd::Shutdown => return Ok(()),
+				SessionEnd::Lost(reason) => self.record_loss(&reason),
+			}
+		}
+	}
+
+	async fn consume_tasks<F>(
+		&self,
+		session: Session,
+		message_handler: Arc<F>,
+		in_flight: Arc<std::sync::Mutex<HashMap<Uuid, AIProcessingTask>>>,
+	) -> SessionEnd
+	where
+		F: Fn(
+				AIProcessingTask,
+			) -> futures::future::BoxFuture<'static, Result<(), Box<dyn Error + Send + Sync>>>
+			+ Send
+			+ Sync
+			+ 'static,
+	{
+		let Session {
+			connection,
+			channel,
+			mut consumer,
+		} = session;
+		let context = DeliveryContext {
+			limits: self.limits.clone(),
+			failure_policy: self.failure_policy.clone(),
+			channel: channel.clone(),
+			shutdown: self.shutdown.clone(),
+			in_flight,
+			cancellation: self.cancellation.clone(),
+			cancel_routing_key: self.topology.cancel_routing_key.clone(),
+		};
+		let mut cancellations = match self.consume_cancellations(&channel).await {
+			Ok(cancellations) => cancellations,
+			Err(e) => {
+				let _ = connection.close(200, "Reconnecting").await;
+				return SessionEnd::Lost(e.to_string());
+			}
+		};
+		let mut shutdown = self.shutdown.clone();
+		let mut deadline: Option<Instant> = None;
+		let mut handlers = JoinSet::new();
+		let mut lost: Option<String> = None;
+
+		// After a shutdown request no new deliveries are taken,
+		// but in-flight handlers are allowed to finish and ack their messages
+		loop {
+			if deadline.is_some() && handlers.is_empty() {
+				break;
+			}
+
+			tokio::select! {
+				_ = shutdown.requested(), if deadline.is_none() => {
+					deadline = Some(Instant::now() + shutdown.grace_period);
+					if let Err(e) = channel
+						.basic_cancel(&self.topology.task_consumer_tag, BasicCancelOptions::default())
+						.await
+					{
+						eprintln!("⚠️ Failed to cancel consumer: {}", e);
+					}
+					println!(
+						"⏳ Waiting up to {:?} for {} in-flight tasks",
+						shutdown.grace_period,
+						handlers.len()
+					);
+				}
+				delivery = consumer.next(), if deadline.is_none() => match delivery {
+					Some(Ok(delivery)) => {
+						println!("📨 Received message");
+						handlers.spawn(Self::handle_delivery(
+							delivery,
+							message_handler.clone(),
+							context.clone(),
+						));
+					}
+					Some(Err(e)) => {
+						lost = Some(format!("Delivery error: {}", e));
+						break;
+					}
+					None => {
+						lost = Some("consumer was cancelled by the broker".to_string());
+						break;
+					}
+				},
+				// Cancellations are still taken while in-flight tasks finish
+				cancel = next_delivery(&mut cancellations) => match cancel {
+					Some(Ok(delivery)) => {
+						if let Some(ref registry) = self.cancellation {
+							Self::register_cancellation(registry, &delivery.data);
+						}
+					}
+					Some(Err(e)) => {
+						lost = Some(format!("Cancellation delivery error: {}", e));
+						break;
+					}
+					None => {
+						lost = Some("cancellation consumer was cancelled by the broker".to_string());
+						break;
+					}
+				},
+				Some(joined) = handlers.join_next() => {
+					let result = joined.unwrap_or_else(|e| {
+						Err(Box::new(std::io::Error::new(
+							std::io::ErrorKind::Other,
+							format!("Handler panicked: {}", e),
+						)) as Box<dyn Error + Send + Sync>)
+					});
+					// Handlers only fail when acking or republishing does, i.e. the channel is gone
+					if let Err(e) = result {
+						lost = Some(format!("Task handler failed: {}", e));
+						break;
+					}
+				}
+				_ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+					// Unacked deliveries of aborted handlers are requeued when the connection closes
+					let unfinished: Vec<AIProcessingTask> = context
+						.in_flight
+						.lock()
+						.unwrap()
+						.drain()
+						.map(|(_, task)| task)
+						.collect();
+					handlers.abort_all();
+					while handlers.join_next().await.is_some() {}
+
+					eprintln!(
+						"⌛ Shutdown grace period is over, {} tasks cancelled",
+						unfinished.len()
+					);
+					if let Some(ref hook) = self.cancelled_hook {
+						for task in unfinished {
+							hook(task).await;
+						}
+					}
+					break;
+				}
+			}
+		}
+
+		if let Some(reason) = lost {
+			// Acks on the dead channel would fail anyway; the broker redelivers these tasks
+			handlers.abort_all();
+			while handlers.join_next().await.is_some() {}
+			context.in_flight.lock().unwrap().clear();
+			let _ = connection.close(200, "Reconnecting").await;
+			return SessionEnd::Lost(reason);
+		}
+
+		if let Err(e) = connection.close(200, "Consumer shutdown").await {
+			eprintln!("⚠️ Failed to close RabbitMQ connection: {}", e);
+		}
+		println!("👋 Consumer stopped");
+		SessionEnd::Shutdown
+	}
+
+	pub async fn start_consuming_results<F>(
+		&self,
+		result_handler: F,
+	) -> Result<(), Box<dyn Error + Send + Sync>>
+	where
+		F: Fn(
+				ResultMessage,
+			) -> futures::future::BoxFuture<'static, Result<(), Box<dyn Error + Send + Sync>>>
+			+ Send
+			+ Sync
+			+ 'static,
+	{
+		let mut shutdown = self.shutdown.clone();
+
+		loop {
+			// Each result is handled before the next one is taken
+			let session = match self
+				.connect_with_backoff(
+					&self.topology.result_exchange,
+					&self.topology.result_bindings,
+					&self.topology.result_consumer_tag,
+					1,
+					&mut shutdown,
+				)
+				.await?
+			{
+				Some(session) => session,
+				None => return Ok(()),
+			};
+
+			println!("🚀 Result consumer started. Waiting for result messages...");
+
+			match self.consume_results(session, &result_handler).await {
+				SessionEnd::Shutdown => return Ok(()),
+				SessionEnd::Lost(reason) => self.record_loss(&reason),
+			}
+		}
+	}
+
+	async fn consume_results<F>(&self, session: Session, result_handler: &F) -> SessionEnd
+	where
+		F: Fn(
+				ResultMessage,
+			) -> futures::future::BoxFuture<'static, Result<(), Box<dyn Error + Send + Sync>>>
+			+ Send
+			+ Sync
+			+ 'static,
+	{
+		let Session {
+			connection,
+			channel,
+			mut consumer,
+		} = session;
+		let mut shutdown = self.shutdown.clone();
+
+		// A result that is being handled is finished before the shutdown takes effect
+		loop {
+			let delivery = tokio::select! {
+				delivery = consumer.next() => delivery,
+				_ = shutdown.requested() => {
+					if let Err(e) = channel
+						.basic_cancel(
+							&self.topology.result_consumer_tag,
+							BasicCancelOptions::default(),
+						)
+						.await
+					{
+						eprintln!("⚠️ Failed to cancel result consumer: {}", e);
+					}
+					break;
+				}
+			};
+
+			let lost = match delivery {
+				Some(Ok(delivery)) => {
+					println!("📨 Received result message");
+					match self
+						.handle_result_delivery(&channel, delivery, result_handler)
+						.await
+					{
+						Ok(()) => continue,
+						Err(e) => format!("Result handler failed: {}", e),
+					}
+				}
+				Some(Err(e)) => format!("Delivery error: {}", e),
+				None => "consumer was cancelled by the broker".to_string(),
+			};
+
+			let _ = connection.close(200, "Reconnecting").await;
+			return SessionEnd::Lost(lost);
+		}
+
+		if let Err(e) = connection.close(200, "Result consumer shutdown").await {
+			eprintln!("⚠️ Failed to close RabbitMQ connection: {}", e);
+		}
+		println!("👋 Result consumer stopped");
+		SessionEnd::Shutdown
+	}
+
+	/// Opens a session, retrying with `ReconnectPolicy` backoff.
+	/// Returns `None` when shutdown is requested while waiting for the broker.
+	async fn connect_with_backoff(
+		&self,
+		exchange: &Exchange,
+		binding_keys: &[String],
+		consumer_tag: &str,
+		prefetch: u16,
+		shutdown: &mut Shutdown,
+	) -> Result<Option<Session>, Box<dyn Error + Send + Sync>> {
+		let mut failed_attempts = 0;
+
+		loop {
+			if shutdown.is_requested() {
+				return Ok(None);
+			}
+
+			match self
+				.open_session(exchange, binding_keys, consumer_tag, prefetch)
+				.await
+			{
+				Ok(session) => {
+					self.metrics.record_connect();
+					if failed_attempts > 0 {
+						println!(
+							"🔌 Connected to RabbitMQ after {} failed attempts",
+							failed_attempts
+						);
+					}
+					return Ok(Some(session));
+				}
+				Err(e) => {
+					failed_attempts += 1;
+					self.metrics.record_failed_attempt();
+
+					if self.reconnect.gives_up(failed_attempts) {
+						return Err(Box::new(std::io::Error::new(
+							std::io::ErrorKind::Other,
+							format!(
+								"Giving up on RabbitMQ after {} attempts: {}",
+								failed_attempts, e
+							),
+						)));
+					}
+
+					let delay = self.reconnect.delay(failed_attempts);
+					eprintln!(
+						"🔌 RabbitMQ connection attempt {} failed: {}; retrying in {:?} ({:?})",
+						failed_attempts,
+						e,
+						delay,
+						self.metrics.snapshot()
+					);
+					tokio::select! {
+						_ = sleep(delay) => {}
+						_ = shutdown.requested() => return Ok(None),
+					}
+				}
+			}
+		}
+	}
+
+	fn record_loss(&self, reason: &str) {
+		self.metrics.record_loss();
+		eprintln!(
+			"🔌 RabbitMQ connection lost: {}; reconnecting ({:?})",
+			reason,
+			self.metrics.snapshot()
+		);
+	}
+
+	/// Connects, declares the topology, binds the queue to `exchange` and starts consuming
+	async fn open_session(
+		&self,
+		exchange: &Exchange,
+		binding_keys: &[String],
+		consumer_tag: &str,
+		prefetch: u16,
+	) -> Result<Session, Box<dyn Error + Send + Sync>> {
 		println!("Connecting to RabbitMQ at: {}", self.connection_string);
 
 		let connection =
@@ -76,26 +604,19 @@ impl RabbitMQConsumer {
 					))
 				})?;
 
-		// Declare exchange
+		// Retried and dead-lettered copies are confirmed before the original is acked
 		channel
-			.exchange_declare(
-				"avito_exchange",
-				lapin::ExchangeKind::Topic,
-				ExchangeDeclareOptions {
-					durable: true,
-					..ExchangeDeclareOptions::default()
-				},
-				FieldTable::default(),
-			)
+			.confirm_select(ConfirmSelectOptions::default())
 			.await
 			.map_err(|e| -> Box<dyn Error + Send + Sync> {
 				Box::new(std::io::Error::new(
 					std::io::ErrorKind::Other,
-					format!("Exchange declaration error: {}", e),
+					format!("Confirm select error: {}", e),
 				))
 			})?;
 
-		// Create the processing tasks queue
+		self.topology.declare(&channel).await?;
+
 		let queue = channel
 			.queue_declare(
 				&self.queue_name,
@@ -105,7 +626,7 @@ impl RabbitMQConsumer {
 					auto_delete: false,
 					..QueueDeclareOptions::default()
 				},
-				FieldTable::default(),
+				self.topology.queue_arguments(&self.priorities),
 			)
 			.await
 			.map_err(|e| -> Box<dyn Error + Send + Sync> {
@@ -115,22 +636,25 @@ impl RabbitMQConsumer {
 				))
 			})?;
 
-		// Bind the queue to the exchange with a pattern to receive AI processing tasks
-		channel
-			.queue_bind(
-				queue.name().as_str(),
-				"avito_exchange",
-				"task.*", // Binding pattern to receive processing tasks
-				QueueBindOptions::default(),
-				FieldTable::default(),
-			)
-			.await
-			.map_err(|e| -> Box<dyn Error + Send + Sync> {
-				Box::new(std::io::Error::new(
-					std::io::ErrorKind::Other,
-					format!("Queue binding error: {}", e),
-				))
-			})?;
+		for binding_key in binding_keys {
+			channel
+				.queue_bind(
+					queue.name().as_str(),
+					&exchange.name,
+					binding_key,
+					QueueBindOptions::default(),
+					FieldTable::default(),
+				)
+				.await
+				.map_err(|e| -> Box<dyn Error + Send + Sync> {
+					Box::new(std::io::Error::new(
+						std::io::ErrorKind::Other,
+						format!("Queue binding error: {}", e),
+					))
+				})?;
+		}
+
+		self.failure_policy.declare(&channel).await?;
 
 		println!(
 			"📋 Queue '{}' declared with {} messages waiting",
@@ -138,9 +662,9 @@ impl RabbitMQConsumer {
 			queue.message_count()
 		);
 
-		// Set up fair dispatch - each consumer processes one message at a time
+		// Fair dispatch: RabbitMQ hands out at most `prefetch` unacked messages
 		channel
-			.basic_qos(1, lapin::options::BasicQosOptions::default())
+			.basic_qos(prefetch, lapin::options::BasicQosOptions::default())
 			.await
 			.map_err(|e| -> Box<dyn Error + Send + Sync> {
 				Box::new(std::io::Error::new(
@@ -149,10 +673,10 @@ impl RabbitMQConsumer {
 				))
 			})?;
 
-		let mut consumer = channel
+		let consumer = channel
 			.basic_consume(
 				queue.name().as_str(),
-				"ai_processing_consumer", // Consumer tag
+				consumer_tag,
 				BasicConsumeOptions::default(),
 				FieldTable::default(),
 			)
@@ -164,91 +688,28 @@ impl RabbitMQConsumer {
 				))
 			})?;
 
-		println!("🚀 Consumer started. Waiting for messages...");
-
-		while let Some(delivery) = consumer.next().await {
-			match delivery {
-				Ok(delivery) => {
-					println!("📨 Received message");
-					self.handle_delivery(delivery, &message_handler).await?;
-				}
-				Err(e) => {
-					eprintln!("❌ Error receiving delivery: {}", e);
-					return Err(Box::new(std::io::Error::new(
-						std::io::ErrorKind::Other,
-						format!("Delivery error: {}", e),
-					)));
-				}
-			}
-		}
-
-		Ok(())
+		Ok(Session {
+			connection,
+			channel,
+			consumer,
+		})
 	}
 
-	pub async fn start_consuming_results<F>(
+	/// Exclusive queue of this worker bound to the cancel routing key, so every worker sees every cancellation
+	async fn consume_cancellations(
 		&self,
-		result_handler: F,
-	) -> Result<(), Box<dyn Error + Send + Sync>>
-	where
-		F: Fn(
-				AIProcessingResult,
-			) -> futures::future::BoxFuture<'static, Result<(), Box<dyn Error + Send + Sync>>>
-			+ Send
-			+ Sync
-			+ 'static,
-	{
-		println!("Connecting to RabbitMQ at: {}", self.connection_string);
-
-		let connection =
-			Connection::connect(&self.connection_string, ConnectionProperties::default())
-				.await
-				.map_err(|e| -> Box<dyn Error + Send + Sync> {
-					Box::new(std::io::Error::new(
-						std::io::ErrorKind::Other,
-						format!("Connection error: {}", e),
-					))
-				})?;
-
-		println!("✅ Connected to RabbitMQ for results");
-
-		let channel =
-			connection
-				.create_channel()
-				.await
-				.map_err(|e| -> Box<dyn Error + Send + Sync> {
-					Box::new(std::io::Error::new(
-						std::io::ErrorKind::Other,
-						format!("Channel error: {}", e),
-					))
-				})?;
-
-		// Declare exchange
-		channel
-			.exchange_declare(
-				"avito_exchange",
-				lapin::ExchangeKind::Topic,
-				ExchangeDeclareOptions {
-					durable: true,
-					..ExchangeDeclareOptions::default()
-				},
-				FieldTable::default(),
-			)
-			.await
-			.map_err(|e| -> Box<dyn Error + Send + Sync> {
-				Box::new(std::io::Error::new(
-					std::io::ErrorKind::Other,
-					format!("Exchange declaration error: {}", e),
-				))
-			})?;
+		channel: &Channel,
+	) -> Result<Option<lapin::Consumer>, Box<dyn Error + Send + Sync>> {
+		if self.cancellation.is_none() {
+			return Ok(None);
+		}
 
-		// Create the results queue
 		let queue = channel
 			.queue_declare(
-				&self.queue_name,
+				"",
 				QueueDeclareOptions {
-					durable: true,
-					exclusive: false,
-					auto_delete: false,
+					exclusive: true,
+					auto_delete: true,
 					..QueueDeclareOptions::default()
 				},
 				FieldTable::default(),
@@ -257,16 +718,15 @@ impl RabbitMQConsumer {
 			.map_err(|e| -> Box<dyn Error + Send + Sync> {
 				Box::new(std::io::Error::new(
 					std::io::ErrorKind::Other,
-					format!("Queue error: {}", e),
+					format!("Cancellation queue error: {}", e),
 				))
 			})?;
 
-		// Bind the queue to the exchange with a pattern to receive AI processing results
 		channel
 			.queue_bind(
 				queue.name().as_str(),
-				"avito_exchange",
-				"result.*", // Binding pattern to receive processing results
+				&self.topology.task_exchange.name,
+				&self.topology.cancel_routing_key,
 				QueueBindOptions::default(),
 				FieldTable::default(),
 			)
@@ -274,68 +734,51 @@ impl RabbitMQConsumer {
 			.map_err(|e| -> Box<dyn Error + Send + Sync> {
 				Box::new(std::io::Error::new(
 					std::io::ErrorKind::Other,
-					format!("Queue binding error: {}", e),
-				))
-			})?;
-
-		println!(
-			"📋 Results queue '{}' declared with {} messages waiting",
-			queue.name(),
-			queue.message_count()
-		);
-
-		// Set up fair dispatch - each consumer processes one message at a time
-		channel
-			.basic_qos(1, lapin::options::BasicQosOptions::default())
-			.await
-			.map_err(|e| -> Box<dyn Error + Send + Sync> {
-				Box::new(std::io::Error::new(
-					std::io::ErrorKind::Other,
-					format!("QoS error: {}", e),
+					format!("Cancellation queue binding error: {}", e),
 				))
 			})?;
 
-		let mut consumer = channel
+		let consumer = channel
 			.basic_consume(
 				queue.name().as_str(),
-				"ai_processing_result_consumer", // Consumer tag
-				BasicConsumeOptions::default(),
+				&self.topology.cancel_consumer_tag,
+				BasicConsumeOptions {
+					no_ack: true,
+					..BasicConsumeOptions::default()
+				},
 				FieldTable::default(),
 			)
 			.await
 			.map_err(|e| -> Box<dyn Error + Send + Sync> {
 				Box::new(std::io::Error::new(
 					std::io::ErrorKind::Other,
-					format!("Consumer error: {}", e),
+					format!("Cancellation consumer error: {}", e),
 				))
 			})?;
 
-		println!("🚀 Result consumer started. Waiting for result messages...");
+		Ok(Some(consumer))
+	}
 
-		while let Some(delivery) = consumer.next().await {
-			match delivery {
-				Ok(delivery) => {
-					println!("📨 Received result message");
-					self.handle_result_delivery(delivery, &result_handler)
-						.await?;
-				}
-				Err(e) => {
-					eprintln!("❌ Error receiving result delivery: {}", e);
-					return Err(Box::new(std::io::Error::new(
-						std::io::ErrorKind::Other,
-						format!("Delivery error: {}", e),
-					)));
+	fn register_cancellation(registry: &CancellationRegistry, data: &[u8]) {
+		match serde_json::from_slice::<AITaskCancel>(data) {
+			Ok(request) => {
+				if registry.cancel(&request) {
+					println!(
+						"🚫 Cancellation received: task {:?}, batch {:?}",
+						request.task_id, request.batch_id
+					);
+				} else {
+					eprintln!("⚠️ Cancellation without task_id or batch_id ignored");
 				}
 			}
+			Err(e) => eprintln!("⚠️ Invalid cancellation message: {}", e),
 		}
-
-		Ok(())
 	}
 
 	async fn handle_delivery<F>(
-		&self,
 		delivery: Delivery,
-		message_handler: &F,
+		message_handler: Arc<F>,
+		context: DeliveryContext,
 	) -> Result<(), Box<dyn Error + Send + Sync>>
 	where
 		F: Fn(
@@ -345,77 +788,73 @@ impl RabbitMQConsumer {
 			+ Sync
 			+ 'static,
 	{
-		let message_str =
-			std::str::from_utf8(&delivery.data).map_err(|e| -> Box<dyn Error + Send + Sync> {
-				Box::new(std::io::Error::new(
-					std::io::ErrorKind::Other,
-					format!("UTF8 error: {}", e),
-				))
-			})?;
-
-		println!("Raw message: {}", message_str);
+		// Task bindings may match cancellations too; the copy in the shared queue is only acked
+		if delivery.routing_key.as_str() == context.cancel_routing_key {
+			if let Some(ref registry) = context.cancellation {
+				Self::register_cancellation(registry, &delivery.data);
+			}
+			return delivery.ack(BasicAckOptions::default()).await.map_err(
+				|e| -> Box<dyn Error + Send + Sync> {
+					Box::new(std::io::Error::new(
+						std::io::ErrorKind::Other,
+						format!("Ack error: {}", e),
+					))
+				},
+			);
+		}
 
-		// Try to parse as AIProcessingTask first
-		let task: AIProcessingTask = match serde_json::from_str::<AIProcessingTask>(message_str) {
+		let task = match Self::parse_task(&delivery.data) {
 			Ok(task) => task,
-			Err(_) => {
-				// If that fails, try to parse as the legacy title format and convert it
-				match serde_json::from_str::<LegacyTitleTaskFormat>(message_str) {
-					Ok(legacy_task) => {
-						// Convert the legacy format to the new AIProcessingTask format
-						AIProcessingTask {
-							task_id: legacy_task.task_id,
-							request_data: AIRequestData {
-								request_id: legacy_task.task_id, // Use task_id as request_id for compatibility
-								user_id: legacy_task.user_id,
-								processing_type: "title".to_string(), // Default to title processing
-								parameters: serde_json::json!({
-									"title": legacy_task.title,
-									"category": legacy_task.category,
-									"created_ts": legacy_task.created_ts
-								}),
-							},
-							created_at: legacy_task.created_ts.to_rfc3339(),
-						}
-					}
-					Err(_) => {
-						// If that fails, try to parse as the legacy description format and convert it
-						match serde_json::from_str::<LegacyDescriptionTaskFormat>(message_str) {
-							Ok(legacy_desc_task) => {
-								// Convert the legacy format to the new AIProcessingTask format
-								AIProcessingTask {
-									task_id: legacy_desc_task.task_id,
-									request_data: AIRequestData {
-										request_id: legacy_desc_task.task_id, // Use task_id as request_id for compatibility
-										user_id: legacy_desc_task.user_id,
-										processing_type: "description".to_string(), // Set to description processing
-										parameters: serde_json::json!({
-											"description": legacy_desc_task.description,
-											"category": legacy_desc_task.category,
-											"created_ts": legacy_desc_task.created_ts
-										}),
-									},
-									created_at: legacy_desc_task.created_ts.to_rfc3339(),
-								}
-							}
-							Err(e) => {
-								eprintln!("Failed to parse message as AIProcessingTask, legacy title format, or legacy description format: {}", e);
-								eprintln!("Message content: {}", message_str);
-								return Err(Box::new(std::io::Error::new(
-									std::io::ErrorKind::Other,
-									format!("Parse error: {}", e),
-								)));
-							}
-						}
-					}
-				}
+			Err(e) => {
+				return context
+					.failure_policy
+					.dead_letter(&context.channel, &delivery, "parse", &e.to_string())
+					.await;
 			}
 		};
 
 		println!("🎯 Parsed AI processing task: {}", task.task_id);
-
-		// Process the message
-		message_handler(task).await?;
+		let task_id = task.task_id;
+		context
+			.in_flight
+			.lock()
+			.unwrap()
+			.insert(task_id, task.clone());
+
+		// Process the message once a slot for its type is free; a task that has
+		// not started before shutdown goes back to the queue untouched
+		let mut shutdown = context.shutdown.clone();
+		let permit = tokio::select! {
+			permit = context
+				.limits
+				.acquire(&task.request_data.processing_type, task.request_data.user_id) => permit,
+			_ = shutdown.requested() => {
+				context.in_flight.lock().unwrap().remove(&task_id);
+				return delivery
+					.nack(BasicNackOptions {
+						requeue: true,
+						..BasicNackOptions::default()
+					})
+					.await
+					.map_err(|e| -> Box<dyn Error + Send + Sync> {
+						Box::new(std::io::Error::new(
+							std::io::ErrorKind::Other,
+							format!("Nack error: {}", e),
+						))
+					});
+			}
+		};
+		let result = message_handler(task).await;
+		drop(permit);
+		context.in_flight.lock().unwrap().remove(&task_id);
+
+		if let Err(e) = result {
+			eprintln!("❌ Task {} failed: {}", task_id, e);
+			return context
+				.failure_policy
+				.retry_or_dead_letter(&context.channel, &delivery, &e.to_string())
+				.await;
+		}
 
 		// Acknowledge the message
 		delivery.ack(BasicAckOptions::default()).await.map_err(
@@ -431,50 +870,64 @@ impl RabbitMQConsumer {
 		Ok(())
 	}
 
+	fn parse_task(data: &[u8]) -> Result<AIProcessingTask, Box<dyn Error + Send + Sync>> {
+		println!("Raw message: {}", String::from_utf8_lossy(data));
+
+		AIProcessingTask::from_message(data).map_err(|e| {
+			eprintln!("Failed to parse task message: {}", e);
+			e
+		})
+	}
+
 	async fn handle_result_delivery<F>(
 		&self,
+		channel: &Channel,
 		delivery: Delivery,
 		result_handler: &F,
 	) -> Result<(), Box<dyn Error + Send + Sync>>
 	where
 		F: Fn(
-				AIProcessingResult,
+				ResultMessage,
 			) -> futures::future::BoxFuture<'static, Result<(), Box<dyn Error + Send + Sync>>>
 			+ Send
 			+ Sync
 			+ 'static,
 	{
-		let message_str =
-			std::str::from_utf8(&delivery.data).map_err(|e| -> Box<dyn Error + Send + Sync> {
-				Box::new(std::io::Error::new(
-					std::io::ErrorKind::Other,
-					format!("UTF8 error: {}", e),
-				))
-			})?;
+		let message_str = match std::str::from_utf8(&delivery.data) {
+			Ok(message_str) => message_str,
+			Err(e) => {
+				return self
+					.failure_policy
+					.dead_letter(channel, &delivery, "parse", &format!("UTF8 error: {}", e))
+					.await;
+			}
+		};
 
 		println!("Raw result message: {}", message_str);
 
-		// Parse as AIProcessingResult
-		let result: AIProcessingResult =
-			match serde_json::from_str::<AIProcessingResult>(message_str) {
-				Ok(result) => result,
-				Err(e) => {
-					eprintln!(
-						"Failed to parse result message as AIProcessingResult: {}",
-						e
-					);
-					eprintln!("Message content: {}", message_str);
-					return Err(Box::new(std::io::Error::new(
-						std::io::ErrorKind::Other,
-						format!("Parse error: {}", e),
-					)));
-				}
-			};
+		// A final result or a progress update
+		let result = match ResultMessage::from_message(message_str.as_bytes()) {
+			Ok(result) => result,
+			Err(e) => {
+				eprintln!("Failed to parse result message: {}", e);
+				eprintln!("Message content: {}", message_str);
+				return self
+					.failure_policy
+					.dead_letter(channel, &delivery, "parse", &e.to_string())
+					.await;
+			}
+		};
 
-		println!("🎯 Parsed AI processing result: {}", result.task_id);
+		println!("🎯 Parsed AI processing result: {}", result.task_id());
 
-		// Process the result
-		result_handler(result).await?;
+		// Acked only once the handler has stored the result
+		if let Err(e) = result_handler(result).await {
+			eprintln!("❌ Result handler failed: {}", e);
+			return self
+				.failure_policy
+				.retry_or_dead_letter(channel, &delivery, &e.to_string())
+				.await;
+		}
 
 		// Acknowledge the message
 		delivery.ack(BasicAckOptions::default()).await.map_err(
@@ -490,3 +943,12 @@ impl RabbitMQConsumer {
 		Ok(())
 	}
 }
+
+async fn next_delivery(
+	consumer: &mut Option<lapin::Consumer>,
+) -> Option<Result<Delivery, lapin::Error>> {
+	match consumer {
+		Some(consumer) => consumer.next().await,
+		None => std::future::pending().await,
+	}
+}
@@ -0,0 +1,106 @@
+use serde::Serialize;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::time::Duration;
+use uuid::Uuid;
+
+/// Backoff between attempts to (re)connect the consumer to RabbitMQ.
+///
+/// Configured with `RABBITMQ_RECONNECT_BASE_DELAY_MS` (default 1000),
+/// `RABBITMQ_RECONNECT_MAX_DELAY_MS` (default 30000) and
+/// `RABBITMQ_RECONNECT_MAX_ATTEMPTS` (default 0, keep trying forever).
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+	pub base_delay: Duration,
+	pub max_delay: Duration,
+	/// Failed attempts in a row before the consumer gives up; 0 means never
+	pub max_attempts: u32,
+}
+
+impl Default for ReconnectPolicy {
+	fn default() -> Self {
+		Self {
+			base_delay: Duration::from_secs(1),
+			max_delay: Duration::from_secs(30),
+			max_attempts: 0,
+		}
+	}
+}
+
+impl ReconnectPolicy {
+	pub fn from_env() -> Self {
+		let default = Self::default();
+		let number = |key: &str| {
+			env::var(key)
+				.ok()
+				.and_then(|value| value.trim().parse::<u64>().ok())
+		};
+
+		Self {
+			base_delay: number("RABBITMQ_RECONNECT_BASE_DELAY_MS")
+				.map(Duration::from_millis)
+				.unwrap_or(default.base_delay),
+			max_delay: number("RABBITMQ_RECONNECT_MAX_DELAY_MS")
+				.map(Duration::from_millis)
+				.unwrap_or(default.max_delay),
+			max_attempts: number("RABBITMQ_RECONNECT_MAX_ATTEMPTS")
+				.map(|value| value as u32)
+				.unwrap_or(default.max_attempts),
+		}
+	}
+
+	pub fn gives_up(&self, failed_attempts: u32) -> bool {
+		self.max_attempts > 0 && failed_attempts >= self.max_attempts
+	}
+
+	/// Exponential delay with jitter, so restarted workers do not reconnect in lockstep
+	pub fn delay(&self, attempt: u32) -> Duration {
+		let exponential = self
+			.base_delay
+			.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+			.min(self.max_delay);
+		let half = exponential / 2;
+		let jitter_ms = half.as_millis() as u64;
+		let random = (Uuid::new_v4().as_u128() as u64) % (jitter_ms + 1);
+
+		half + Duration::from_millis(random)
+	}
+}
+
+/// Connection counters of one consumer; printed with every reconnect
+#[derive(Debug, Default)]
+pub struct ConnectionMetrics {
+	connects: AtomicU64,
+	losses: AtomicU64,
+	failed_attempts: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ConnectionStats {
+	/// Successful connections, the first one included
+	pub connects: u64,
+	pub losses: u64,
+	pub failed_attempts: u64,
+}
+
+impl ConnectionMetrics {
+	pub fn record_connect(&self) {
+		self.connects.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn record_loss(&self) {
+		self.losses.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn record_failed_attempt(&self) {
+		self.failed_attempts.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn snapshot(&self) -> ConnectionStats {
+		ConnectionStats {
+			connects: self.connects.load(Ordering::Relaxed),
+			losses: self.losses.load(Ordering::Relaxed),
+			failed_attempts: self.failed_attempts.load(Ordering::Relaxed),
+		}
+	}
+}
@@ -0,0 +1,190 @@
+use chrono::Utc;
+use futures::future::join_all;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio::time::{sleep, Duration};
+
+use crate::models::rabbitmq::ResultMessage;
+use crate::models::{AiResult, AiResultDelivery, SaveAiResult};
+use crate::services::result_sinks::ResultSink;
+use crate::services::shutdown::Shutdown;
+
+/// Stores results and progress updates in `ai_results` and forwards them to the sinks.
+///
+/// Every stored message gets a row in `ai_result_deliveries` per sink, so forwarding survives
+/// restarts and sink outages. A failed delivery is retried after `RESULT_SINK_RETRY_SECS`
+/// (default 5), doubling up to an hour, and given up after `RESULT_SINK_MAX_ATTEMPTS` (default 10).
+/// Deliveries are checked when a message is stored and every `RESULT_DISPATCH_POLL_SECS` (default 5).
+pub struct ResultDispatcher {
+	pool: Pool<Postgres>,
+	sinks: HashMap<String, Arc<dyn ResultSink>>,
+	stored: Notify,
+	poll_interval: Duration,
+	batch_size: i64,
+	lease_secs: i64,
+	max_attempts: i32,
+	retry_base_secs: i64,
+}
+
+impl ResultDispatcher {
+	pub fn new(pool: Pool<Postgres>, sinks: Vec<Arc<dyn ResultSink>>) -> Self {
+		let number = |key: &str, default: u64| {
+			env::var(key)
+				.ok()
+				.and_then(|value| value.trim().parse::<u64>().ok())
+				.unwrap_or(default)
+		};
+
+		Self {
+			pool,
+			sinks: sinks
+				.into_iter()
+				.map(|sink| (sink.name().to_string(), sink))
+				.collect(),
+			stored: Notify::new(),
+			poll_interval: Duration::from_secs(number("RESULT_DISPATCH_POLL_SECS", 5).max(1)),
+			batch_size: 100,
+			// Longer than a webhook may take, so a slow delivery is not sent twice
+			lease_secs: 60,
+			max_attempts: number("RESULT_SINK_MAX_ATTEMPTS", 10).max(1) as i32,
+			retry_base_secs: number("RESULT_SINK_RETRY_SECS", 5).max(1) as i64,
+		}
+	}
+
+	pub fn sink_names(&self) -> Vec<String> {
+		let mut names = self.sinks.keys().cloned().collect::<Vec<String>>();
+		names.sort();
+		names
+	}
+
+	/// Stores the message durably; the caller acks it after this succeeds.
+	/// Returns `false` for a message that was already stored
+	pub async fn store(
+		&self,
+		message: &ResultMessage,
+	) -> Result<bool, Box<dyn Error + Send + Sync>> {
+		let stored =
+			AiResult::save_result(&self.pool, SaveAiResult::from(message), &self.sink_names())
+				.await?
+				.is_some();
+
+		if stored && !self.sinks.is_empty() {
+			self.stored.notify_one();
+		}
+
+		Ok(stored)
+	}
+
+	pub async fn run(&self, mut shutdown: Shutdown) {
+		if self.sinks.is_empty() {
+			return;
+		}
+		println!("📤 Forwarding results to: {}", self.sink_names().join(", "));
+
+		loop {
+			// A full batch means more deliveries may be due already
+			match self.deliver_due().await {
+				Ok(claimed) if claimed as i64 >= self.batch_size => continue,
+				Ok(_) => {}
+				Err(e) => eprintln!("⚠️ Failed to forward results: {}", e),
+			}
+
+			tokio::select! {
+				_ = sleep(self.poll_interval) => {}
+				_ = self.stored.notified() => {}
+				_ = shutdown.requested() => break,
+			}
+		}
+	}
+
+	/// Delivers the due messages once; returns how many deliveries were taken
+	pub async fn deliver_due(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+		let deliveries =
+			AiResultDelivery::claim_due_deliveries(&self.pool, self.batch_size, self.lease_secs)
+				.await?;
+
+		// Sinks run side by side, each gets its messages one by one in the order they were stored
+		let mut by_sink: HashMap<&str, Vec<&AiResultDelivery>> = HashMap::new();
+		for delivery in &deliveries {
+			by_sink.entry(&delivery.sink).or_default().push(delivery);
+		}
+		let outcomes = join_all(by_sink.into_values().map(|mut queue| async move {
+			queue.sort_by_key(|delivery| delivery.result_id);
+			let mut outcomes = Vec::new();
+			for delivery in queue {
+				outcomes.push((delivery, self.deliver(delivery).await));
+			}
+			outcomes
+		}))
+		.await;
+
+		for (delivery, outcome) in outcomes.into_iter().flatten() {
+			match outcome {
+				Ok(()) => {
+					AiResultDelivery::mark_delivered(&self.pool, delivery.result_id, &delivery.sink)
+						.await?
+				}
+				Err(e) => {
+					let next_attempt_ts = if delivery.attempts < self.max_attempts {
+						Some(
+							Utc::now()
+								+ chrono::Duration::seconds(self.retry_delay(delivery.attempts)),
+						)
+					} else {
+						None
+					};
+					eprintln!(
+						"⚠️ Result {} not delivered to {} (attempt {}): {}{}",
+						delivery.result_id,
+						delivery.sink,
+						delivery.attempts,
+						e,
+						if next_attempt_ts.is_none() {
+							", giving up"
+						} else {
+							""
+						}
+					);
+					AiResultDelivery::mark_failed(
+						&self.pool,
+						delivery.result_id,
+						&delivery.sink,
+						&e.to_string(),
+						next_attempt_ts,
+					)
+					.await?;
+				}
+			}
+		}
+
+		Ok(deliveries.len())
+	}
+
+	async fn deliver(
+		&self,
+		delivery: &AiResultDelivery,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		// A sink removed from RESULT_SINKS fails until its deliveries are given up
+		let sink = self.sinks.get(&delivery.sink).ok_or_else(|| {
+			std::io::Error::new(
+				std::io::ErrorKind::Other,
+				format!("Sink {} is not configured", delivery.sink),
+			)
+		})?;
+		let message = ResultMessage::from_message(delivery.message.as_bytes())?;
+
+		sink.deliver(&message).await
+	}
+
+	/// Seconds until the next attempt: the base delay doubled per failed attempt, at most an hour
+	fn retry_delay(&self, attempts: i32) -> i64 {
+		let doublings = (attempts - 1).clamp(0, 20) as u32;
+		self.retry_base_secs
+			.saturating_mul(1 << doublings)
+			.min(3600)
+	}
+}
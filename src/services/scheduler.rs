@@ -0,0 +1,205 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use std::env;
+use std::error::Error;
+use tokio::time::{sleep, Duration};
+use uuid::Uuid;
+
+use crate::models::rabbitmq::{AIProcessingTask, AIRequestData, MESSAGE_SCHEMA_VERSION};
+use crate::models::AiTaskSchedule;
+use crate::services::rabbitmq_producer::RabbitMQProducer;
+use crate::services::shutdown::Shutdown;
+use crate::utils::CronSchedule;
+
+const RECORD_RUN_ATTEMPTS: u32 = 3;
+
+/// When a task with `run_at` or `cron` runs first; `None` if it should run right away.
+/// `cron` repeats the task starting from `run_at`, if that is given too.
+pub fn first_run(
+	task: &AIProcessingTask,
+	now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, String> {
+	let start = task.run_at.filter(|run_at| *run_at > now);
+
+	match task.cron {
+		Some(ref cron) => {
+			let schedule = CronSchedule::parse(cron)?;
+			// The minute before `run_at`, so a cron time equal to `run_at` counts
+			let after = start.map_or(now, |start| start - chrono::Duration::minutes(1));
+			schedule
+				.next_after(after)
+				.map(Some)
+				.ok_or_else(|| format!("'{}' never runs", cron))
+		}
+		None => Ok(start),
+	}
+}
+
+/// Publishes scheduled tasks from `ai_task_schedules` when they are due.
+///
+/// Polls every `SCHEDULER_POLL_SECS` (default 30) and takes up to `SCHEDULER_BATCH_SIZE`
+/// (default 100) due schedules at a time. Several schedulers can run side by side:
+/// a taken schedule is leased for `SCHEDULER_LEASE_SECS` (default 300) and published again
+/// after that only if its run was not recorded.
+pub struct TaskScheduler {
+	pool: Pool<Postgres>,
+	producer: RabbitMQProducer,
+	poll_interval: Duration,
+	batch_size: i64,
+	lease_secs: i64,
+}
+
+impl TaskScheduler {
+	pub fn new(pool: Pool<Postgres>, producer: RabbitMQProducer) -> Self {
+		let number = |key: &str, default: u64| {
+			env::var(key)
+				.ok()
+				.and_then(|value| value.trim().parse::<u64>().ok())
+				.unwrap_or(default)
+		};
+
+		Self {
+			pool,
+			producer,
+			poll_interval: Duration::from_secs(number("SCHEDULER_POLL_SECS", 30).max(1)),
+			batch_size: number("SCHEDULER_BATCH_SIZE", 100).max(1) as i64,
+			lease_secs: number("SCHEDULER_LEASE_SECS", 300).max(1) as i64,
+		}
+	}
+
+	pub async fn run(&self, mut shutdown: Shutdown) -> Result<(), Box<dyn Error + Send + Sync>> {
+		println!(
+			"⏰ Scheduler started, checking schedules every {:?}",
+			self.poll_interval
+		);
+
+		loop {
+			// A full batch means more schedules may be due already
+			let published = match self.publish_due().await {
+				Ok(published) => published,
+				Err(e) => {
+					eprintln!("⚠️ Failed to publish scheduled tasks: {}", e);
+					0
+				}
+			};
+			if published as i64 >= self.batch_size {
+				continue;
+			}
+
+			tokio::select! {
+				_ = sleep(self.poll_interval) => {}
+				_ = shutdown.requested() => break,
+			}
+		}
+
+		println!("👋 Scheduler stopped");
+		Ok(())
+	}
+
+	/// Publishes the due schedules once; returns how many were taken
+	pub async fn publish_due(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+		let schedules =
+			AiTaskSchedule::claim_due_schedules(&self.pool, self.batch_size, self.lease_secs)
+				.await?;
+
+		for schedule in &schedules {
+			let task = match task_for(schedule) {
+				Ok(task) => task,
+				Err(e) => {
+					eprintln!("❌ Schedule {} is broken: {}", schedule.schedule_id, e);
+					continue;
+				}
+			};
+
+			// Left leased on failure, so it is published again after the lease
+			if let Err(e) = self.producer.send_ai_processing_task_confirmed(&task).await {
+				eprintln!(
+					"⚠️ Failed to publish scheduled task {}: {}",
+					schedule.schedule_id, e
+				);
+				continue;
+			}
+
+			let next_run_ts = match schedule.cron {
+				Some(ref cron) => CronSchedule::parse(cron)
+					.ok()
+					.and_then(|cron| cron.next_after(Utc::now())),
+				None => None,
+			};
+			// The task is out, so a failed write must not stop the rest of the batch;
+			// an unrecorded run is published again once the lease runs out
+			if let Err(e) = self.record_run(schedule, &task, next_run_ts).await {
+				eprintln!(
+					"⚠️ Failed to record run of schedule {}, it runs again after the lease: {}",
+					schedule.schedule_id, e
+				);
+				continue;
+			}
+
+			println!(
+				"⏰ Published {} task {} for schedule {}, next run: {}",
+				schedule.processing_type,
+				task.task_id,
+				schedule.schedule_id,
+				next_run_ts.map_or("none".to_string(), |next| next.to_rfc3339())
+			);
+		}
+
+		Ok(schedules.len())
+	}
+
+	/// Records the run, retrying a few times so a short database hiccup does not
+	/// publish the task twice
+	async fn record_run(
+		&self,
+		schedule: &AiTaskSchedule,
+		task: &AIProcessingTask,
+		next_run_ts: Option<DateTime<Utc>>,
+	) -> Result<(), std::io::Error> {
+		let mut attempt = 1;
+		loop {
+			match AiTaskSchedule::record_run(
+				&self.pool,
+				&schedule.schedule_id,
+				&task.task_id,
+				next_run_ts,
+			)
+			.await
+			{
+				Ok(()) => return Ok(()),
+				Err(e) if attempt >= RECORD_RUN_ATTEMPTS => return Err(e),
+				Err(e) => {
+					eprintln!(
+						"⚠️ Failed to record run of schedule {} (attempt {}): {}",
+						schedule.schedule_id, attempt, e
+					);
+					sleep(Duration::from_millis(500 * attempt as u64)).await;
+					attempt += 1;
+				}
+			}
+		}
+	}
+}
+
+/// A one-off schedule keeps the `task_id` it was created with,
+/// every run of a repeated one gets its own
+fn task_for(schedule: &AiTaskSchedule) -> Result<AIProcessingTask, Box<dyn Error + Send + Sync>> {
+	let task_id = match schedule.cron {
+		Some(_) => Uuid::new_v4(),
+		None => schedule.schedule_id,
+	};
+
+	Ok(AIProcessingTask {
+		schema_version: MESSAGE_SCHEMA_VERSION,
+		task_id,
+		request_data: AIRequestData {
+			request_id: schedule.request_id,
+			user_id: schedule.user_id,
+			processing_type: schedule.processing_type.clone(),
+			parameters: serde_json::from_str(&schedule.parameters)?,
+		},
+		created_at: Utc::now().to_rfc3339(),
+		run_at: None,
+		cron: None,
+	})
+}
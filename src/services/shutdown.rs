@@ -0,0 +1,84 @@
+use std::env;
+use tokio::sync::watch;
+use tokio::time::Duration;
+
+/// Shutdown request shared by the consumer loop and its handlers.
+///
+/// `Shutdown::listen` turns SIGTERM (Docker stop) and SIGINT (Ctrl+C) into a
+/// shutdown request; in-flight work gets `SHUTDOWN_TIMEOUT_SECS` (default 30) to finish.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+	receiver: watch::Receiver<bool>,
+	pub grace_period: Duration,
+}
+
+impl Shutdown {
+	pub fn listen() -> Self {
+		let (sender, receiver) = watch::channel(false);
+
+		tokio::spawn(async move {
+			wait_for_signal().await;
+			println!("🛑 Shutdown requested, finishing in-flight tasks...");
+			let _ = sender.send(true);
+			// Keep the sender alive so receivers do not see a closed channel
+			std::future::pending::<()>().await;
+		});
+
+		Self {
+			receiver,
+			grace_period: Duration::from_secs(
+				env::var("SHUTDOWN_TIMEOUT_SECS")
+					.ok()
+					.and_then(|value| value.trim().parse::<u64>().ok())
+					.unwrap_or(30),
+			),
+		}
+	}
+
+	/// Never fires; for consumers started without signal handling
+	pub fn never() -> Self {
+		let (sender, receiver) = watch::channel(false);
+		// Leak the sender so the channel never reports closed
+		std::mem::forget(sender);
+
+		Self {
+			receiver,
+			grace_period: Duration::from_secs(30),
+		}
+	}
+
+	pub fn is_requested(&self) -> bool {
+		*self.receiver.borrow()
+	}
+
+	pub async fn requested(&mut self) {
+		while !*self.receiver.borrow_and_update() {
+			if self.receiver.changed().await.is_err() {
+				std::future::pending::<()>().await;
+			}
+		}
+	}
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+	use tokio::signal::unix::{signal, SignalKind};
+
+	match signal(SignalKind::terminate()) {
+		Ok(mut terminate) => {
+			tokio::select! {
+				_ = terminate.recv() => {}
+				_ = tokio::signal::ctrl_c() => {}
+			}
+		}
+		Err(e) => {
+			eprintln!("⚠️ Failed to listen for SIGTERM: {}", e);
+			let _ = tokio::signal::ctrl_c().await;
+		}
+	}
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+	let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,722 @@
+use lapin::{
+	options::ExchangeDeclareOptions,
+	types::{AMQPValue, FieldTable, LongString, ShortString},
+	Channel, ExchangeKind,
+};
+use serde_json::Value;
+use std::env;
+use std::error::Error;
+use std::fmt::Display;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::services::priority::TaskPriorities;
+
+static TOPOLOGY: OnceLock<Topology> = OnceLock::new();
+
+/// Reads and validates the topology once; called at startup so a bad setting
+/// stops the process before anything is published or consumed
+pub fn init() -> Result<&'static Topology, Box<dyn Error + Send + Sync>> {
+	if let Some(topology) = TOPOLOGY.get() {
+		return Ok(topology);
+	}
+	let topology = Topology::from_env()?;
+
+	Ok(TOPOLOGY.get_or_init(|| topology))
+}
+
+/// The topology loaded by `init`; loads it on first use when `init` was not called
+pub fn current() -> &'static Topology {
+	TOPOLOGY.get_or_init(|| {
+		Topology::from_env().unwrap_or_else(|e| panic!("Invalid RabbitMQ topology: {}", e))
+	})
+}
+
+/// A durable exchange the service declares
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+	pub name: String,
+	pub kind: ExchangeKind,
+}
+
+impl Exchange {
+	fn from_env(name_key: &str, kind_key: &str, default: &Exchange) -> Result<Self, String> {
+		let name = env::var(name_key).unwrap_or_else(|_| default.name.clone());
+		let kind = match env::var(kind_key) {
+			Ok(kind) => match kind.trim().to_lowercase().as_str() {
+				"topic" => ExchangeKind::Topic,
+				"direct" => ExchangeKind::Direct,
+				"fanout" => ExchangeKind::Fanout,
+				other => {
+					return Err(format!(
+						"{} must be 'topic', 'direct' or 'fanout', got '{}'",
+						kind_key, other
+					))
+				}
+			},
+			Err(_) => default.kind.clone(),
+		};
+
+		if name.trim().is_empty() {
+			return Err(format!("{} must not be empty", name_key));
+		}
+		if name.starts_with("amq.") {
+			return Err(format!(
+				"{} '{}' uses the reserved 'amq.' prefix",
+				name_key, name
+			));
+		}
+
+		Ok(Self { name, kind })
+	}
+
+	pub async fn declare(&self, channel: &Channel) -> Result<(), Box<dyn Error + Send + Sync>> {
+		channel
+			.exchange_declare(
+				&self.name,
+				self.kind.clone(),
+				ExchangeDeclareOptions {
+					durable: true,
+					..ExchangeDeclareOptions::default()
+				},
+				FieldTable::default(),
+			)
+			.await
+			.map_err(|e| -> Box<dyn Error + Send + Sync> {
+				Box::new(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Exchange {} declaration error: {}", self.name, e),
+				))
+			})
+	}
+
+	/// Whether a message with `routing_key` reaches a queue bound with one of `bindings`
+	pub fn routes(&self, bindings: &[String], routing_key: &str) -> bool {
+		match self.kind {
+			ExchangeKind::Topic => bindings
+				.iter()
+				.any(|binding| topic_matches(binding, routing_key)),
+			ExchangeKind::Direct => bindings.iter().any(|binding| binding == routing_key),
+			_ => !bindings.is_empty(),
+		}
+	}
+}
+
+/// Exchanges, bindings, routing keys, consumer tags and queue arguments of the service.
+///
+/// Tasks and cancellations go to `RABBITMQ_EXCHANGE` (default `avito_exchange`, kind
+/// `RABBITMQ_EXCHANGE_KIND`, default `topic`), results and progress updates to
+/// `RABBITMQ_RESULT_EXCHANGE` / `RABBITMQ_RESULT_EXCHANGE_KIND` (default the same exchange).
+/// Routing keys are templates: `RABBITMQ_TASK_ROUTING_KEY` (`task.{processing_type}`),
+/// `RABBITMQ_CANCEL_ROUTING_KEY` (`task.cancel`), `RABBITMQ_RESULT_ROUTING_KEY`
+/// (`ai.result.{user_id}`) and `RABBITMQ_PROGRESS_ROUTING_KEY` (`ai.progress.{user_id}`).
+/// The task queue is bound with `RABBITMQ_TASK_BINDINGS` (`task.*`), the result queue with
+/// `RABBITMQ_RESULT_BINDINGS` (`ai.result.*,ai.progress.*`). `RABBITMQ_QUEUE_ARGUMENTS` is a JSON
+/// object of extra arguments for both queues, `RABBITMQ_CONSUMER_TAG_PREFIX` (`ai_processing`)
+/// names the consumers.
+#[derive(Debug, Clone)]
+pub struct Topology {
+	pub task_exchange: Exchange,
+	pub result_exchange: Exchange,
+	pub task_bindings: Vec<String>,
+	pub result_bindings: Vec<String>,
+	pub cancel_routing_key: String,
+	pub task_consumer_tag: String,
+	pub result_consumer_tag: String,
+	pub cancel_consumer_tag: String,
+	task_routing_key: String,
+	result_routing_key: String,
+	progress_routing_key: String,
+	queue_arguments: Vec<(String, AMQPValue)>,
+}
+
+impl Default for Topology {
+	/// What the Avito backend expects
+	fn default() -> Self {
+		let exchange = Exchange {
+			name: "avito_exchange".to_string(),
+			kind: ExchangeKind::Topic,
+		};
+
+		Self::new(
+			exchange.clone(),
+			exchange,
+			"task.{processing_type}",
+			"task.cancel",
+			"ai.result.{user_id}",
+			"ai.progress.{user_id}",
+			"ai_processing",
+		)
+	}
+}
+
+impl Topology {
+	/// Topology with the bindings that route every routing key to its queue
+	/// and no extra queue arguments
+	pub fn new(
+		task_exchange: Exchange,
+		result_exchange: Exchange,
+		task_routing_key: &str,
+		cancel_routing_key: &str,
+		result_routing_key: &str,
+		progress_routing_key: &str,
+		consumer_tag_prefix: &str,
+	) -> Self {
+		Self {
+			task_exchange,
+			result_exchange,
+			task_bindings: vec![default_binding(task_routing_key)],
+			result_bindings: vec![
+				default_binding(result_routing_key),
+				default_binding(progress_routing_key),
+			],
+			cancel_routing_key: cancel_routing_key.to_string(),
+			task_consumer_tag: format!("{}_consumer", consumer_tag_prefix),
+			result_consumer_tag: format!("{}_result_consumer", consumer_tag_prefix),
+			cancel_consumer_tag: format!("{}_cancel_consumer", consumer_tag_prefix),
+			task_routing_key: task_routing_key.to_string(),
+			result_routing_key: result_routing_key.to_string(),
+			progress_routing_key: progress_routing_key.to_string(),
+			queue_arguments: Vec::new(),
+		}
+	}
+
+	pub fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+		Self::read_env().map_err(|e| -> Box<dyn Error + Send + Sync> {
+			Box::new(std::io::Error::new(
+				std::io::ErrorKind::Other,
+				format!("Invalid RabbitMQ topology: {}", e),
+			))
+		})
+	}
+
+	fn read_env() -> Result<Self, String> {
+		let defaults = Self::default();
+		let var = |key: &str, default: &str| env::var(key).unwrap_or_else(|_| default.to_string());
+
+		let task_exchange = Exchange::from_env(
+			"RABBITMQ_EXCHANGE",
+			"RABBITMQ_EXCHANGE_KIND",
+			&defaults.task_exchange,
+		)?;
+		let result_exchange = Exchange::from_env(
+			"RABBITMQ_RESULT_EXCHANGE",
+			"RABBITMQ_RESULT_EXCHANGE_KIND",
+			&task_exchange,
+		)?;
+
+		let mut topology = Self::new(
+			task_exchange,
+			result_exchange,
+			&var("RABBITMQ_TASK_ROUTING_KEY", &defaults.task_routing_key),
+			&var("RABBITMQ_CANCEL_ROUTING_KEY", &defaults.cancel_routing_key),
+			&var("RABBITMQ_RESULT_ROUTING_KEY", &defaults.result_routing_key),
+			&var(
+				"RABBITMQ_PROGRESS_ROUTING_KEY",
+				&defaults.progress_routing_key,
+			),
+			&var("RABBITMQ_CONSUMER_TAG_PREFIX", "ai_processing"),
+		);
+		if let Ok(bindings) = env::var("RABBITMQ_TASK_BINDINGS") {
+			topology.task_bindings = split_list(&bindings);
+		}
+		if let Ok(bindings) = env::var("RABBITMQ_RESULT_BINDINGS") {
+			topology.result_bindings = split_list(&bindings);
+		}
+		if let Ok(arguments) = env::var("RABBITMQ_QUEUE_ARGUMENTS") {
+			topology.queue_arguments = parse_queue_arguments(&arguments)?;
+		}
+
+		topology.validate()?;
+		Ok(topology)
+	}
+
+	/// Checks the templates and bindings, and that every routing key reaches
+	/// its own queue and no other
+	pub fn validate(&self) -> Result<(), String> {
+		check_template(
+			"RABBITMQ_TASK_ROUTING_KEY",
+			&self.task_routing_key,
+			&["processing_type"],
+		)?;
+		check_template("RABBITMQ_CANCEL_ROUTING_KEY", &self.cancel_routing_key, &[])?;
+		check_template(
+			"RABBITMQ_RESULT_ROUTING_KEY",
+			&self.result_routing_key,
+			&["user_id"],
+		)?;
+		check_template(
+			"RABBITMQ_PROGRESS_ROUTING_KEY",
+			&self.progress_routing_key,
+			&["user_id"],
+		)?;
+		check_bindings(
+			"RABBITMQ_TASK_BINDINGS",
+			&self.task_exchange,
+			&self.task_bindings,
+		)?;
+		check_bindings(
+			"RABBITMQ_RESULT_BINDINGS",
+			&self.result_exchange,
+			&self.result_bindings,
+		)?;
+
+		// A direct exchange routes by the exact key, so a per-user key would need a binding per user
+		for (exchange, key, template) in [
+			(
+				&self.task_exchange,
+				"RABBITMQ_TASK_ROUTING_KEY",
+				&self.task_routing_key,
+			),
+			(
+				&self.result_exchange,
+				"RABBITMQ_RESULT_ROUTING_KEY",
+				&self.result_routing_key,
+			),
+			(
+				&self.result_exchange,
+				"RABBITMQ_PROGRESS_ROUTING_KEY",
+				&self.progress_routing_key,
+			),
+		] {
+			if exchange.kind == ExchangeKind::Direct && template.contains('{') {
+				return Err(format!(
+					"{} '{}' has a placeholder, but {} is a direct exchange",
+					key, template, exchange.name
+				));
+			}
+		}
+
+		let task_key = self.task_routing_key("title");
+		let result_keys = [
+			self.result_routing_key(Uuid::nil()),
+			self.progress_routing_key(Uuid::nil()),
+		];
+
+		if !self.task_exchange.routes(&self.task_bindings, &task_key) {
+			return Err(format!(
+				"task routing key {} does not match RABBITMQ_TASK_BINDINGS {:?}",
+				task_key, self.task_bindings
+			));
+		}
+		for key in &result_keys {
+			if !self.result_exchange.routes(&self.result_bindings, key) {
+				return Err(format!(
+					"result routing key {} does not match RABBITMQ_RESULT_BINDINGS {:?}",
+					key, self.result_bindings
+				));
+			}
+		}
+
+		// On a shared exchange results must stay out of the task queue and the other way round
+		if self.task_exchange.name == self.result_exchange.name {
+			if self.task_exchange != self.result_exchange {
+				return Err(format!(
+					"exchange {} is configured with two different kinds",
+					self.task_exchange.name
+				));
+			}
+			if self.task_exchange.kind == ExchangeKind::Fanout {
+				return Err(format!(
+					"fanout exchange {} can't carry both tasks and results",
+					self.task_exchange.name
+				));
+			}
+			for key in &result_keys {
+				if self.task_exchange.routes(&self.task_bindings, key) {
+					return Err(format!(
+						"result routing key {} also matches RABBITMQ_TASK_BINDINGS {:?}",
+						key, self.task_bindings
+					));
+				}
+			}
+			for key in [&task_key, &self.cancel_routing_key] {
+				if self.result_exchange.routes(&self.result_bindings, key) {
+					return Err(format!(
+						"task routing key {} also matches RABBITMQ_RESULT_BINDINGS {:?}",
+						key, self.result_bindings
+					));
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	pub fn task_routing_key(&self, processing_type: &str) -> String {
+		self.task_routing_key
+			.replace("{processing_type}", processing_type)
+	}
+
+	/// `user_id` may be a placeholder text, as in the message schemas
+	pub fn result_routing_key(&self, user_id: impl Display) -> String {
+		self.result_routing_key
+			.replace("{user_id}", &user_id.to_string())
+	}
+
+	pub fn progress_routing_key(&self, user_id: impl Display) -> String {
+		self.progress_routing_key
+			.replace("{user_id}", &user_id.to_string())
+	}
+
+	/// Arguments for `queue_declare` of the task and result queues; a queue keeps them for life,
+	/// so producers and consumers must declare the same ones
+	pub fn queue_arguments(&self, priorities: &TaskPriorities) -> FieldTable {
+		let mut arguments = priorities.queue_arguments();
+		for (key, value) in &self.queue_arguments {
+			arguments.insert(ShortString::from(key.as_str()), value.clone());
+		}
+		arguments
+	}
+
+	/// Declares the exchanges, once when tasks and results share one
+	pub async fn declare(&self, channel: &Channel) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.task_exchange.declare(channel).await?;
+		if self.result_exchange.name != self.task_exchange.name {
+			self.result_exchange.declare(channel).await?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Binding that matches every key of the template: placeholders become `*`
+fn default_binding(template: &str) -> String {
+	template
+		.split('.')
+		.map(|word| if word.contains('{') { "*" } else { word })
+		.collect::<Vec<&str>>()
+		.join(".")
+}
+
+fn split_list(value: &str) -> Vec<String> {
+	value
+		.split(',')
+		.map(|item| item.trim().to_string())
+		.filter(|item| !item.is_empty())
+		.collect()
+}
+
+fn check_template(key: &str, template: &str, placeholders: &[&str]) -> Result<(), String> {
+	if template.trim().is_empty() {
+		return Err(format!("{} must not be empty", key));
+	}
+
+	let mut rest = template;
+	while let Some(start) = rest.find('{') {
+		let end = match rest[start..].find('}') {
+			Some(end) => start + end,
+			None => return Err(format!("{} has an unclosed '{{' in '{}'", key, template)),
+		};
+		let name = &rest[start + 1..end];
+		if !placeholders.contains(&name) {
+			return Err(format!(
+				"{} has an unknown placeholder {{{}}}; allowed: {}",
+				key,
+				name,
+				if placeholders.is_empty() {
+					"none".to_string()
+				} else {
+					placeholders
+						.iter()
+						.map(|name| format!("{{{}}}", name))
+						.collect::<Vec<String>>()
+						.join(", ")
+				}
+			));
+		}
+		rest = &rest[end + 1..];
+	}
+
+	if template.contains('*') || template.contains('#') {
+		return Err(format!(
+			"{} is a routing key, not a binding pattern: '{}'",
+			key, template
+		));
+	}
+
+	Ok(())
+}
+
+fn check_bindings(key: &str, exchange: &Exchange, bindings: &[String]) -> Result<(), String> {
+	if bindings.is_empty() {
+		return Err(format!("{} must list at least one binding", key));
+	}
+
+	if exchange.kind == ExchangeKind::Topic {
+		for binding in bindings {
+			let valid = binding.split('.').all(|word| {
+				word == "*" || word == "#" || !(word.contains('*') || word.contains('#'))
+			});
+			if !valid {
+				return Err(format!(
+					"{} entry '{}' uses '*' or '#' inside a word",
+					key, binding
+				));
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Topic exchange matching: `*` is exactly one word, `#` zero or more
+fn topic_matches(binding: &str, routing_key: &str) -> bool {
+	fn matches(binding: &[&str], key: &[&str]) -> bool {
+		match binding.split_first() {
+			None => key.is_empty(),
+			Some((&"#", rest)) => (0..=key.len()).any(|skip| matches(rest, &key[skip..])),
+			Some((&"*", rest)) => !key.is_empty() && matches(rest, &key[1..]),
+			Some((word, rest)) => key.first() == Some(word) && matches(rest, &key[1..]),
+		}
+	}
+
+	matches(
+		&binding.split('.').collect::<Vec<&str>>(),
+		&routing_key.split('.').collect::<Vec<&str>>(),
+	)
+}
+
+/// `{"x-queue-type": "quorum", "x-message-ttl": 60000}`; strings, integers and booleans only
+fn parse_queue_arguments(value: &str) -> Result<Vec<(String, AMQPValue)>, String> {
+	let arguments = match serde_json::from_str::<Value>(value) {
+		Ok(Value::Object(arguments)) => arguments,
+		_ => return Err("RABBITMQ_QUEUE_ARGUMENTS must be a JSON object".to_string()),
+	};
+
+	arguments
+		.into_iter()
+		.map(|(key, value)| {
+			if key == "x-max-priority" {
+				return Err(
+					"RABBITMQ_QUEUE_ARGUMENTS must not set x-max-priority, use RABBITMQ_MAX_PRIORITY"
+						.to_string(),
+				);
+			}
+			let value = match value {
+				Value::String(value) => AMQPValue::LongString(LongString::from(value)),
+				Value::Bool(value) => AMQPValue::Boolean(value),
+				Value::Number(ref number) => match number.as_i64() {
+					Some(number) => match i32::try_from(number) {
+						Ok(number) => AMQPValue::LongInt(number),
+						Err(_) => AMQPValue::LongLongInt(number),
+					},
+					None => {
+						return Err(format!(
+							"RABBITMQ_QUEUE_ARGUMENTS {} must be an integer",
+							key
+						))
+					}
+				},
+				_ => {
+					return Err(format!(
+						"RABBITMQ_QUEUE_ARGUMENTS {} must be a string, integer or boolean",
+						key
+					))
+				}
+			};
+			Ok((key, value))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn topology(change: impl FnOnce(&mut Topology)) -> Result<(), String> {
+		let mut topology = Topology::default();
+		change(&mut topology);
+		topology.validate()
+	}
+
+	fn assert_error(result: Result<(), String>, expected: &str) {
+		match result {
+			Err(error) => assert!(error.contains(expected), "{}", error),
+			Ok(()) => panic!("expected an error with '{}'", expected),
+		}
+	}
+
+	#[test]
+	fn topic_wildcards() {
+		for (binding, routing_key, expected) in [
+			("task.title", "task.title", true),
+			("task.title", "task.titles", false),
+			// `*` is exactly one word
+			("task.*", "task.title", true),
+			("task.*", "task", false),
+			("task.*", "task.title.extra", false),
+			("*.result.*", "ai.result.42", true),
+			("*.*", "task", false),
+			// `#` is zero or more words
+			("task.#", "task", true),
+			("task.#", "task.title", true),
+			("task.#", "task.title.extra", true),
+			("ai.#.42", "ai.42", true),
+			("ai.#.42", "ai.result.user.42", true),
+			("ai.#.42", "ai.result.43", false),
+			("#.cancel", "task.cancel", true),
+			("#", "ai.progress.42", true),
+			("#.*", "task", true),
+			("#.*", "", true),
+			("ai.result.*", "ai.progress.42", false),
+		] {
+			assert_eq!(
+				topic_matches(binding, routing_key),
+				expected,
+				"{} / {}",
+				binding,
+				routing_key
+			);
+		}
+	}
+
+	#[test]
+	fn default_topology_is_valid() {
+		let topology = Topology::default();
+
+		assert_eq!(topology.validate(), Ok(()));
+		assert_eq!(topology.task_bindings, vec!["task.*"]);
+		assert_eq!(
+			topology.result_bindings,
+			vec!["ai.result.*", "ai.progress.*"]
+		);
+		assert_eq!(topology.task_routing_key("title"), "task.title");
+		assert_eq!(
+			topology.result_routing_key("<user_id>"),
+			"ai.result.<user_id>"
+		);
+	}
+
+	#[test]
+	fn rejects_invalid_templates() {
+		assert_error(
+			topology(|t| t.task_routing_key = "task.{type}".to_string()),
+			"unknown placeholder {type}; allowed: {processing_type}",
+		);
+		assert_error(
+			topology(|t| t.result_routing_key = "ai.result.{user_id".to_string()),
+			"unclosed",
+		);
+		assert_error(
+			topology(|t| t.cancel_routing_key = "task.{user_id}".to_string()),
+			"allowed: none",
+		);
+		assert_error(
+			topology(|t| t.cancel_routing_key = "task.*".to_string()),
+			"not a binding pattern",
+		);
+		assert_error(
+			topology(|t| t.progress_routing_key = " ".to_string()),
+			"must not be empty",
+		);
+	}
+
+	#[test]
+	fn rejects_unbound_routing_keys() {
+		assert_error(
+			topology(|t| t.task_bindings = vec!["tasks.*".to_string()]),
+			"task routing key task.title does not match",
+		);
+		assert_error(
+			topology(|t| t.result_bindings = vec!["ai.result.*".to_string()]),
+			"result routing key ai.progress.",
+		);
+		assert_error(
+			topology(|t| t.task_bindings.clear()),
+			"at least one binding",
+		);
+		assert_error(
+			topology(|t| t.task_bindings = vec!["task.ti*".to_string()]),
+			"inside a word",
+		);
+	}
+
+	#[test]
+	fn rejects_keys_reaching_the_other_queue() {
+		assert_error(
+			topology(|t| t.task_bindings = vec!["#".to_string()]),
+			"also matches RABBITMQ_TASK_BINDINGS",
+		);
+		assert_error(
+			topology(|t| t.result_bindings.push("task.#".to_string())),
+			"also matches RABBITMQ_RESULT_BINDINGS",
+		);
+		// Separate exchanges keep them apart whatever the bindings
+		assert_eq!(
+			topology(|t| {
+				t.task_bindings = vec!["#".to_string()];
+				t.result_exchange.name = "avito_results".to_string();
+			}),
+			Ok(())
+		);
+	}
+
+	#[test]
+	fn rejects_exchange_kinds_that_cannot_route() {
+		assert_error(
+			topology(|t| {
+				t.task_exchange.kind = ExchangeKind::Direct;
+				t.result_exchange.kind = ExchangeKind::Direct;
+			}),
+			"has a placeholder, but avito_exchange is a direct exchange",
+		);
+		assert_error(
+			topology(|t| t.result_exchange.kind = ExchangeKind::Fanout),
+			"two different kinds",
+		);
+		assert_error(
+			topology(|t| {
+				t.task_exchange.kind = ExchangeKind::Fanout;
+				t.result_exchange.kind = ExchangeKind::Fanout;
+			}),
+			"can't carry both",
+		);
+	}
+
+	#[test]
+	fn parses_queue_arguments() {
+		let arguments = parse_queue_arguments(
+			r#"{"x-queue-type": "quorum", "x-message-ttl": 60000, "x-max-length-bytes": 8589934592, "x-single-active-consumer": true}"#,
+		)
+		.unwrap();
+
+		assert_eq!(
+			arguments,
+			vec![
+				(
+					"x-max-length-bytes".to_string(),
+					AMQPValue::LongLongInt(8_589_934_592)
+				),
+				("x-message-ttl".to_string(), AMQPValue::LongInt(60000)),
+				(
+					"x-queue-type".to_string(),
+					AMQPValue::LongString(LongString::from("quorum"))
+				),
+				(
+					"x-single-active-consumer".to_string(),
+					AMQPValue::Boolean(true)
+				),
+			]
+		);
+	}
+
+	#[test]
+	fn rejects_bad_queue_arguments() {
+		for (value, expected) in [
+			(r#"["x-queue-type"]"#, "must be a JSON object"),
+			("x-queue-type=quorum", "must be a JSON object"),
+			(r#"{"x-max-priority": 10}"#, "use RABBITMQ_MAX_PRIORITY"),
+			(
+				r#"{"x-message-ttl": 1.5}"#,
+				"x-message-ttl must be an integer",
+			),
+			(
+				r#"{"x-args": {"a": 1}}"#,
+				"must be a string, integer or boolean",
+			),
+		] {
+			assert_error(parse_queue_arguments(value).map(|_| ()), expected);
+		}
+	}
+}
@@ -0,0 +1,448 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::models::rabbitmq::{AIProcessingTask, AIRequestData, MESSAGE_SCHEMA_VERSION};
+use crate::tasks::{validate_task, TaskRegistry};
+
+/// Columns of a CSV file that are not task parameters
+const CSV_TASK_COLUMNS: [&str; 6] = [
+	"processing_type",
+	"user_id",
+	"request_id",
+	"task_id",
+	"run_at",
+	"cron",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+	JsonLines,
+	Csv,
+}
+
+impl ImportFormat {
+	pub fn parse(value: &str) -> Option<Self> {
+		match value.trim().to_lowercase().as_str() {
+			"jsonl" | "json" | "ndjson" => Some(Self::JsonLines),
+			"csv" => Some(Self::Csv),
+			_ => None,
+		}
+	}
+
+	/// CSV for `*.csv`, JSON Lines for anything else, stdin included
+	pub fn for_path(path: &str) -> Self {
+		if path.to_lowercase().ends_with(".csv") {
+			Self::Csv
+		} else {
+			Self::JsonLines
+		}
+	}
+}
+
+/// A task definition as ops write it: the `request_data` fields at the top level,
+/// ids generated when missing
+#[derive(Debug, Deserialize)]
+struct TaskDefinition {
+	processing_type: String,
+	user_id: Uuid,
+	#[serde(default)]
+	request_id: Option<Uuid>,
+	#[serde(default)]
+	task_id: Option<Uuid>,
+	#[serde(default)]
+	parameters: Option<Value>,
+	#[serde(default)]
+	run_at: Option<DateTime<Utc>>,
+	#[serde(default)]
+	cron: Option<String>,
+}
+
+impl From<TaskDefinition> for AIProcessingTask {
+	fn from(definition: TaskDefinition) -> Self {
+		let task_id = definition.task_id.unwrap_or_else(Uuid::new_v4);
+
+		Self {
+			schema_version: MESSAGE_SCHEMA_VERSION,
+			task_id,
+			request_data: AIRequestData {
+				request_id: definition.request_id.unwrap_or(task_id),
+				user_id: definition.user_id,
+				processing_type: definition.processing_type,
+				parameters: definition
+					.parameters
+					.unwrap_or_else(|| Value::Object(Map::new())),
+			},
+			created_at: Utc::now().to_rfc3339(),
+			run_at: definition.run_at,
+			cron: definition.cron,
+		}
+	}
+}
+
+/// One task of the input, or why it can't be published
+#[derive(Debug)]
+pub struct ImportedTask {
+	/// Line of the input the task starts on, for the report
+	pub line: usize,
+	pub task: Result<AIProcessingTask, String>,
+}
+
+/// Reads and validates the tasks of a JSON Lines or CSV input.
+///
+/// A JSON line is either a full task message (with `request_data`) or a definition with
+/// `processing_type`, `user_id`, `parameters` and optional `request_id`, `task_id`, `run_at`, `cron`.
+/// A CSV file has a header row with the same columns; every other column is a parameter,
+/// converted to the type the processor's schema expects, and a `parameters` column may hold
+/// a JSON object of the rest. Blank lines and JSON lines starting with `#` are skipped.
+pub fn read_tasks(input: &str, format: ImportFormat, registry: &TaskRegistry) -> Vec<ImportedTask> {
+	let records = match format {
+		ImportFormat::JsonLines => read_json_lines(input),
+		ImportFormat::Csv => read_csv(input, registry),
+	};
+
+	records
+		.into_iter()
+		.map(|(line, task)| ImportedTask {
+			line,
+			task: task.and_then(|task| check(task, registry)),
+		})
+		.collect()
+}
+
+fn check(task: AIProcessingTask, registry: &TaskRegistry) -> Result<AIProcessingTask, String> {
+	let processor = registry
+		.get(&task.request_data.processing_type)
+		.ok_or_else(|| {
+			format!(
+				"unsupported processing_type '{}', expected one of: {}",
+				task.request_data.processing_type,
+				registry.names().join(", ")
+			)
+		})?;
+	validate_task(processor.as_ref(), &task).map_err(|e| e.to_string())?;
+
+	Ok(task)
+}
+
+fn read_json_lines(input: &str) -> Vec<(usize, Result<AIProcessingTask, String>)> {
+	input
+		.lines()
+		.enumerate()
+		.filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+		.map(|(index, line)| (index + 1, parse_json_line(line)))
+		.collect()
+}
+
+fn parse_json_line(line: &str) -> Result<AIProcessingTask, String> {
+	let value: Value = serde_json::from_str(line).map_err(|e| format!("Parse error: {}", e))?;
+
+	if value.get("request_data").is_some() {
+		AIProcessingTask::from_message(line.as_bytes()).map_err(|e| e.to_string())
+	} else {
+		serde_json::from_value::<TaskDefinition>(value)
+			.map(AIProcessingTask::from)
+			.map_err(|e| format!("Invalid task definition: {}", e))
+	}
+}
+
+fn read_csv(
+	input: &str,
+	registry: &TaskRegistry,
+) -> Vec<(usize, Result<AIProcessingTask, String>)> {
+	let (records, unterminated) = parse_csv(input);
+	let mut records = records.into_iter();
+	let header = match records.next() {
+		Some((_, header)) => header
+			.into_iter()
+			.map(|column| column.trim().to_string())
+			.collect::<Vec<String>>(),
+		None => return Vec::new(),
+	};
+
+	records
+		.filter(|(_, fields)| fields.iter().any(|field| !field.trim().is_empty()))
+		.map(|(line, fields)| (line, parse_csv_record(&header, fields, registry)))
+		.chain(unterminated.map(|line| (line, Err("unterminated quoted field".to_string()))))
+		.collect()
+}
+
+fn parse_csv_record(
+	header: &[String],
+	fields: Vec<String>,
+	registry: &TaskRegistry,
+) -> Result<AIProcessingTask, String> {
+	if fields.len() != header.len() {
+		return Err(format!(
+			"expected {} columns, got {}",
+			header.len(),
+			fields.len()
+		));
+	}
+
+	let row = header
+		.iter()
+		.zip(fields)
+		.filter(|(_, value)| !value.is_empty())
+		.collect::<Vec<(&String, String)>>();
+
+	let mut definition = Map::new();
+	let mut parameters = Map::new();
+	for (column, value) in &row {
+		if CSV_TASK_COLUMNS.contains(&column.as_str()) {
+			definition.insert(column.to_string(), Value::String(value.clone()));
+		} else if column.as_str() == "parameters" {
+			match serde_json::from_str::<Value>(value) {
+				Ok(Value::Object(object)) => parameters.extend(object),
+				_ => return Err("parameters must be a JSON object".to_string()),
+			}
+		}
+	}
+
+	// Parameter columns are typed by the processor's schema, the rest stay strings
+	let schema = definition
+		.get("processing_type")
+		.and_then(|value| value.as_str())
+		.and_then(|processing_type| registry.get(processing_type))
+		.map(|processor| processor.parameter_schema());
+	for (column, value) in row {
+		if CSV_TASK_COLUMNS.contains(&column.as_str()) || column.as_str() == "parameters" {
+			continue;
+		}
+		let expected = match column.as_str() {
+			// Read by the producer, not the processor
+			"priority" => Some("integer"),
+			_ => schema
+				.as_ref()
+				.and_then(|schema| schema.pointer(&format!("/properties/{}/type", column)))
+				.and_then(|value| value.as_str()),
+		};
+		parameters.insert(column.clone(), typed_value(&value, expected));
+	}
+	definition.insert("parameters".to_string(), Value::Object(parameters));
+
+	serde_json::from_value::<TaskDefinition>(Value::Object(definition))
+		.map(AIProcessingTask::from)
+		.map_err(|e| format!("Invalid task definition: {}", e))
+}
+
+/// The cell as the JSON type the schema expects; left a string if it isn't one,
+/// so validation reports the column
+fn typed_value(value: &str, expected: Option<&str>) -> Value {
+	let parsed = match expected {
+		Some("integer") => value.trim().parse::<i64>().ok().map(Value::from),
+		Some("number") => value.trim().parse::<f64>().ok().map(Value::from),
+		Some("boolean") => value.trim().parse::<bool>().ok().map(Value::from),
+		_ => None,
+	};
+
+	parsed.unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// Splits CSV into records with the line each starts on. Quoted fields may contain
+/// commas, newlines and `""` for a quote; a quote left open drops the last record
+/// and returns the line it starts on
+fn parse_csv(input: &str) -> (Vec<(usize, Vec<String>)>, Option<usize>) {
+	let mut records = Vec::new();
+	let mut fields = Vec::new();
+	let mut field = String::new();
+	let mut in_quotes = false;
+	let mut line = 1;
+	let mut record_line = 1;
+	let mut chars = input.trim_start_matches('\u{feff}').chars().peekable();
+
+	while let Some(c) = chars.next() {
+		match c {
+			'"' if in_quotes => {
+				if chars.peek() == Some(&'"') {
+					chars.next();
+					field.push('"');
+				} else {
+					in_quotes = false;
+				}
+			}
+			'"' if field.is_empty() => in_quotes = true,
+			'\n' if in_quotes => {
+				line += 1;
+				field.push('\n');
+			}
+			',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+			'\r' if !in_quotes && chars.peek() == Some(&'\n') => {}
+			'\n' => {
+				fields.push(std::mem::take(&mut field));
+				records.push((record_line, std::mem::take(&mut fields)));
+				line += 1;
+				record_line = line;
+			}
+			_ => field.push(c),
+		}
+	}
+
+	if in_quotes {
+		return (records, Some(record_line));
+	}
+	if !field.is_empty() || !fields.is_empty() {
+		fields.push(field);
+		records.push((record_line, fields));
+	}
+
+	(records, None)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const USER_ID: &str = "6f1c2d3e-4b5a-4c6d-8e7f-901234567890";
+
+	fn errors(tasks: &[ImportedTask]) -> Vec<(usize, String)> {
+		tasks
+			.iter()
+			.filter_map(|imported| match imported.task {
+				Ok(_) => None,
+				Err(ref error) => Some((imported.line, error.clone())),
+			})
+			.collect()
+	}
+
+	#[test]
+	fn json_lines_skip_blank_and_comment_lines() {
+		let input = format!(
+			"# titles for the spring sale\n\n{{\"processing_type\": \"title\", \"user_id\": \"{user}\", \"parameters\": {{\"input_text\": \"ремонт окон\"}}}}\n   \n  # disabled\n{{\"processing_type\": \"description\", \"user_id\": \"{user}\", \"parameters\": {{\"input_text\": \"Окна под ключ\"}}, \"cron\": \"@daily\"}}\n",
+			user = USER_ID
+		);
+
+		let tasks = read_tasks(&input, ImportFormat::JsonLines, &TaskRegistry::builtin());
+
+		assert_eq!(
+			tasks.iter().map(|task| task.line).collect::<Vec<usize>>(),
+			vec![3, 6]
+		);
+		let title = tasks[0].task.as_ref().unwrap();
+		assert_eq!(title.request_data.processing_type, "title");
+		assert_eq!(title.request_data.user_id.to_string(), USER_ID);
+		// A missing request_id is the generated task_id
+		assert_eq!(title.request_data.request_id, title.task_id);
+		let description = tasks[1].task.as_ref().unwrap();
+		assert_eq!(description.cron.as_deref(), Some("@daily"));
+	}
+
+	#[test]
+	fn json_lines_report_bad_lines_by_number() {
+		let input = format!(
+			"{{\"processing_type\": \"title\", \"user_id\": \"{user}\", \"parameters\": {{\"input_text\": \"окна\"}}}}\n{{\"processing_type\": \"title\",\n\n{{\"processing_type\": \"translation\", \"user_id\": \"{user}\"}}\n{{\"processing_type\": \"title\", \"user_id\": \"{user}\", \"parameters\": {{\"input_text\": 5}}}}\n{{\"processing_type\": \"title\"}}\n",
+			user = USER_ID
+		);
+
+		let tasks = read_tasks(&input, ImportFormat::JsonLines, &TaskRegistry::builtin());
+		let errors = errors(&tasks);
+
+		assert!(tasks[0].task.is_ok());
+		assert_eq!(
+			errors.iter().map(|(line, _)| *line).collect::<Vec<usize>>(),
+			vec![2, 4, 5, 6]
+		);
+		assert!(errors[0].1.starts_with("Parse error"), "{}", errors[0].1);
+		assert!(
+			errors[1]
+				.1
+				.starts_with("unsupported processing_type 'translation'"),
+			"{}",
+			errors[1].1
+		);
+		assert_eq!(
+			errors[2].1,
+			"Invalid request_data.parameters.input_text: expected string, got number"
+		);
+		assert!(errors[3].1.contains("user_id"), "{}", errors[3].1);
+	}
+
+	#[test]
+	fn csv_columns_map_to_the_task_and_typed_parameters() {
+		let input = format!(
+			"{bom}processing_type,user_id,feed_id,total_replacements,priority,batch_id\r\n\
+			 keyword_extraction,{user},{feed},120,5,0042\r\n",
+			bom = '\u{feff}',
+			user = USER_ID,
+			feed = "0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d"
+		);
+
+		let tasks = read_tasks(&input, ImportFormat::Csv, &TaskRegistry::builtin());
+
+		assert_eq!(tasks.len(), 1);
+		assert_eq!(tasks[0].line, 2);
+		let task = tasks[0].task.as_ref().unwrap();
+		assert_eq!(task.request_data.processing_type, "keyword_extraction");
+		assert_eq!(
+			task.request_data.parameters,
+			serde_json::json!({
+				"feed_id": "0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d",
+				"total_replacements": 120,
+				"priority": 5,
+				// Strings in the schema stay strings, leading zeros included
+				"batch_id": "0042"
+			})
+		);
+	}
+
+	#[test]
+	fn csv_quoted_fields_and_parameters_column() {
+		let input = format!(
+			"processing_type,user_id,input_text,parameters\n\
+			 title,{user},\"Окна, двери\nи \"\"балконы\"\"\",\"{{\"\"category\"\": \"\"Окна\"\"}}\"\n\
+			 \n\
+			 title,{user},ремонт окон,\n",
+			user = USER_ID
+		);
+
+		let tasks = read_tasks(&input, ImportFormat::Csv, &TaskRegistry::builtin());
+
+		// The quoted newline keeps the first record together, the blank line is skipped
+		assert_eq!(
+			tasks.iter().map(|task| task.line).collect::<Vec<usize>>(),
+			vec![2, 5]
+		);
+		let parameters = &tasks[0].task.as_ref().unwrap().request_data.parameters;
+		assert_eq!(parameters["input_text"], "Окна, двери\nи \"балконы\"");
+		assert_eq!(parameters["category"], "Окна");
+		assert!(tasks[1].task.is_ok());
+	}
+
+	#[test]
+	fn csv_reports_bad_rows_by_line() {
+		let input = format!(
+			"processing_type,user_id,input_text\n\
+			 title,{user}\n\
+			 title,{user},\n\
+			 title,{user},\"ремонт окон\n",
+			user = USER_ID
+		);
+
+		let tasks = read_tasks(&input, ImportFormat::Csv, &TaskRegistry::builtin());
+
+		assert_eq!(
+			errors(&tasks),
+			vec![
+				(2, "expected 3 columns, got 2".to_string()),
+				(
+					3,
+					"Invalid request_data.parameters.input_text: is required".to_string()
+				),
+				(4, "unterminated quoted field".to_string()),
+			]
+		);
+	}
+
+	#[test]
+	fn format_from_name_or_path() {
+		assert_eq!(
+			ImportFormat::parse(" NDJSON "),
+			Some(ImportFormat::JsonLines)
+		);
+		assert_eq!(ImportFormat::parse("csv"), Some(ImportFormat::Csv));
+		assert_eq!(ImportFormat::parse("xlsx"), None);
+		assert_eq!(ImportFormat::for_path("tasks.CSV"), ImportFormat::Csv);
+		assert_eq!(ImportFormat::for_path("-"), ImportFormat::JsonLines);
+	}
+}
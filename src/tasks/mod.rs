@@ -0,0 +1,17 @@
+pub mod context;
+pub mod import;
+pub mod parameters;
+pub mod processor;
+pub mod processors;
+pub mod registry;
+pub mod schema;
+pub mod validation;
+
+pub use self::context::*;
+pub use self::import::*;
+pub use self::parameters::*;
+pub use self::processor::*;
+pub use self::processors::*;
+pub use self::registry::*;
+pub use self::schema::*;
+pub use self::validation::*;
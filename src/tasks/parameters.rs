@@ -0,0 +1,77 @@
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::models::rabbitmq::AIProcessingTask;
+use crate::tasks::ValidationError;
+
+const PARAMETERS_PATH: &str = "request_data.parameters";
+
+/// `parameters` of `title` and `description` tasks
+#[derive(Debug, Clone)]
+pub struct TextParameters {
+	pub input_text: String,
+	pub category: String,
+}
+
+impl TextParameters {
+	/// `legacy_field` (`title` or `description`) is read when `input_text` is missing
+	pub fn from_task(task: &AIProcessingTask, legacy_field: &str) -> Result<Self, ValidationError> {
+		let parameters = &task.request_data.parameters;
+		let input_text = [
+			("input_text", parameters.get("input_text")),
+			(legacy_field, parameters.get(legacy_field)),
+		]
+		.into_iter()
+		.find_map(|(field, value)| value.and_then(Value::as_str).map(|text| (field, text)));
+
+		let input_text = match input_text {
+			Some((field, text)) if text.trim().is_empty() => {
+				return Err(ValidationError::new(
+					format!("{}.{}", PARAMETERS_PATH, field),
+					"must not be empty",
+				));
+			}
+			Some((_, text)) => text.to_string(),
+			None => {
+				return Err(ValidationError::new(
+					format!("{}.input_text", PARAMETERS_PATH),
+					format!("is required (or its legacy alias '{}')", legacy_field),
+				));
+			}
+		};
+
+		Ok(Self {
+			input_text,
+			// Default to "General" if category is not provided
+			category: parameters
+				.get("category")
+				.and_then(Value::as_str)
+				.unwrap_or("General")
+				.to_string(),
+		})
+	}
+}
+
+/// `parameters` of `keyword_extraction` tasks
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeywordExtractionParameters {
+	pub feed_id: Uuid,
+	pub batch_id: Option<String>,
+	pub total_replacements: Option<i64>,
+}
+
+impl KeywordExtractionParameters {
+	pub fn from_task(task: &AIProcessingTask) -> Result<Self, ValidationError> {
+		let parameters = &task.request_data.parameters;
+		if parameters.get("feed_id").is_none_or(Value::is_null) {
+			return Err(ValidationError::new(
+				format!("{}.feed_id", PARAMETERS_PATH),
+				"is required",
+			));
+		}
+
+		serde_json::from_value(parameters.clone())
+			.map_err(|e| ValidationError::new(PARAMETERS_PATH, e.to_string()))
+	}
+}
@@ -0,0 +1,63 @@
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::models::rabbitmq::AIProcessingTask;
+use crate::tasks::AppContext;
+
+/// What a processor gets from the consumer besides the task itself
+pub struct TaskContext {
+	pub app: Arc<AppContext>,
+	/// Partial LLM output, forwarded to the client as `in_progress` updates
+	pub partial_output: UnboundedSender<String>,
+}
+
+/// Payload of the final `completed` result
+#[derive(Debug, Clone, Default)]
+pub struct TaskOutput {
+	pub result_data: Value,
+}
+
+impl TaskOutput {
+	pub fn new(result_data: Value) -> Self {
+		Self { result_data }
+	}
+
+	/// Output with a single field, e.g. `{"beautified_title": "..."}`
+	pub fn field(key: &str, value: impl Into<Value>) -> Self {
+		let mut result_data = json!({});
+		result_data[key] = value.into();
+		Self { result_data }
+	}
+
+	pub fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+		if !self.result_data.is_object() {
+			self.result_data = json!({});
+		}
+		self.result_data[key] = value.into();
+		self
+	}
+}
+
+/// One processing type of the consumer (`title`, `description`, ...)
+pub trait TaskProcessor: Send + Sync {
+	/// Value of `request_data.processing_type` handled by this processor
+	fn name(&self) -> &str;
+
+	/// JSON Schema of `request_data.parameters`
+	fn parameter_schema(&self) -> Value;
+
+	/// Batch processors publish their own progress and per-item results,
+	/// the consumer then sends neither the start update nor the final result
+	fn emits_own_results(&self) -> bool {
+		false
+	}
+
+	fn process<'a>(
+		&'a self,
+		ctx: &'a TaskContext,
+		task: &'a AIProcessingTask,
+	) -> BoxFuture<'a, Result<TaskOutput, Box<dyn Error + Send + Sync>>>;
+}
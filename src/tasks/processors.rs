@@ -0,0 +1,133 @@
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use std::error::Error;
+
+use crate::models::rabbitmq::AIProcessingTask;
+use crate::oai_processing::keyword_extraction_processing::process_keyword_extraction_with_llm;
+use crate::oai_processing::oai_description_processing::process_description_with_llm;
+use crate::oai_processing::oai_title_processing::process_title_with_llm;
+use crate::tasks::{TaskContext, TaskOutput, TaskProcessor};
+
+/// Avito ad title rewrite; the result carries the title constraint report
+pub struct TitleProcessor;
+
+impl TaskProcessor for TitleProcessor {
+	fn name(&self) -> &str {
+		"title"
+	}
+
+	fn parameter_schema(&self) -> Value {
+		json!({
+			"type": "object",
+			"properties": {
+				"input_text": {"type": "string", "minLength": 1},
+				"title": {"type": "string", "minLength": 1, "description": "Legacy alias of input_text"},
+				"category": {"type": "string", "default": "General"}
+			},
+			"anyOf": [{"required": ["input_text"]}, {"required": ["title"]}]
+		})
+	}
+
+	fn process<'a>(
+		&'a self,
+		ctx: &'a TaskContext,
+		task: &'a AIProcessingTask,
+	) -> BoxFuture<'a, Result<TaskOutput, Box<dyn Error + Send + Sync>>> {
+		Box::pin(async move {
+			let check = process_title_with_llm(
+				ctx.app.pool.clone(),
+				ctx.app.provider(self.name())?,
+				task,
+				Some(ctx.partial_output.clone()),
+			)
+			.await?;
+
+			Ok(TaskOutput::field("beautified_title", check.title.clone())
+				.with("constraints", json!(check)))
+		})
+	}
+}
+
+/// Avito ad description rewrite
+pub struct DescriptionProcessor;
+
+impl TaskProcessor for DescriptionProcessor {
+	fn name(&self) -> &str {
+		"description"
+	}
+
+	fn parameter_schema(&self) -> Value {
+		json!({
+			"type": "object",
+			"properties": {
+				"input_text": {"type": "string", "minLength": 1},
+				"description": {"type": "string", "minLength": 1, "description": "Legacy alias of input_text"},
+				"category": {"type": "string", "default": "General"}
+			},
+			"anyOf": [{"required": ["input_text"]}, {"required": ["description"]}]
+		})
+	}
+
+	fn process<'a>(
+		&'a self,
+		ctx: &'a TaskContext,
+		task: &'a AIProcessingTask,
+	) -> BoxFuture<'a, Result<TaskOutput, Box<dyn Error + Send + Sync>>> {
+		Box::pin(async move {
+			let description = process_description_with_llm(
+				ctx.app.pool.clone(),
+				ctx.app.provider(self.name())?,
+				task,
+				Some(ctx.partial_output.clone()),
+			)
+			.await?;
+
+			Ok(TaskOutput::field("beautified_description", description))
+		})
+	}
+}
+
+/// Keywords for every ad replacement of a feed; sends a result per replacement itself
+/// and stops between replacements when the task or its batch is cancelled
+pub struct KeywordExtractionProcessor;
+
+impl TaskProcessor for KeywordExtractionProcessor {
+	fn name(&self) -> &str {
+		"keyword_extraction"
+	}
+
+	fn parameter_schema(&self) -> Value {
+		json!({
+			"type": "object",
+			"properties": {
+				"feed_id": {"type": "string", "format": "uuid"},
+				"batch_id": {"type": "string"},
+				"total_replacements": {"type": "integer", "minimum": 0}
+			},
+			"required": ["feed_id"]
+		})
+	}
+
+	fn emits_own_results(&self) -> bool {
+		true
+	}
+
+	fn process<'a>(
+		&'a self,
+		ctx: &'a TaskContext,
+		task: &'a AIProcessingTask,
+	) -> BoxFuture<'a, Result<TaskOutput, Box<dyn Error + Send + Sync>>> {
+		Box::pin(async move {
+			let summary = process_keyword_extraction_with_llm(
+				ctx.app.pool.clone(),
+				ctx.app.provider(self.name())?,
+				task,
+				ctx.app.producer.clone(),
+				&ctx.app.cancellation,
+			)
+			.await?;
+
+			Ok(TaskOutput::field("result", summary))
+		})
+	}
+}
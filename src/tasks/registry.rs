@@ -0,0 +1,41 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::tasks::{
+	DescriptionProcessor, KeywordExtractionProcessor, TaskProcessor, TitleProcessor,
+};
+
+/// Processors by `processing_type`
+#[derive(Clone, Default)]
+pub struct TaskRegistry {
+	processors: BTreeMap<String, Arc<dyn TaskProcessor>>,
+}
+
+impl TaskRegistry {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Registry with every processing type the consumer supports
+	pub fn builtin() -> Self {
+		Self::new()
+			.register(TitleProcessor)
+			.register(DescriptionProcessor)
+			.register(KeywordExtractionProcessor)
+	}
+
+	/// Adds a processor; a processor with the same name is replaced
+	pub fn register(mut self, processor: impl TaskProcessor + 'static) -> Self {
+		self.processors
+			.insert(processor.name().to_string(), Arc::new(processor));
+		self
+	}
+
+	pub fn get(&self, processing_type: &str) -> Option<Arc<dyn TaskProcessor>> {
+		self.processors.get(processing_type).cloned()
+	}
+
+	pub fn names(&self) -> Vec<String> {
+		self.processors.keys().cloned().collect()
+	}
+}
@@ -0,0 +1,231 @@
+use serde_json::{json, Value};
+
+use crate::models::rabbitmq::MESSAGE_SCHEMA_VERSION;
+use crate::services::topology;
+use crate::tasks::TaskRegistry;
+
+const JSON_SCHEMA_DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// JSON Schemas of every message on the configured exchanges, keyed by message kind.
+/// Printed by `RUN_MODE=schema`; task parameters come from the registered processors.
+pub fn message_schemas(registry: &TaskRegistry) -> Value {
+	json!({
+		"schema_version": MESSAGE_SCHEMA_VERSION,
+		"task": task_schema(registry),
+		"result": result_schema(),
+		"progress": progress_schema(),
+		"cancel": cancel_schema(),
+	})
+}
+
+fn schema_version() -> Value {
+	json!({
+		"type": "integer",
+		"minimum": 1,
+		"maximum": MESSAGE_SCHEMA_VERSION,
+		"default": 1,
+		"description": "Missing in messages published before versioning; read as 1"
+	})
+}
+
+/// Task message; `parameters` depend on `processing_type`
+pub fn task_schema(registry: &TaskRegistry) -> Value {
+	let parameters = registry
+		.names()
+		.into_iter()
+		.filter_map(|name| registry.get(&name).map(|processor| (name, processor)))
+		.map(|(name, processor)| {
+			json!({
+				"if": {"properties": {"processing_type": {"const": name}}},
+				"then": {"properties": {"parameters": processor.parameter_schema()}}
+			})
+		})
+		.collect::<Vec<Value>>();
+
+	json!({
+		"$schema": JSON_SCHEMA_DRAFT,
+		"title": "AIProcessingTask",
+		"description": format!("Routing key {}", topology::current().task_routing_key("<processing_type>")),
+		"type": "object",
+		"properties": {
+			"schema_version": schema_version(),
+			"task_id": {"type": "string", "format": "uuid"},
+			"request_data": {
+				"type": "object",
+				"properties": {
+					"request_id": {"type": "string", "format": "uuid"},
+					"user_id": {"type": "string", "format": "uuid"},
+					"processing_type": {"type": "string", "enum": registry.names()},
+					"parameters": {"type": "object"}
+				},
+				"required": ["request_id", "user_id", "processing_type", "parameters"],
+				"allOf": parameters
+			},
+			"created_at": {"type": "string"},
+			"run_at": {
+				"type": ["string", "null"],
+				"format": "date-time",
+				"description": "Run the task at this time instead of right away"
+			},
+			"cron": {
+				"type": ["string", "null"],
+				"description": "Repeat the task on this 5-field cron expression (UTC), from run_at if given"
+			}
+		},
+		"required": ["task_id", "request_data", "created_at"]
+	})
+}
+
+/// Result message, one routing key per user
+pub fn result_schema() -> Value {
+	json!({
+		"$schema": JSON_SCHEMA_DRAFT,
+		"title": "AIProcessingResult",
+		"description": format!("Routing key {}", topology::current().result_routing_key("<user_id>")),
+		"type": "object",
+		"properties": {
+			"schema_version": schema_version(),
+			"task_id": {"type": "string", "format": "uuid"},
+			"user_id": {"type": "string", "format": "uuid"},
+			"request_id": {"type": ["string", "null"], "format": "uuid"},
+			"status": {
+				"type": "string",
+				"description": "completed, failed, cancelled; batch tasks also send completed per item and all_completed at the end"
+			},
+			"result_data": {
+				"type": ["object", "null"],
+				"properties": {
+					"error": {
+						"type": "object",
+						"properties": {
+							"code": {"type": "string", "enum": ["invalid_parameters", "unsupported_processing_type"]},
+							"field": {"type": "string", "description": "Path of the bad field, for invalid_parameters"},
+							"message": {"type": "string"}
+						},
+						"required": ["code"]
+					}
+				}
+			},
+			"error_message": {"type": ["string", "null"]},
+			"completed_at": {"type": "string"}
+		},
+		"required": ["task_id", "user_id", "status", "completed_at"]
+	})
+}
+
+/// Progress update, one routing key per user
+pub fn progress_schema() -> Value {
+	json!({
+		"$schema": JSON_SCHEMA_DRAFT,
+		"title": "AIProcessingProgress",
+		"description": format!("Routing key {}", topology::current().progress_routing_key("<user_id>")),
+		"type": "object",
+		"properties": {
+			"schema_version": schema_version(),
+			"task_id": {"type": "string", "format": "uuid"},
+			"user_id": {"type": "string", "format": "uuid"},
+			"request_id": {"type": ["string", "null"], "format": "uuid"},
+			"progress": {"type": "number", "minimum": 0, "maximum": 100},
+			"status": {"type": "string", "description": "scheduled, in_progress, completed, error, cancelled"},
+			"message": {"type": "string"},
+			"timestamp": {"type": "string"}
+		},
+		"required": ["task_id", "user_id", "progress", "status", "message", "timestamp"]
+	})
+}
+
+pub fn cancel_schema() -> Value {
+	json!({
+		"$schema": JSON_SCHEMA_DRAFT,
+		"title": "AITaskCancel",
+		"description": format!("Routing key {}", topology::current().cancel_routing_key),
+		"type": "object",
+		"properties": {
+			"task_id": {"type": ["string", "null"], "format": "uuid"},
+			"batch_id": {"type": ["string", "null"]},
+			"reason": {"type": ["string", "null"]}
+		},
+		"anyOf": [{"required": ["task_id"]}, {"required": ["batch_id"]}]
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tasks::validate;
+	use uuid::Uuid;
+
+	fn message(processing_type: &str) -> Value {
+		json!({
+			"task_id": Uuid::new_v4(),
+			"created_at": "2024-05-01T00:00:00Z",
+			"request_data": {
+				"request_id": Uuid::new_v4(),
+				"user_id": Uuid::new_v4(),
+				"processing_type": processing_type,
+				"parameters": {"input_text": "ремонт окон"}
+			}
+		})
+	}
+
+	#[test]
+	fn task_schema_lists_registered_types() {
+		let registry = TaskRegistry::builtin();
+		let schema = task_schema(&registry);
+
+		assert_eq!(
+			schema["properties"]["request_data"]["properties"]["processing_type"]["enum"],
+			json!(["description", "keyword_extraction", "title"])
+		);
+		assert_eq!(
+			schema["properties"]["request_data"]["allOf"]
+				.as_array()
+				.unwrap()
+				.len(),
+			3
+		);
+		assert_eq!(validate(&schema, &message("title"), "task"), Ok(()));
+	}
+
+	#[test]
+	fn task_schema_rejects_unknown_types() {
+		let registry = TaskRegistry::builtin();
+
+		assert!(registry.get("translation").is_none());
+		let error = validate(&task_schema(&registry), &message("translation"), "task").unwrap_err();
+		assert_eq!(error.field, "task.request_data.processing_type");
+		assert!(error.message.starts_with("must be one of"), "{}", error);
+	}
+
+	#[test]
+	fn task_schema_requires_ids() {
+		let mut task = message("title");
+		task["request_data"]
+			.as_object_mut()
+			.unwrap()
+			.remove("user_id");
+
+		assert_eq!(
+			validate(&task_schema(&TaskRegistry::builtin()), &task, "task")
+				.unwrap_err()
+				.field,
+			"task.request_data.user_id"
+		);
+	}
+
+	#[test]
+	fn cancel_needs_a_task_or_batch() {
+		let schema = cancel_schema();
+
+		assert_eq!(
+			validate(&schema, &json!({"batch_id": "b1"}), "cancel"),
+			Ok(())
+		);
+		assert_eq!(
+			validate(&schema, &json!({"reason": "feed removed"}), "cancel")
+				.unwrap_err()
+				.field,
+			"cancel.task_id"
+		);
+	}
+}
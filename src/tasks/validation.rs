@@ -0,0 +1,325 @@
+use chrono::Utc;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::models::rabbitmq::{AIProcessingTask, MESSAGE_SCHEMA_VERSION};
+use crate::tasks::TaskProcessor;
+use crate::utils::CronSchedule;
+
+/// A task that does not match its schema; sent back as a `failed` result
+/// with `result_data.error.code = "invalid_parameters"`
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Invalid {field}: {message}")]
+pub struct ValidationError {
+	/// Path of the bad field, e.g. `request_data.parameters.input_text`
+	pub field: String,
+	pub message: String,
+}
+
+impl ValidationError {
+	pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+		Self {
+			field: field.into(),
+			message: message.into(),
+		}
+	}
+
+	pub fn to_result_data(&self) -> Value {
+		json!({
+			"error": {
+				"code": "invalid_parameters",
+				"field": self.field,
+				"message": self.message,
+			}
+		})
+	}
+}
+
+/// Checks the message version, the cron expression and the parameters against the processor's schema
+pub fn validate_task(
+	processor: &dyn TaskProcessor,
+	task: &AIProcessingTask,
+) -> Result<(), ValidationError> {
+	if task.schema_version == 0 || task.schema_version > MESSAGE_SCHEMA_VERSION {
+		return Err(ValidationError::new(
+			"schema_version",
+			format!(
+				"unsupported version {}, this worker understands up to {}",
+				task.schema_version, MESSAGE_SCHEMA_VERSION
+			),
+		));
+	}
+
+	if let Some(ref cron) = task.cron {
+		match CronSchedule::parse(cron) {
+			Ok(schedule) if schedule.next_after(Utc::now()).is_none() => {
+				return Err(ValidationError::new("cron", "never runs"));
+			}
+			Ok(_) => {}
+			Err(e) => return Err(ValidationError::new("cron", e)),
+		}
+	}
+
+	validate(
+		&processor.parameter_schema(),
+		&task.request_data.parameters,
+		"request_data.parameters",
+	)
+}
+
+/// Validates `value` against the part of JSON Schema the processors use:
+/// `type`, `properties`, `required`, `anyOf`, `enum`, `minLength`, `minimum` and `format: uuid`
+pub fn validate(schema: &Value, value: &Value, path: &str) -> Result<(), ValidationError> {
+	if let Some(expected) = schema.get("type").and_then(|v| v.as_str()) {
+		if !has_type(value, expected) {
+			return Err(ValidationError::new(
+				path,
+				format!("expected {}, got {}", expected, type_name(value)),
+			));
+		}
+	}
+
+	if let Some(allowed) = schema.get("enum").and_then(|v| v.as_array()) {
+		if !allowed.contains(value) {
+			return Err(ValidationError::new(
+				path,
+				format!("must be one of {}", Value::Array(allowed.clone())),
+			));
+		}
+	}
+
+	if let Some(text) = value.as_str() {
+		let min_length = schema.get("minLength").and_then(|v| v.as_u64());
+		if let Some(min_length) = min_length {
+			if (text.chars().count() as u64) < min_length {
+				return Err(ValidationError::new(
+					path,
+					format!("must be at least {} characters long", min_length),
+				));
+			}
+		}
+		if schema.get("format").and_then(|v| v.as_str()) == Some("uuid")
+			&& Uuid::parse_str(text).is_err()
+		{
+			return Err(ValidationError::new(path, "must be a UUID"));
+		}
+	}
+
+	if let (Some(number), Some(minimum)) = (
+		value.as_f64(),
+		schema.get("minimum").and_then(|v| v.as_f64()),
+	) {
+		if number < minimum {
+			return Err(ValidationError::new(
+				path,
+				format!("must be at least {}", minimum),
+			));
+		}
+	}
+
+	if let Some(object) = value.as_object() {
+		let required = schema.get("required").and_then(|v| v.as_array());
+		for field in required.into_iter().flatten().filter_map(|v| v.as_str()) {
+			if object.get(field).is_none_or(Value::is_null) {
+				return Err(ValidationError::new(
+					format!("{}.{}", path, field),
+					"is required",
+				));
+			}
+		}
+
+		if let Some(properties) = schema.get("properties").and_then(|v| v.as_object()) {
+			for (field, property_schema) in properties {
+				match object.get(field) {
+					Some(Value::Null) | None => {}
+					Some(property) => {
+						validate(property_schema, property, &format!("{}.{}", path, field))?
+					}
+				}
+			}
+		}
+	}
+
+	// Alternatives are only `required` lists, e.g. `input_text` or its legacy alias
+	if let Some(alternatives) = schema.get("anyOf").and_then(|v| v.as_array()) {
+		let mut first_error = None;
+		for alternative in alternatives {
+			match validate(alternative, value, path) {
+				Ok(()) => return Ok(()),
+				Err(e) => {
+					first_error.get_or_insert(e);
+				}
+			}
+		}
+		if let Some(error) = first_error {
+			return Err(error);
+		}
+	}
+
+	Ok(())
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+	match expected {
+		"object" => value.is_object(),
+		"array" => value.is_array(),
+		"string" => value.is_string(),
+		"integer" => value.is_i64() || value.is_u64(),
+		"number" => value.is_number(),
+		"boolean" => value.is_boolean(),
+		"null" => value.is_null(),
+		_ => true,
+	}
+}
+
+fn type_name(value: &Value) -> &'static str {
+	match value {
+		Value::Null => "null",
+		Value::Bool(_) => "boolean",
+		Value::Number(_) => "number",
+		Value::String(_) => "string",
+		Value::Array(_) => "array",
+		Value::Object(_) => "object",
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tasks::{KeywordExtractionProcessor, TitleProcessor};
+
+	fn task(processing_type: &str, parameters: Value) -> AIProcessingTask {
+		serde_json::from_value(json!({
+			"schema_version": 1,
+			"task_id": Uuid::new_v4(),
+			"created_at": "2024-05-01T00:00:00Z",
+			"request_data": {
+				"request_id": Uuid::new_v4(),
+				"user_id": Uuid::new_v4(),
+				"processing_type": processing_type,
+				"parameters": parameters
+			}
+		}))
+		.unwrap()
+	}
+
+	fn error(processor: &dyn TaskProcessor, parameters: Value) -> ValidationError {
+		validate_task(processor, &task(processor.name(), parameters)).unwrap_err()
+	}
+
+	#[test]
+	fn accepts_valid_parameters() {
+		let title = task(
+			"title",
+			json!({"input_text": "ремонт окон", "category": null}),
+		);
+		assert_eq!(validate_task(&TitleProcessor, &title), Ok(()));
+
+		// The legacy alias satisfies the other `anyOf` alternative
+		let legacy = task("title", json!({"title": "ремонт окон"}));
+		assert_eq!(validate_task(&TitleProcessor, &legacy), Ok(()));
+
+		let keywords = task(
+			"keyword_extraction",
+			json!({"feed_id": Uuid::new_v4(), "batch_id": "b1", "total_replacements": 0}),
+		);
+		assert_eq!(
+			validate_task(&KeywordExtractionProcessor, &keywords),
+			Ok(())
+		);
+	}
+
+	#[test]
+	fn reports_missing_required_fields() {
+		assert_eq!(
+			error(&KeywordExtractionProcessor, json!({"batch_id": "b1"})),
+			ValidationError::new("request_data.parameters.feed_id", "is required")
+		);
+		assert_eq!(
+			error(&KeywordExtractionProcessor, json!({"feed_id": null})),
+			ValidationError::new("request_data.parameters.feed_id", "is required")
+		);
+		// Neither `input_text` nor `title`: the first alternative is reported
+		assert_eq!(
+			error(&TitleProcessor, json!({"category": "Окна"})),
+			ValidationError::new("request_data.parameters.input_text", "is required")
+		);
+	}
+
+	#[test]
+	fn reports_wrong_types_and_values() {
+		assert_eq!(
+			error(&TitleProcessor, json!({"input_text": 5})),
+			ValidationError::new(
+				"request_data.parameters.input_text",
+				"expected string, got number"
+			)
+		);
+		assert_eq!(
+			error(&TitleProcessor, json!({"input_text": ""})),
+			ValidationError::new(
+				"request_data.parameters.input_text",
+				"must be at least 1 characters long"
+			)
+		);
+		assert_eq!(
+			error(&KeywordExtractionProcessor, json!({"feed_id": "feed-1"})),
+			ValidationError::new("request_data.parameters.feed_id", "must be a UUID")
+		);
+		assert_eq!(
+			error(
+				&KeywordExtractionProcessor,
+				json!({"feed_id": Uuid::new_v4(), "total_replacements": "10"})
+			),
+			ValidationError::new(
+				"request_data.parameters.total_replacements",
+				"expected integer, got string"
+			)
+		);
+		assert_eq!(
+			error(
+				&KeywordExtractionProcessor,
+				json!({"feed_id": Uuid::new_v4(), "total_replacements": -1})
+			),
+			ValidationError::new(
+				"request_data.parameters.total_replacements",
+				"must be at least 0"
+			)
+		);
+		assert_eq!(
+			error(&TitleProcessor, json!(["ремонт окон"])),
+			ValidationError::new("request_data.parameters", "expected object, got array")
+		);
+	}
+
+	#[test]
+	fn rejects_unsupported_versions_and_bad_cron() {
+		let mut future = task("title", json!({"input_text": "ремонт окон"}));
+		future.schema_version = MESSAGE_SCHEMA_VERSION + 1;
+		assert_eq!(
+			validate_task(&TitleProcessor, &future).unwrap_err().field,
+			"schema_version"
+		);
+
+		let mut repeated = task("title", json!({"input_text": "ремонт окон"}));
+		repeated.cron = Some("61 * * * *".to_string());
+		assert_eq!(
+			validate_task(&TitleProcessor, &repeated).unwrap_err().field,
+			"cron"
+		);
+	}
+
+	#[test]
+	fn error_becomes_result_data() {
+		let error = ValidationError::new("request_data.parameters.feed_id", "is required");
+
+		assert_eq!(
+			error.to_result_data(),
+			json!({"error": {
+				"code": "invalid_parameters",
+				"field": "request_data.parameters.feed_id",
+				"message": "is required"
+			}})
+		);
+	}
+}
@@ -0,0 +1,49 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone)]
+pub struct ItemFailure {
+	pub item: String,
+	pub error: String,
+}
+
+/// Outcome of a batch run: a failed item does not stop the loop,
+/// it is added to the `failed` list
+#[derive(Debug, Clone, Default)]
+pub struct BatchReport {
+	pub name: String,
+	pub processed: usize,
+	pub failed: Vec<ItemFailure>,
+}
+
+impl BatchReport {
+	pub fn new(name: impl Into<String>) -> Self {
+		Self {
+			name: name.into(),
+			..Self::default()
+		}
+	}
+
+	pub fn success(&mut self) {
+		self.processed += 1;
+	}
+
+	pub fn failure(&mut self, item: impl Display, error: impl Display) {
+		eprintln!("❌ {}: {} failed: {}", self.name, item, error);
+		self.failed.push(ItemFailure {
+			item: item.to_string(),
+			error: error.to_string(),
+		});
+	}
+
+	pub fn print_summary(&self) {
+		println!(
+			"{}: {} processed, {} failed",
+			self.name,
+			self.processed,
+			self.failed.len()
+		);
+		for failure in &self.failed {
+			println!("  - {}: {}", failure.item, failure.error);
+		}
+	}
+}
@@ -0,0 +1,290 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use std::fmt;
+
+/// Years without a match after which an expression is taken to never fire (`0 0 30 2 *`)
+const MAX_YEARS_AHEAD: i32 = 5;
+
+/// Five-field cron expression: minute, hour, day of month, month, day of week (0 and 7 are Sunday).
+///
+/// Fields accept `*`, lists (`1,15`), ranges (`1-5`) and steps (`*/10`, `8-18/2`),
+/// as well as the `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` shortcuts. Times are UTC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+	expression: String,
+	minutes: u64,
+	hours: u64,
+	days: u64,
+	months: u64,
+	weekdays: u64,
+	any_day: bool,
+	any_weekday: bool,
+}
+
+impl CronSchedule {
+	pub fn parse(expression: &str) -> Result<Self, String> {
+		let expanded = match expression.trim() {
+			"@hourly" => "0 * * * *",
+			"@daily" | "@midnight" => "0 0 * * *",
+			"@weekly" => "0 0 * * 0",
+			"@monthly" => "0 0 1 * *",
+			"@yearly" | "@annually" => "0 0 1 1 *",
+			other => other,
+		};
+
+		let fields: Vec<&str> = expanded.split_whitespace().collect();
+		if fields.len() != 5 {
+			return Err(format!(
+				"expected 5 fields (minute hour day month weekday), got {}",
+				fields.len()
+			));
+		}
+
+		let mut weekdays = parse_field(fields[4], 0, 7, "weekday")?;
+		// 7 is another name for Sunday
+		if weekdays & (1 << 7) != 0 {
+			weekdays = (weekdays | 1) & !(1 << 7);
+		}
+
+		let days = parse_field(fields[2], 1, 31, "day")?;
+
+		Ok(Self {
+			expression: expression.trim().to_string(),
+			minutes: parse_field(fields[0], 0, 59, "minute")?,
+			hours: parse_field(fields[1], 0, 23, "hour")?,
+			days,
+			months: parse_field(fields[3], 1, 12, "month")?,
+			weekdays,
+			// `1-31` or `0-7` restrict nothing, just like `*`
+			any_day: days == full_mask(1, 31),
+			any_weekday: weekdays == full_mask(0, 6),
+		})
+	}
+
+	/// First time strictly after `after`; `None` if the expression never fires
+	pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+		let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+		let last_year = after.year() + MAX_YEARS_AHEAD;
+
+		while time.year() <= last_year {
+			if !matches(self.months, time.month()) {
+				let (year, month) = if time.month() == 12 {
+					(time.year() + 1, 1)
+				} else {
+					(time.year(), time.month() + 1)
+				};
+				time = start_of_day(NaiveDate::from_ymd_opt(year, month, 1)?);
+				continue;
+			}
+			if !self.matches_day(time) {
+				time = start_of_day(time.date_naive().succ_opt()?);
+				continue;
+			}
+			if !matches(self.hours, time.hour()) {
+				time = time.with_minute(0)? + Duration::hours(1);
+				continue;
+			}
+			if !matches(self.minutes, time.minute()) {
+				time += Duration::minutes(1);
+				continue;
+			}
+			return Some(time);
+		}
+
+		None
+	}
+
+	/// As in regular cron: when both day of month and day of week are set, either one matches
+	fn matches_day(&self, time: DateTime<Utc>) -> bool {
+		let day = matches(self.days, time.day());
+		let weekday = matches(self.weekdays, time.weekday().num_days_from_sunday());
+
+		match (self.any_day, self.any_weekday) {
+			(true, true) => true,
+			(true, false) => weekday,
+			(false, true) => day,
+			(false, false) => day || weekday,
+		}
+	}
+}
+
+impl fmt::Display for CronSchedule {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.expression)
+	}
+}
+
+fn matches(mask: u64, value: u32) -> bool {
+	mask & (1 << value) != 0
+}
+
+fn full_mask(min: u32, max: u32) -> u64 {
+	(min..=max).fold(0, |mask, value| mask | 1 << value)
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+	Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).expect("midnight is a valid time"))
+}
+
+/// Bit mask of the values a field allows
+fn parse_field(field: &str, min: u32, max: u32, name: &str) -> Result<u64, String> {
+	let number = |value: &str| {
+		value
+			.parse::<u32>()
+			.ok()
+			.filter(|value| (min..=max).contains(value))
+			.ok_or_else(|| format!("invalid {} '{}', expected {}-{}", name, value, min, max))
+	};
+
+	let mut mask = 0u64;
+	for part in field.split(',') {
+		let (range, step) = match part.split_once('/') {
+			Some((range, step)) => {
+				let step = step
+					.parse::<u32>()
+					.ok()
+					.filter(|step| *step > 0)
+					.ok_or_else(|| format!("invalid {} step '{}'", name, step))?;
+				(range, step)
+			}
+			None => (part, 1),
+		};
+
+		let (start, end) = match range {
+			"*" => (min, max),
+			_ => match range.split_once('-') {
+				Some((start, end)) => (number(start)?, number(end)?),
+				// `5/15` means from 5 to the end of the field
+				None if step > 1 => (number(range)?, max),
+				None => {
+					let value = number(range)?;
+					(value, value)
+				}
+			},
+		};
+		if start > end {
+			return Err(format!("invalid {} range '{}'", name, range));
+		}
+
+		for value in (start..=end).step_by(step as usize) {
+			mask |= 1 << value;
+		}
+	}
+
+	Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn at(text: &str) -> DateTime<Utc> {
+		DateTime::parse_from_rfc3339(text)
+			.unwrap()
+			.with_timezone(&Utc)
+	}
+
+	fn next(expression: &str, after: &str) -> Option<DateTime<Utc>> {
+		CronSchedule::parse(expression)
+			.unwrap()
+			.next_after(at(after))
+	}
+
+	#[test]
+	fn parses_fields() {
+		let schedule = CronSchedule::parse("*/15 8-18/5 1,15 * 1-5").unwrap();
+
+		assert_eq!(schedule.minutes, 1 << 0 | 1 << 15 | 1 << 30 | 1 << 45);
+		assert_eq!(schedule.hours, 1 << 8 | 1 << 13 | 1 << 18);
+		assert_eq!(schedule.days, 1 << 1 | 1 << 15);
+		assert_eq!(schedule.months, full_mask(1, 12));
+		assert_eq!(schedule.weekdays, full_mask(1, 5));
+		assert!(!schedule.any_day);
+		assert!(!schedule.any_weekday);
+		assert_eq!(schedule.to_string(), "*/15 8-18/5 1,15 * 1-5");
+	}
+
+	#[test]
+	fn parses_shortcuts_and_sunday_as_seven() {
+		assert_eq!(
+			CronSchedule::parse("@daily").unwrap().minutes,
+			CronSchedule::parse("0 0 * * *").unwrap().minutes
+		);
+		assert_eq!(CronSchedule::parse("0 0 * * 7").unwrap().weekdays, 1);
+		assert_eq!(
+			CronSchedule::parse("0 0 * * 5/2").unwrap().weekdays,
+			1 << 5 | 1,
+			"5 and 7, i.e. Friday and Sunday"
+		);
+	}
+
+	#[test]
+	fn full_ranges_restrict_nothing() {
+		for expression in ["0 0 * * *", "0 0 1-31 * *", "0 0 */1 * 0-7", "0 0 * * 0-6"] {
+			let schedule = CronSchedule::parse(expression).unwrap();
+			assert!(schedule.any_day, "{}", expression);
+			assert!(schedule.any_weekday, "{}", expression);
+		}
+
+		// With a literal `*` test only Mondays would match, `1-31` must behave the same
+		assert_eq!(
+			next("0 9 1-31 * 1", "2024-05-01T00:00:00Z"),
+			Some(at("2024-05-06T09:00:00Z"))
+		);
+		assert_eq!(
+			next("0 9 1 * 0-7", "2024-05-02T00:00:00Z"),
+			Some(at("2024-06-01T09:00:00Z"))
+		);
+	}
+
+	#[test]
+	fn rejects_invalid_expressions() {
+		for expression in [
+			"* * * *",
+			"60 * * * *",
+			"* 24 * * *",
+			"* * 0 * *",
+			"* * * 13 *",
+			"* * * * 8",
+			"*/0 * * * *",
+			"5-1 * * * *",
+			"x * * * *",
+		] {
+			assert!(CronSchedule::parse(expression).is_err(), "{}", expression);
+		}
+	}
+
+	#[test]
+	fn finds_next_fire_time() {
+		// Strictly after, seconds are dropped
+		assert_eq!(
+			next("*/15 * * * *", "2024-05-01T10:15:30Z"),
+			Some(at("2024-05-01T10:30:00Z"))
+		);
+		assert_eq!(
+			next("30 8 * * *", "2024-12-31T09:00:00Z"),
+			Some(at("2025-01-01T08:30:00Z"))
+		);
+		assert_eq!(
+			next("0 0 29 2 *", "2024-03-01T00:00:00Z"),
+			Some(at("2028-02-29T00:00:00Z"))
+		);
+		assert_eq!(
+			next("@monthly", "2024-01-31T12:00:00Z"),
+			Some(at("2024-02-01T00:00:00Z"))
+		);
+		assert_eq!(next("0 0 30 2 *", "2024-01-01T00:00:00Z"), None);
+	}
+
+	#[test]
+	fn day_or_weekday_matches() {
+		// The 13th or any Friday, whichever comes first
+		assert_eq!(
+			next("0 0 13 * 5", "2024-09-01T00:00:00Z"),
+			Some(at("2024-09-06T00:00:00Z"))
+		);
+		assert_eq!(
+			next("0 0 13 * 5", "2024-09-07T00:00:00Z"),
+			Some(at("2024-09-13T00:00:00Z"))
+		);
+	}
+}
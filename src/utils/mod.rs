@@ -1,3 +1,7 @@
+pub mod batch_report;
+pub mod cron;
 pub mod transliterate;
 
+pub use self::batch_report::*;
+pub use self::cron::*;
 pub use self::transliterate::*;